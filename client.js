@@ -6,7 +6,7 @@ const { Retcher, Browser } = require('./index');
     try {
         console.log('Fetching...');
         const x = await client.retch('https://httpbin.org/absolute-redirect/4');
-        console.log(x.body.toString());
+        console.log(await x.text());
     } catch (e) {
         console.log(e.message);
     }
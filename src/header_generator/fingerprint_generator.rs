@@ -0,0 +1,245 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use super::super::retcher::profile::BrowserProfile;
+use super::super::retcher::retcher::{Browser, FetchError, FetchErrorKind, FetchPhase};
+
+/// How many of the latest versions of a browser are picked from when `FingerprintOptions` don't limit them.
+const RECENT_VERSIONS: u32 = 4;
+
+/// How many versions (up to its own one) a built-in profile impersonates. The older ones had other TLS and HTTP/2 fingerprints.
+const SUPPORTED_VERSIONS: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperatingSystem {
+    Windows,
+    MacOs,
+    Linux,
+    Android,
+    Ios,
+}
+
+impl OperatingSystem {
+    /// The value of `sec-ch-ua-platform` on this system.
+    fn platform(&self) -> &'static str {
+        match self {
+            OperatingSystem::Windows => "Windows",
+            OperatingSystem::MacOs => "macOS",
+            OperatingSystem::Linux => "Linux",
+            OperatingSystem::Android => "Android",
+            OperatingSystem::Ios => "iOS",
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        match self {
+            OperatingSystem::Android | OperatingSystem::Ios => Device::Mobile,
+            _ => Device::Desktop,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Desktop,
+    Mobile,
+}
+
+/// The browsers on the systems they run on, weighted by (roughly) how common they are.
+///
+/// Only these combinations are generated, so there's no Safari on Linux or Edge on iOS.
+const COMBINATIONS: [(Browser, OperatingSystem, u32); 11] = [
+    (Browser::Chrome, OperatingSystem::Windows, 30),
+    (Browser::Chrome, OperatingSystem::MacOs, 10),
+    (Browser::Chrome, OperatingSystem::Linux, 3),
+    (Browser::Edge, OperatingSystem::Windows, 10),
+    (Browser::Edge, OperatingSystem::MacOs, 1),
+    (Browser::Firefox, OperatingSystem::Windows, 4),
+    (Browser::Firefox, OperatingSystem::MacOs, 1),
+    (Browser::Firefox, OperatingSystem::Linux, 2),
+    (Browser::Safari, OperatingSystem::MacOs, 7),
+    (Browser::ChromeAndroid, OperatingSystem::Android, 20),
+    (Browser::SafariIos, OperatingSystem::Ios, 12),
+];
+
+/// The common locales, weighted by (roughly) how common they are.
+const LOCALES: [(&str, u32); 11] = [
+    ("en-US", 40),
+    ("en-GB", 8),
+    ("de-DE", 8),
+    ("fr-FR", 7),
+    ("es-ES", 6),
+    ("pt-BR", 6),
+    ("it-IT", 4),
+    ("ja-JP", 4),
+    ("nl-NL", 3),
+    ("pl-PL", 3),
+    ("cs-CZ", 2),
+];
+
+/// FingerprintOptions constrain the fingerprints `generate_fingerprint` picks from. The empty lists don't constrain anything.
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintOptions {
+    pub browsers: Vec<Browser>,
+    pub operating_systems: Vec<OperatingSystem>,
+    pub devices: Vec<Device>,
+    /// The locales (e.g. `de-DE`) to pick from, with equal chances. The common ones by default.
+    pub locales: Vec<String>,
+    /// The lowest major version to pick.
+    ///
+    /// The versions are limited to the ones the built-in profiles impersonate, the last 12 up to their own one.
+    pub min_version: Option<u32>,
+    /// The highest major version to pick. The latest ones the built-in profiles know are the most likely.
+    pub max_version: Option<u32>,
+    /// The seed of the random choices, so the same options generate the same fingerprint. A random one by default.
+    pub seed: Option<u64>,
+}
+
+/// Fingerprint is a generated browser identity, with the profile impersonating it.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub browser: Browser,
+    pub version: u32,
+    pub operating_system: OperatingSystem,
+    pub device: Device,
+    pub locale: String,
+    pub accept_language: String,
+    pub profile: BrowserProfile,
+}
+
+fn invalid_fingerprint(message: String) -> FetchError {
+    FetchError::new(FetchErrorKind::InvalidProfile, FetchPhase::Setup, None, message)
+}
+
+/// Generates a random, but consistent, browser fingerprint matching the options.
+///
+/// The browser, its version, the operating system, the device and the locale are picked with (roughly) the chances they have in the real
+/// traffic, and the profile is the browser's built-in one with the `User-Agent`, `sec-ch-ua-platform` and `Accept-Language` to match.
+pub fn generate_fingerprint(options: &FingerprintOptions) -> Result<Fingerprint, FetchError> {
+    if let Some(locale) = options.locales.iter().find(|locale| !is_locale(locale)) {
+        return Err(invalid_fingerprint(format!("'{}' is not a valid locale, e.g. 'de-DE'", locale)));
+    }
+
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let combinations: Vec<(Browser, OperatingSystem, u32, Vec<u32>)> = COMBINATIONS
+        .iter()
+        .filter(|(browser, _, _)| options.browsers.is_empty() || options.browsers.contains(browser))
+        .filter(|(_, system, _)| options.operating_systems.is_empty() || options.operating_systems.contains(system))
+        .filter(|(_, system, _)| options.devices.is_empty() || options.devices.contains(&system.device()))
+        .map(|(browser, system, weight)| (browser.clone(), *system, *weight, versions(&BrowserProfile::builtin(browser), options)))
+        .filter(|(_, _, _, versions)| !versions.is_empty())
+        .collect();
+
+    let (browser, system, _, versions) = combinations
+        .choose_weighted(&mut rng, |(_, _, weight, _)| *weight)
+        .map_err(|_| invalid_fingerprint("No browser fingerprint matches the constraints".into()))?;
+
+    // The newer versions are the more common ones.
+    let version = *versions.choose_weighted(&mut rng, |version| version - versions[0] + 1).expect("there's a version to pick");
+
+    let locale = if options.locales.is_empty() {
+        LOCALES.choose_weighted(&mut rng, |(_, weight)| *weight).expect("there's a locale to pick").0.to_string()
+    } else {
+        options.locales[rng.gen_range(0..options.locales.len())].clone()
+    };
+    let accept_language = accept_language(browser, &locale);
+
+    let mut profile = BrowserProfile::builtin(browser).with_version(version)?;
+    profile.name = format!("{}-{}-{}", profile.name.split('-').next().unwrap_or_default(), version, system.platform().to_lowercase());
+    profile.user_agent = user_agent(&profile, *system);
+    for header in profile.headers.iter_mut() {
+        if header.name.eq_ignore_ascii_case("sec-ch-ua-platform") {
+            header.value = format!("\"{}\"", system.platform());
+        } else if header.name.eq_ignore_ascii_case("Accept-Language") {
+            header.value = accept_language.clone();
+        }
+    }
+    profile.validate_headers()?;
+
+    Ok(Fingerprint {
+        browser: browser.clone(),
+        version,
+        operating_system: *system,
+        device: system.device(),
+        locale,
+        accept_language,
+        profile,
+    })
+}
+
+/// The versions of the browser the options allow, oldest first. Empty if none of the profile's supported versions is allowed.
+fn versions(profile: &BrowserProfile, options: &FingerprintOptions) -> Vec<u32> {
+    // Without a `{version}` in its `User-Agent`, the profile only impersonates its own version (see `BrowserProfile::with_version`).
+    let oldest = if profile.user_agent.contains("{version}") {
+        profile.version.saturating_sub(SUPPORTED_VERSIONS - 1).max(1)
+    } else {
+        profile.version
+    };
+
+    let max = options.max_version.unwrap_or(profile.version).min(profile.version);
+    let min = options.min_version.unwrap_or(max.saturating_sub(RECENT_VERSIONS - 1)).max(oldest);
+    (min..=max).collect()
+}
+
+/// Replaces the platform in the profile's `User-Agent` (the first parenthesized part) with the operating system's one.
+fn user_agent(profile: &BrowserProfile, system: OperatingSystem) -> String {
+    let platform = match (&profile.browser, system) {
+        (Browser::Firefox, OperatingSystem::Windows) => "Windows NT 10.0; Win64; x64; rv:{version}.0",
+        (Browser::Firefox, OperatingSystem::MacOs) => "Macintosh; Intel Mac OS X 10.15; rv:{version}.0",
+        (Browser::Firefox, _) => "X11; Linux x86_64; rv:{version}.0",
+        (Browser::Chrome | Browser::Edge, OperatingSystem::Windows) => "Windows NT 10.0; Win64; x64",
+        (Browser::Chrome | Browser::Edge, OperatingSystem::MacOs) => "Macintosh; Intel Mac OS X 10_15_7",
+        (Browser::Chrome, OperatingSystem::Linux) => "X11; Linux x86_64",
+        // The browsers only running on one system keep the profile's platform.
+        _ => return profile.user_agent.clone(),
+    };
+
+    match (profile.user_agent.find('('), profile.user_agent.find(')')) {
+        (Some(start), Some(end)) if start < end => format!("{}{}{}", &profile.user_agent[..=start], platform, &profile.user_agent[end..]),
+        _ => profile.user_agent.clone(),
+    }
+}
+
+/// Returns whether the locale is a language with an optional region, e.g. `de` or `de-DE`.
+fn is_locale(locale: &str) -> bool {
+    let (language, region) = locale.split_once('-').map_or((locale, None), |(language, region)| (language, Some(region)));
+
+    (2..=3).contains(&language.len())
+        && language.chars().all(|character| character.is_ascii_lowercase())
+        && region.is_none_or(|region| region.len() == 2 && region.chars().all(|character| character.is_ascii_uppercase()))
+}
+
+/// Builds the `Accept-Language` the browser sends for a user with the locale (and English as the fallback).
+fn accept_language(browser: &Browser, locale: &str) -> String {
+    let language = locale.split('-').next().unwrap_or(locale);
+
+    // Safari only sends the system's language.
+    let mut languages = match browser {
+        Browser::Safari | Browser::SafariIos => vec![locale, language],
+        _ => vec![locale, language, "en-US", "en"],
+    };
+    let mut seen = Vec::new();
+    languages.retain(|language| {
+        let first = !seen.contains(language);
+        seen.push(*language);
+        first
+    });
+
+    let count = languages.len();
+    languages
+        .iter()
+        .enumerate()
+        .map(|(index, language)| match (browser, index) {
+            (_, 0) => language.to_string(),
+            // Firefox spreads the weights evenly, rounded to one decimal.
+            (Browser::Firefox, _) => format!("{};q=0.{}", language, ((count - index) * 20 + count) / (count * 2)),
+            // The others lower them by 0.1 for every language.
+            _ => format!("{};q=0.{}", language, 10 - index),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -1,95 +1,156 @@
-use std::{collections::HashMap, str::FromStr};
-use reqwest::header::{HeaderMap, HeaderName};
-use super::super::retcher::retcher::Browser;
-
-#[derive(Default)]
-struct Header {
-    key: String,
-    value: String,
-    is_https: Option<bool>,
-    is_http1: Option<bool>
-}
+use std::str::FromStr;
+use std::sync::Arc;
+use indexmap::IndexMap;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use super::super::retcher::profile::{BrowserProfile, ProfileHeader};
+use super::super::retcher::retcher::{Destination, FetchError, FetchErrorKind, FetchPhase, NavigationType, RequestMode};
 
 pub struct HeaderGeneratorOptions {
+    /// The `Host` value, the URL's host with its port when it isn't the default one.
     pub host: String,
-    pub browser: Browser,
+    /// The profile of the impersonated browser, with the headers to send.
+    pub profile: Arc<BrowserProfile>,
     pub https: bool,
-    pub custom_headers: Option<HashMap<String, String>>,
+    /// What the requested resource is for, deciding the `Accept`, the `Sec-Fetch-*` and the navigation-only headers.
+    pub destination: Destination,
+    pub mode: RequestMode,
+    /// How the user got to the page, for the reload and prefetch headers.
+    pub navigation_type: NavigationType,
+    pub custom_headers: Option<IndexMap<String, String>>,
+    /// The `Content-Type` of the request body, if the body has one.
+    pub content_type: Option<String>,
+    /// The length of the request body. `None` if the request doesn't send a `Content-Length`.
+    pub content_length: Option<usize>,
+    /// The serialized origin for the `Origin` header. `None` if the browser wouldn't send one.
+    pub origin: Option<String>,
+    /// The `Sec-Fetch-Site` value. `None` for requests the user started directly (e.g. by typing the URL).
+    pub sec_fetch_site: Option<String>,
+    /// The value for the `Referer` header. `None` if the browser wouldn't send one.
+    pub referer: Option<String>,
+    /// The value for the `Cookie` header. `None` if there are no cookies to send.
+    pub cookie: Option<String>,
 }
 
-pub fn generate_headers(options: HeaderGeneratorOptions) -> HeaderMap {
-    let HeaderGeneratorOptions { host, browser, https, custom_headers } = options;
-
-    let firefox_headers: Vec<Header> = vec![
-        Header { key: "Host".into(), value: host.as_str().into(), is_http1: Some(true), ..Header::default() },
-        Header { key: "User-Agent".into(), value: "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0".into(), ..Header::default() }, 
-        Header { key: "Accept".into(), value: "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/png,image/svg+xml,*/*;q=0.8".into(), ..Header::default() }, 
-        Header { key: "Accept-Language".into(), value: "en,cs;q=0.7,en-US;q=0.3".into(), ..Header::default() }, 
-        Header { key: "Accept-Encoding".into(), value: "gzip, deflate".into(), ..Header::default() }, 
-        Header { key: "sec-fetch-dest".into(), value: "document".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "sec-fetch-mode".into(), value: "navigate".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "sec-fetch-site".into(), value: "none".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "sec-fetch-user".into(), value: "?1".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "Connection".into(), value: "keep-alive".into(), ..Header::default() }, 
-        Header { key: "Upgrade-Insecure-Requests".into(), value: "1".into(), ..Header::default() }, 
-        Header { key: "Priority".into(), value: "u=0, i".into(), ..Header::default() },
-    ];
+fn invalid_header(key: &str, reason: String) -> FetchError {
+    FetchError::new(
+        FetchErrorKind::InvalidRequest,
+        FetchPhase::Setup,
+        None,
+        format!("Invalid header '{}': {}", key, reason),
+    )
+}
+
+/// GeneratedHeaders are the headers of a request, with the order and the name casing the browser sends them with.
+///
+/// `HeaderMap` keeps neither, so they're kept in `order` (and restored by the relay, see `tunnel`).
+/// The relay also drops the headers of the other HTTP version than the negotiated one, e.g. `Host` on HTTP/2 (where it's `:authority`).
+#[derive(Default)]
+pub struct GeneratedHeaders {
+    pub headers: HeaderMap,
+    /// The header names, in the browser's order and casing, with the `ProfileHeader::http1` condition of the ones only sent over one HTTP version.
+    pub order: Vec<(String, Option<bool>)>,
+}
+
+impl GeneratedHeaders {
+    fn insert(&mut self, key: &str, value: &str, http1: Option<bool>) -> Result<(), FetchError> {
+        let name = HeaderName::from_str(key)
+            .map_err(|error| invalid_header(key, error.to_string()).with_source(error))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|error| invalid_header(key, error.to_string()).with_source(error))?;
+
+        self.headers.insert(name, value);
+        self.order.push((key.to_string(), http1));
+        Ok(())
+    }
+}
+
+/// Generates the headers the browser would send with the request.
+///
+/// The headers come from the browser's profile (see `BrowserProfile`), with the placeholders in their values filled in from the options.
+/// The custom headers replace the browser's headers with the same name (in their place), the other ones go where the browser puts the headers
+/// set by the page - before the profile's `customHeadersBefore` header (`User-Agent` in Chrome, `Content-Type` in Firefox).
+pub fn generate_headers(options: HeaderGeneratorOptions) -> Result<GeneratedHeaders, FetchError> {
+    let HeaderGeneratorOptions {
+        host, profile, https, destination, mode, navigation_type, custom_headers, content_type, content_length, origin, sec_fetch_site, referer,
+        cookie,
+    } = options;
 
-    // [TODO!]
-    // Note that not all requests are made the same:
-    //  - on forced (Ctrl+R) reloads, Chrome sets Cache-Control: max-age=0
-    //  - when the URL is in the address bar (but not submitted yet), Chrome sets `Purpose: prefetch` and `Sec-Purpose: prefetch`
-    let chrome_headers: Vec<Header> = vec![
-        Header { key: "sec-ch-ua".into(), value: "\"Google Chrome\";v=\"125\", \"Chromium\";v=\"125\", \"Not.A/Brand\";v=\"24\"".into(), is_https: Some(true), ..Header::default() },
-        Header { key: "sec-ch-ua-mobile".into(), value: "?0".into(), is_https: Some(true), ..Header::default() },
-        Header { key: "sec-ch-ua-platform".into(), value: "Linux".into(), is_https: Some(true), ..Header::default() },
-        Header { key: "Host".into(), value: host.into(), is_http1: Some(true), ..Header::default() },
-        Header { key: "Connection".into(), value: "keep-alive".into(), ..Header::default() },
-        Header { key: "Upgrade-Insecure-Requests".into(), value: "1".into(), ..Header::default() },
-        Header { key: "User-Agent".into(), value: "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/125.0.0.0 Safari/537.36".into(), ..Header::default() },
-        Header { key: "Accept".into(), value: "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7".into(), ..Header::default() },
-        Header { key: "sec-fetch-site".into(), value: "none".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "sec-fetch-mode".into(), value: "navigate".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "sec-fetch-user".into(), value: "?1".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "sec-fetch-dest".into(), value: "document".into(), is_https: Some(true), ..Header::default() }, 
-        Header { key: "Accept-Encoding".into(), value: "gzip, deflate".into(), ..Header::default() },
-        Header { key: "Accept-Language".into(), value: "en-US,en;q=0.9".into(), ..Header::default() },
+    let content_length = content_length.map(|length| length.to_string()).unwrap_or_default();
+    let user_agent = profile.user_agent();
+    let version = profile.version().to_string();
+    let sec_ch_ua = profile.sec_ch_ua().unwrap_or_default();
+    let values = [
+        ("host", host.as_str()),
+        ("userAgent", user_agent.as_str()),
+        ("version", version.as_str()),
+        ("secChUa", sec_ch_ua.as_str()),
+        ("accept", profile.accept.get(destination).as_str()),
+        ("priority", profile.priority.as_ref().map(|priority| priority.get(destination).as_str()).unwrap_or_default()),
+        ("secFetchDest", destination.as_str()),
+        ("secFetchMode", mode.as_str()),
+        ("contentType", content_type.as_deref().unwrap_or_default()),
+        ("contentLength", content_length.as_str()),
+        ("origin", origin.as_deref().unwrap_or_default()),
+        ("secFetchSite", sec_fetch_site.as_deref().unwrap_or("none")),
+        ("referer", referer.as_deref().unwrap_or_default()),
+        ("cookie", cookie.as_deref().unwrap_or_default()),
     ];
 
-    let mut headers = HeaderMap::new();
+    let source_headers: Vec<(&ProfileHeader, String)> = profile.headers.iter().map(|header| (header, header.render(&values))).collect();
+    let custom_slot = profile.custom_headers_before.as_str();
 
-    let source_headers = match browser {
-        Browser::Chrome => chrome_headers,
-        _ => firefox_headers, // Default to Firefox
+    // Header names are case-insensitive, so custom headers are matched against the profile by their lowercase names.
+    let custom_headers: IndexMap<String, (String, String)> = custom_headers
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), (key, value)))
+        .collect();
+
+    let is_sent = |(header, value): &(&ProfileHeader, String)| {
+        if header.https.is_some_and(|only_https| only_https != https) {
+            return false;
+        }
+
+        if header.destinations.as_ref().is_some_and(|destinations| !destinations.contains(&destination)) {
+            return false;
+        }
+
+        if header.navigations.as_ref().is_some_and(|navigations| !navigations.contains(&navigation_type)) {
+            return false;
+        }
+
+        // Body, origin, referer and cookie headers are only present on some requests, an empty value means "not sent".
+        !(header.optional && value.is_empty() && !custom_headers.contains_key(&header.name.to_lowercase()))
     };
 
-    let mut custom_headers: HashMap<String, String> = custom_headers.unwrap_or(HashMap::new());
+    // The custom headers that don't replace any of the browser's ones.
+    let new_headers: Vec<&(String, String)> = custom_headers
+        .iter()
+        .filter(|(key, _)| !source_headers.iter().any(|header| header.0.name.eq_ignore_ascii_case(key) && is_sent(header)))
+        .map(|(_, header)| header)
+        .collect();
 
-    for Header { key, value, is_https, is_http1 } in source_headers.iter() {
-        if is_https.is_some() && !https {
-            continue;
+    let mut headers = GeneratedHeaders::default();
+
+    for header in source_headers.iter() {
+        if header.0.name.eq_ignore_ascii_case(custom_slot) {
+            for (key, value) in new_headers.iter() {
+                headers.insert(key, value, None)?;
+            }
         }
 
-        // [TODO!!] - HTTPS != !HTTP1
-        if is_http1.is_some() && https {
+        if !is_sent(header) {
             continue;
         }
 
-        let header_value = match custom_headers.get(key) {
-            Some(value) => value,
+        let (header, value) = header;
+        let value = match custom_headers.get(&header.name.to_lowercase()) {
+            Some((_, value)) => value,
             None => value,
         };
 
-        headers.insert(HeaderName::from_str(key).unwrap(), header_value.parse().unwrap());
-    }
-
-    for key in headers.iter() {
-        custom_headers.remove(key.0.as_str());
-    }
-
-    for (key, value) in custom_headers.iter() {
-        headers.insert(HeaderName::from_str(key).unwrap(), value.parse().unwrap());
+        headers.insert(&header.name, value, header.http1)?;
     }
 
-    headers
+    Ok(headers)
 }
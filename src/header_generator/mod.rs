@@ -1 +1,3 @@
+#[allow(clippy::module_inception)]
 pub mod header_generator;
+pub mod fingerprint_generator;
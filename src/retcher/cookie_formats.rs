@@ -0,0 +1,210 @@
+use serde_json::{json, Value};
+
+use super::cookies::{Cookie, CookieJar, SameSite};
+use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+
+/// CookieFormat is a serialization format of the cookie jar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieFormat {
+  /// The Netscape `cookies.txt` format, used e.g. by curl and yt-dlp.
+  ///
+  /// The format has no field for `SameSite`, so it is lost on export and imported cookies have none.
+  Netscape,
+  /// A JSON array of cookies in the shape used by Puppeteer's and Playwright's `cookies()`.
+  Json,
+}
+
+const NETSCAPE_HEADER: &str = "# Netscape HTTP Cookie File";
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+impl CookieJar {
+  /// Serializes all the (unexpired) cookies in the jar.
+  pub fn export(&self, format: CookieFormat) -> String {
+    let cookies = self.cookies();
+
+    match format {
+      CookieFormat::Netscape => to_netscape(&cookies),
+      CookieFormat::Json => to_json(&cookies),
+    }
+  }
+
+  /// Adds the serialized cookies to the jar and returns how many were stored (already expired cookies are not).
+  ///
+  /// The import is all or nothing - on invalid input or a cookie rejected by the jar, it fails with `FetchErrorKind::InvalidCookie`
+  /// and no cookie is imported.
+  pub fn import(&self, data: &str, format: CookieFormat) -> Result<usize, FetchError> {
+    let cookies = match format {
+      CookieFormat::Netscape => from_netscape(data)?,
+      CookieFormat::Json => from_json(data)?,
+    };
+
+    self.add_all(cookies)
+  }
+}
+
+fn invalid_input(message: String) -> FetchError {
+  FetchError::new(FetchErrorKind::InvalidCookie, FetchPhase::Setup, None, message)
+}
+
+/// The domain as browsers export it - with a leading dot for cookies shared with subdomains.
+fn export_domain(cookie: &Cookie) -> String {
+  if cookie.host_only {
+    cookie.domain.clone()
+  } else {
+    format!(".{}", cookie.domain)
+  }
+}
+
+fn netscape_bool(value: bool) -> &'static str {
+  if value { "TRUE" } else { "FALSE" }
+}
+
+fn to_netscape(cookies: &[Cookie]) -> String {
+  let mut output = format!("{}\n\n", NETSCAPE_HEADER);
+
+  for cookie in cookies {
+    output.push_str(&format!(
+      "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+      if cookie.http_only { HTTP_ONLY_PREFIX } else { "" },
+      export_domain(cookie),
+      netscape_bool(!cookie.host_only),
+      cookie.path,
+      netscape_bool(cookie.secure),
+      // Session cookies have no expiration, `0` is what curl uses for them.
+      cookie.expires.unwrap_or(0),
+      cookie.name,
+      cookie.value,
+    ));
+  }
+
+  output
+}
+
+fn from_netscape(data: &str) -> Result<Vec<Cookie>, FetchError> {
+  let mut cookies = Vec::new();
+
+  for (index, line) in data.lines().enumerate() {
+    let line = line.trim_end_matches('\r');
+
+    let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+      Some(line) => (line, true),
+      None => (line, false),
+    };
+
+    if line.trim().is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let invalid_line = |reason: &str| invalid_input(format!("Invalid cookies.txt line {}: {}", index + 1, reason));
+
+    let fields: Vec<&str> = line.split('\t').collect();
+    // The value may be missing altogether for empty cookies.
+    if fields.len() != 7 && fields.len() != 6 {
+      return Err(invalid_line("expected 7 tab-separated fields"));
+    }
+
+    let parse_bool = |value: &str| match value.to_uppercase().as_str() {
+      "TRUE" => Ok(true),
+      "FALSE" => Ok(false),
+      _ => Err(invalid_line(&format!("'{}' is not TRUE or FALSE", value))),
+    };
+
+    let include_subdomains = parse_bool(fields[1])?;
+    let secure = parse_bool(fields[3])?;
+    let expires: i64 = fields[4].parse().map_err(|_| invalid_line(&format!("'{}' is not a valid expiration", fields[4])))?;
+
+    cookies.push(Cookie {
+      name: fields[5].to_string(),
+      value: fields.get(6).unwrap_or(&"").to_string(),
+      domain: fields[0].trim_start_matches('.').to_string(),
+      host_only: !include_subdomains,
+      path: fields[2].to_string(),
+      expires: if expires == 0 { None } else { Some(expires) },
+      secure,
+      http_only,
+      same_site: None,
+    });
+  }
+
+  Ok(cookies)
+}
+
+fn to_json(cookies: &[Cookie]) -> String {
+  let cookies: Vec<Value> = cookies.iter().map(|cookie| {
+    let mut value = json!({
+      "name": cookie.name,
+      "value": cookie.value,
+      "domain": export_domain(cookie),
+      "path": cookie.path,
+      "expires": cookie.expires.unwrap_or(-1),
+      "size": cookie.name.len() + cookie.value.len(),
+      "httpOnly": cookie.http_only,
+      "secure": cookie.secure,
+      "session": cookie.expires.is_none(),
+    });
+
+    if let Some(same_site) = cookie.same_site {
+      value["sameSite"] = json!(match same_site {
+        SameSite::Strict => "Strict",
+        SameSite::Lax => "Lax",
+        SameSite::None => "None",
+      });
+    }
+
+    value
+  }).collect();
+
+  Value::Array(cookies).to_string()
+}
+
+fn from_json(data: &str) -> Result<Vec<Cookie>, FetchError> {
+  let value: Value = serde_json::from_str(data)
+    .map_err(|error| invalid_input(format!("Invalid cookie JSON: {}", error)).with_source(error))?;
+
+  let Value::Array(items) = value else {
+    return Err(invalid_input("Invalid cookie JSON: expected an array of cookies".to_string()));
+  };
+
+  items.iter().enumerate().map(|(index, item)| {
+    let invalid_cookie = |reason: &str| invalid_input(format!("Invalid cookie JSON at index {}: {}", index, reason));
+
+    let string = |key: &str| item.get(key).and_then(Value::as_str);
+    let boolean = |key: &str| item.get(key).and_then(Value::as_bool).unwrap_or(false);
+
+    let name = string("name").ok_or_else(|| invalid_cookie("missing 'name'"))?;
+    let value = string("value").ok_or_else(|| invalid_cookie("missing 'value'"))?;
+    let domain = string("domain").ok_or_else(|| invalid_cookie("missing 'domain'"))?;
+
+    // Puppeteer and Playwright use `-1` (and Puppeteer also `session: true`) for session cookies.
+    let expires = match item.get("expires").and_then(Value::as_f64) {
+      Some(expires) if expires >= 0.0 && !boolean("session") => Some(expires as i64),
+      _ => None,
+    };
+
+    let same_site = match string("sameSite") {
+      None => None,
+      Some(same_site) if same_site.eq_ignore_ascii_case("strict") => Some(SameSite::Strict),
+      Some(same_site) if same_site.eq_ignore_ascii_case("lax") => Some(SameSite::Lax),
+      Some(same_site) if same_site.eq_ignore_ascii_case("none") => Some(SameSite::None),
+      Some(same_site) => return Err(invalid_cookie(&format!("'{}' is not a valid sameSite value", same_site))),
+    };
+    // Firefox and Playwright export `None` for cookies set without `SameSite`, even insecure ones the jar would reject.
+    // Like Chromium does for such legacy cookies, they are treated as `Lax`.
+    let same_site = match same_site {
+      Some(SameSite::None) if !boolean("secure") => Some(SameSite::Lax),
+      same_site => same_site,
+    };
+
+    Ok(Cookie {
+      name: name.to_string(),
+      value: value.to_string(),
+      domain: domain.trim_start_matches('.').to_string(),
+      host_only: !domain.starts_with('.'),
+      path: string("path").unwrap_or("/").to_string(),
+      expires,
+      secure: boolean("secure"),
+      http_only: boolean("httpOnly"),
+      same_site,
+    })
+  }).collect()
+}
@@ -0,0 +1,280 @@
+use std::sync::{Mutex, MutexGuard};
+
+use cookie::time::OffsetDateTime;
+use cookie::Cookie as RawCookie;
+use cookie_store::{CookieDomain, CookieError, CookieExpiration, CookieStore};
+use reqwest::Method;
+use url::Url;
+
+use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+use super::site::{self, SiteRelation};
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+  /// Sent only with same-site requests.
+  Strict,
+  /// Sent with same-site requests and with cross-site top-level navigations using a safe method (e.g. `GET`).
+  Lax,
+  /// Sent with all requests. Requires the `Secure` attribute.
+  None,
+}
+
+/// Cookie is a single cookie stored in a `CookieJar`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+  pub name: String,
+  pub value: String,
+  /// The domain of the cookie, without any leading dot.
+  pub domain: String,
+  /// Whether the cookie is sent only to `domain` itself and not to its subdomains (i.e. it was set without the `Domain` attribute).
+  pub host_only: bool,
+  pub path: String,
+  /// The expiration time as a UNIX timestamp (in seconds), `None` for session cookies.
+  pub expires: Option<i64>,
+  pub secure: bool,
+  pub http_only: bool,
+  /// The `SameSite` attribute, `None` if the cookie was set without it.
+  pub same_site: Option<SameSite>,
+}
+
+impl From<&cookie_store::Cookie<'static>> for Cookie {
+  fn from(cookie: &cookie_store::Cookie<'static>) -> Self {
+    let (domain, host_only) = match &cookie.domain {
+      CookieDomain::HostOnly(domain) => (domain.clone(), true),
+      CookieDomain::Suffix(domain) => (domain.clone(), false),
+      _ => (String::new(), true),
+    };
+
+    Cookie {
+      name: cookie.name().to_string(),
+      value: cookie.value().to_string(),
+      domain,
+      host_only,
+      path: cookie.path.as_ref().to_string(),
+      expires: match cookie.expires {
+        CookieExpiration::AtUtc(expires) => Some(expires.unix_timestamp()),
+        CookieExpiration::SessionEnd => None,
+      },
+      secure: cookie.secure().unwrap_or(false),
+      http_only: cookie.http_only().unwrap_or(false),
+      same_site: cookie.same_site().map(|same_site| match same_site {
+        cookie::SameSite::Strict => SameSite::Strict,
+        cookie::SameSite::Lax => SameSite::Lax,
+        cookie::SameSite::None => SameSite::None,
+      }),
+    }
+  }
+}
+
+/// CookieJar stores the cookies of a `Retcher` instance, like a browser profile does.
+///
+/// Cookies are matched by `Domain`, `Path`, `Secure` and `SameSite` the way browsers do it.
+/// Cookies for public suffixes (e.g. `Domain=co.uk`) are rejected using the Public Suffix List.
+pub struct CookieJar {
+  store: Mutex<CookieStore>,
+}
+
+impl Default for CookieJar {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl CookieJar {
+  /// Creates an empty `CookieJar`.
+  pub fn new() -> Self {
+    CookieJar {
+      store: Mutex::new(CookieStore::new_with_public_suffix(Some(site::public_suffix_list().clone()))),
+    }
+  }
+
+  fn store(&self) -> MutexGuard<'_, CookieStore> {
+    // The store is never left in an inconsistent state, so a panic elsewhere doesn't make it unusable.
+    self.store.lock().unwrap_or_else(|error| error.into_inner())
+  }
+
+  /// Returns all the (unexpired) cookies in the jar.
+  pub fn cookies(&self) -> Vec<Cookie> {
+    self.store().iter_unexpired().map(Cookie::from).collect()
+  }
+
+  /// Returns the cookies that would be sent with a same-site request to the given URL.
+  pub fn cookies_for_url(&self, url: &str) -> Result<Vec<Cookie>, FetchError> {
+    let url = parse_cookie_url(url)?;
+
+    Ok(sorted_matches(&self.store(), &url).into_iter().map(Cookie::from).collect())
+  }
+
+  /// Stores a cookie from a `Set-Cookie` header value, as if it was received in a response from `url`.
+  pub fn set_cookie(&self, set_cookie: &str, url: &str) -> Result<(), FetchError> {
+    let url = parse_cookie_url(url)?;
+
+    let cookie = RawCookie::parse(set_cookie.to_string())
+      .map_err(|error| invalid_cookie(format!("Invalid cookie '{}': {}", set_cookie, error)).with_source(error))?;
+
+    self.insert(cookie, &url).map_err(invalid_cookie)
+  }
+
+  /// Adds the cookie to the jar, replacing any cookie with the same name, domain and path.
+  pub fn add(&self, cookie: Cookie) -> Result<(), FetchError> {
+    let (cookie, url) = to_raw_cookie(cookie)?;
+    self.insert(cookie, &url).map_err(invalid_cookie)
+  }
+
+  /// Adds all the cookies to the jar, or none of them if any is invalid or rejected.
+  /// Returns how many were stored (already expired cookies are not).
+  pub(crate) fn add_all(&self, cookies: Vec<Cookie>) -> Result<usize, FetchError> {
+    let cookies = cookies.into_iter().map(to_raw_cookie).collect::<Result<Vec<_>, _>>()?;
+
+    // The cookies are staged in a copy of the store, which replaces it once they are all in.
+    let mut store = self.store();
+    let mut staged = store.clone();
+    let mut stored = 0;
+    for (cookie, url) in cookies {
+      if insert_into(&mut staged, cookie, &url).map_err(invalid_cookie)? {
+        stored += 1;
+      }
+    }
+    *store = staged;
+
+    Ok(stored)
+  }
+
+  /// Removes the cookie with the given name, domain and path. Returns whether there was such a cookie.
+  pub fn remove(&self, name: &str, domain: &str, path: &str) -> bool {
+    self.store().remove(domain.trim_start_matches('.'), path, name).is_some()
+  }
+
+  /// Removes all the cookies.
+  pub fn clear(&self) {
+    self.store().clear();
+  }
+
+  /// Stores the cookies from the `Set-Cookie` headers of a response. Invalid cookies are ignored, like in browsers.
+  pub(crate) fn store_response_cookies(&self, url: &Url, set_cookies: impl IntoIterator<Item = String>) {
+    for set_cookie in set_cookies {
+      if let Ok(cookie) = RawCookie::parse(set_cookie) {
+        let _ = self.insert(cookie, url);
+      }
+    }
+  }
+
+  /// Returns the `Cookie` header value for a request to `url`, `None` if there are no cookies to send.
+  ///
+  /// `site_relation` is the relation of the request to its initiator. Cross-site, `SameSite=Lax` cookies are only sent with the `top_level`
+  /// navigations. With `lax_by_default`, cookies without `SameSite` are treated as `SameSite=Lax` (like in Chrome).
+  pub(crate) fn cookie_header(&self, url: &Url, site_relation: SiteRelation, method: &Method, top_level: bool, lax_by_default: bool) -> Option<String> {
+    let store = self.store();
+
+    let cookies: Vec<String> = sorted_matches(&store, url)
+      .into_iter()
+      .filter(|cookie| {
+        let same_site = match cookie.same_site() {
+          Some(same_site) => same_site,
+          None if lax_by_default => cookie::SameSite::Lax,
+          None => cookie::SameSite::None,
+        };
+
+        match same_site {
+          cookie::SameSite::Strict => site_relation != SiteRelation::CrossSite,
+          cookie::SameSite::Lax => site_relation != SiteRelation::CrossSite || (top_level && method.is_safe()),
+          cookie::SameSite::None => true,
+        }
+      })
+      .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+      .collect();
+
+    if cookies.is_empty() {
+      None
+    } else {
+      Some(cookies.join("; "))
+    }
+  }
+
+  /// Inserts the cookie, enforcing the rules browsers have on top of RFC 6265.
+  fn insert(&self, cookie: RawCookie<'_>, url: &Url) -> Result<(), String> {
+    insert_into(&mut self.store(), cookie, url).map(|_| ())
+  }
+}
+
+/// Builds the cookie to store and the URL it's stored as received from.
+fn to_raw_cookie(cookie: Cookie) -> Result<(RawCookie<'static>, Url), FetchError> {
+  let domain = cookie.domain.trim_start_matches('.').to_string();
+  let scheme = if cookie.secure { "https" } else { "http" };
+  let url = parse_cookie_url(&format!("{}://{}{}", scheme, domain, cookie.path))?;
+
+  let mut raw = RawCookie::build((cookie.name, cookie.value))
+    .path(cookie.path)
+    .secure(cookie.secure)
+    .http_only(cookie.http_only);
+
+  if !cookie.host_only {
+    raw = raw.domain(domain);
+  }
+
+  if let Some(expires) = cookie.expires {
+    let expires = OffsetDateTime::from_unix_timestamp(expires)
+      .map_err(|error| invalid_cookie(format!("Invalid cookie expiration '{}': {}", expires, error)).with_source(error))?;
+    raw = raw.expires(expires);
+  }
+
+  if let Some(same_site) = cookie.same_site {
+    raw = raw.same_site(match same_site {
+      SameSite::Strict => cookie::SameSite::Strict,
+      SameSite::Lax => cookie::SameSite::Lax,
+      SameSite::None => cookie::SameSite::None,
+    });
+  }
+
+  Ok((raw.build(), url))
+}
+
+/// Inserts the cookie into the store, enforcing the rules browsers have on top of RFC 6265.
+/// Returns whether the cookie was stored, `false` for an already expired one.
+fn insert_into(store: &mut CookieStore, cookie: RawCookie<'_>, url: &Url) -> Result<bool, String> {
+  let secure = cookie.secure().unwrap_or(false);
+
+  if secure && url.scheme() != "https" {
+    return Err(format!("Cookie '{}' has the Secure attribute, but {} is not secure", cookie.name(), url));
+  }
+
+  if cookie.same_site() == Some(cookie::SameSite::None) && !secure {
+    return Err(format!("Cookie '{}' has SameSite=None, but not the Secure attribute", cookie.name()));
+  }
+
+  if cookie.name().starts_with("__Secure-") && !secure {
+    return Err(format!("Cookie '{}' with the __Secure- prefix must have the Secure attribute", cookie.name()));
+  }
+
+  if cookie.name().starts_with("__Host-") && (!secure || cookie.domain().is_some() || cookie.path() != Some("/")) {
+    return Err(format!("Cookie '{}' with the __Host- prefix must be Secure, without Domain and with Path=/", cookie.name()));
+  }
+
+  match store.insert_raw(&cookie, url) {
+    Ok(_) => Ok(true),
+    // An already expired cookie only deletes the stored one.
+    Err(CookieError::Expired) => Ok(false),
+    Err(error) => Err(format!("Cookie '{}' was rejected for {}: {}", cookie.name(), url, error)),
+  }
+}
+
+/// Returns the cookies matching the URL, in the order browsers send them (longer paths first).
+fn sorted_matches<'a>(store: &'a CookieStore, url: &Url) -> Vec<&'a cookie_store::Cookie<'static>> {
+  let mut cookies = store.matches(url);
+  cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.as_ref().len()));
+  cookies
+}
+
+fn parse_cookie_url(url: &str) -> Result<Url, FetchError> {
+  Url::parse(url).map_err(|error| FetchError::new(
+    FetchErrorKind::InvalidUrl,
+    FetchPhase::Setup,
+    Some(url.to_string()),
+    format!("Invalid URL '{}': {}", url, error),
+  ).with_source(error))
+}
+
+fn invalid_cookie(message: String) -> FetchError {
+  FetchError::new(FetchErrorKind::InvalidCookie, FetchPhase::Setup, None, message)
+}
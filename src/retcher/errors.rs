@@ -0,0 +1,259 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// The kind of a `FetchError`, telling what went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchErrorKind {
+  /// The URL couldn't be parsed.
+  InvalidUrl,
+  /// The URL uses a protocol other than `http` or `https`.
+  UnsupportedProtocol,
+  /// The request couldn't be built, e.g. because of an invalid header.
+  InvalidRequest,
+  /// The hostname couldn't be resolved.
+  Dns,
+  /// The server actively refused the connection.
+  ConnectionRefused,
+  /// The connection failed or was closed unexpectedly.
+  Connection,
+  /// The TLS handshake failed.
+  Tls,
+  /// The proxy is misconfigured or failed to connect to the target.
+  Proxy,
+  /// The request timed out.
+  Timeout,
+  /// The redirect handling failed (e.g. a redirect loop).
+  Redirect,
+  /// Reading the response body failed.
+  Body,
+  /// The response body couldn't be decoded (e.g. corrupted compression).
+  Decode,
+  /// The response body has already been consumed.
+  BodyUsed,
+  /// The response body isn't valid JSON.
+  InvalidJson,
+  /// A cookie couldn't be parsed or was rejected by the cookie jar.
+  InvalidCookie,
+  /// A browser profile couldn't be read or isn't valid.
+  InvalidProfile,
+  /// Any other error.
+  Request,
+}
+
+impl FetchErrorKind {
+  /// Returns a stable, machine-readable code for this kind (used as `err.code` in Node).
+  pub fn code(&self) -> &'static str {
+    match self {
+      FetchErrorKind::InvalidUrl => "ERR_INVALID_URL",
+      FetchErrorKind::UnsupportedProtocol => "ERR_UNSUPPORTED_PROTOCOL",
+      FetchErrorKind::InvalidRequest => "ERR_INVALID_REQUEST",
+      FetchErrorKind::Dns => "ERR_DNS",
+      FetchErrorKind::ConnectionRefused => "ERR_CONNECTION_REFUSED",
+      FetchErrorKind::Connection => "ERR_CONNECTION",
+      FetchErrorKind::Tls => "ERR_TLS",
+      FetchErrorKind::Proxy => "ERR_PROXY",
+      FetchErrorKind::Timeout => "ERR_TIMEOUT",
+      FetchErrorKind::Redirect => "ERR_REDIRECT",
+      FetchErrorKind::Body => "ERR_BODY",
+      FetchErrorKind::Decode => "ERR_DECODE",
+      FetchErrorKind::BodyUsed => "ERR_BODY_USED",
+      FetchErrorKind::InvalidJson => "ERR_INVALID_JSON",
+      FetchErrorKind::InvalidCookie => "ERR_INVALID_COOKIE",
+      FetchErrorKind::InvalidProfile => "ERR_INVALID_PROFILE",
+      FetchErrorKind::Request => "ERR_REQUEST",
+    }
+  }
+}
+
+impl fmt::Display for FetchErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.code())
+  }
+}
+
+/// The phase of the request in which a `FetchError` happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchPhase {
+  /// Parsing the URL and building the request, before any network activity.
+  Setup,
+  /// Resolving the host and establishing the (TLS) connection.
+  Connect,
+  /// Sending the request and waiting for the response head.
+  Request,
+  /// Reading the response body.
+  Response,
+}
+
+impl fmt::Display for FetchPhase {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      FetchPhase::Setup => "setup",
+      FetchPhase::Connect => "connect",
+      FetchPhase::Request => "request",
+      FetchPhase::Response => "response",
+    })
+  }
+}
+
+/// FetchError is the error returned when a request fails.
+#[derive(Debug, Clone)]
+pub struct FetchError {
+  /// What went wrong.
+  pub kind: FetchErrorKind,
+  /// When it went wrong.
+  pub phase: FetchPhase,
+  /// The URL of the failed request, if it got far enough to have one.
+  pub url: Option<String>,
+  /// A human-readable description of the error.
+  pub message: String,
+  source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl FetchError {
+  /// Creates a new `FetchError` without an underlying source error.
+  pub fn new(kind: FetchErrorKind, phase: FetchPhase, url: Option<String>, message: impl Into<String>) -> Self {
+    FetchError {
+      kind,
+      phase,
+      url,
+      message: message.into(),
+      source: None,
+    }
+  }
+
+  /// Attaches the underlying error that caused this one.
+  pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+    self.source = Some(Arc::new(source));
+    self
+  }
+
+  /// Returns whether retrying the same request might succeed.
+  ///
+  /// This is only a hint - e.g. a timeout is usually transient, while an invalid URL or a failed TLS handshake aren't.
+  pub fn is_retryable(&self) -> bool {
+    match self.kind {
+      FetchErrorKind::Dns
+        | FetchErrorKind::ConnectionRefused
+        | FetchErrorKind::Connection
+        | FetchErrorKind::Timeout
+        | FetchErrorKind::Body => true,
+      // A failing proxy might work next time (or another one might), a misconfigured one won't.
+      FetchErrorKind::Proxy => self.phase != FetchPhase::Setup,
+      _ => false,
+    }
+  }
+
+  /// Creates the error for a connection the relay (see `tunnel`) couldn't open, described like the `reqwest` ones.
+  pub(crate) fn from_relay(kind: FetchErrorKind, url: &str, cause: &str) -> Self {
+    FetchError::new(
+      kind,
+      FetchPhase::Connect,
+      Some(url.to_string()),
+      format!("{} while processing {} ({})", kind_description(kind), url, cause),
+    )
+  }
+
+  /// Classifies an error returned by `reqwest` during the given phase.
+  pub(crate) fn from_reqwest(error: reqwest::Error, phase: FetchPhase) -> Self {
+    let url = error.url().map(|url| url.to_string());
+
+    let (kind, phase) = if error.is_timeout() {
+      (FetchErrorKind::Timeout, if error.is_connect() { FetchPhase::Connect } else { phase })
+    } else if error.is_connect() {
+      (classify_connect_error(&error), FetchPhase::Connect)
+    } else if error.is_redirect() {
+      (FetchErrorKind::Redirect, phase)
+    } else if error.is_decode() {
+      (FetchErrorKind::Decode, phase)
+    } else if error.is_body() {
+      (FetchErrorKind::Body, phase)
+    } else if error.is_builder() {
+      (FetchErrorKind::InvalidRequest, FetchPhase::Setup)
+    } else if find_source::<std::io::Error>(&error).is_some() || find_source::<hyper::Error>(&error).is_some() {
+      (FetchErrorKind::Connection, phase)
+    } else {
+      (FetchErrorKind::Request, phase)
+    };
+
+    let message = match error.source() {
+      Some(_) => format!("{} while processing {} ({})", kind_description(kind), url.as_deref().unwrap_or("the request"), root_cause(&error)),
+      None => format!("{} while processing {}", kind_description(kind), url.as_deref().unwrap_or("the request")),
+    };
+
+    FetchError::new(kind, phase, url, message).with_source(error)
+  }
+}
+
+impl fmt::Display for FetchError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} [{}, {} phase]", self.message, self.kind, self.phase)
+  }
+}
+
+impl Error for FetchError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    self.source.as_ref().map(|source| source.as_ref() as &(dyn Error + 'static))
+  }
+}
+
+fn kind_description(kind: FetchErrorKind) -> &'static str {
+  match kind {
+    FetchErrorKind::InvalidUrl => "Invalid URL",
+    FetchErrorKind::UnsupportedProtocol => "Unsupported protocol",
+    FetchErrorKind::InvalidRequest => "Invalid request",
+    FetchErrorKind::Dns => "DNS lookup failed",
+    FetchErrorKind::ConnectionRefused => "Connection refused",
+    FetchErrorKind::Connection => "Connection failed",
+    FetchErrorKind::Tls => "TLS handshake failed",
+    FetchErrorKind::Proxy => "Proxy connection failed",
+    FetchErrorKind::Timeout => "Request timed out",
+    FetchErrorKind::Redirect => "Redirect failed",
+    FetchErrorKind::Body => "Reading the response body failed",
+    FetchErrorKind::Decode => "Decoding the response body failed",
+    FetchErrorKind::BodyUsed => "The response body has already been used",
+    FetchErrorKind::InvalidJson => "The response body is not valid JSON",
+    FetchErrorKind::InvalidCookie => "Invalid cookie",
+    FetchErrorKind::InvalidProfile => "Invalid browser profile",
+    FetchErrorKind::Request => "Request failed",
+  }
+}
+
+/// Tells apart the reasons a connection couldn't be established by walking the error's source chain.
+///
+/// `reqwest` only ever connects to the relay in `tunnel`, which reports what happened to the server itself (see `tunnel::target_failure`).
+/// A failed connection without an IO error behind it is a tunnel the relay (or the proxy behind it) refused.
+fn classify_connect_error(error: &reqwest::Error) -> FetchErrorKind {
+  #[cfg(feature = "openssl")]
+  if find_source::<native_tls::Error>(error).is_some() {
+    return FetchErrorKind::Tls;
+  }
+
+  match find_source::<std::io::Error>(error).map(std::io::Error::kind) {
+    Some(std::io::ErrorKind::ConnectionRefused) => FetchErrorKind::ConnectionRefused,
+    Some(std::io::ErrorKind::TimedOut) => FetchErrorKind::Timeout,
+    Some(_) => FetchErrorKind::Connection,
+    None => FetchErrorKind::Proxy,
+  }
+}
+
+fn find_source<'a, T: Error + 'static>(error: &'a (dyn Error + 'static)) -> Option<&'a T> {
+  let mut source: Option<&(dyn Error + 'static)> = Some(error);
+
+  while let Some(error) = source {
+    if let Some(error) = error.downcast_ref::<T>() {
+      return Some(error);
+    }
+    source = error.source();
+  }
+
+  None
+}
+
+fn root_cause(error: &(dyn Error + 'static)) -> String {
+  let mut error = error;
+  while let Some(source) = error.source() {
+    error = source;
+  }
+  error.to_string()
+}
@@ -0,0 +1,147 @@
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use super::tunnel::{self, HEADER_ORDER, MAX_HEAD_LENGTH};
+
+/// How the body of a request is framed.
+#[derive(Debug, PartialEq)]
+enum Body {
+  Length(u64),
+  Chunked,
+}
+
+/// Relays HTTP/1.1 requests from `reqwest` to the server, rewriting their heads with the browser's header order and casing.
+///
+/// `first` is the head of the first request, if the relay has already read it. With `origin_form`, the absolute URLs of forwarded
+/// requests are replaced with their paths, as the server (and not a proxy) gets them. The responses pass through as they are.
+pub(crate) async fn relay<C, U>(client: C, upstream: U, first: Option<String>, origin_form: bool) -> io::Result<()>
+where
+  C: AsyncRead + AsyncWrite + Unpin,
+  U: AsyncRead + AsyncWrite + Unpin,
+{
+  let (client_reader, mut client_writer) = tokio::io::split(client);
+  // The bodies are copied from the same reader, starting with what it buffered after their heads.
+  let mut client_reader = BufReader::new(client_reader);
+  let (mut upstream_reader, mut upstream_writer) = tokio::io::split(upstream);
+
+  let requests = async {
+    let mut first = first;
+
+    loop {
+      let head = match first.take() {
+        Some(head) => head,
+        None => match tunnel::read_head(&mut client_reader).await {
+          Some(head) => head,
+          None => return Ok(()),
+        },
+      };
+
+      let (head, body) = rewrite_head(&head, origin_form);
+      upstream_writer.write_all(head.as_bytes()).await?;
+
+      match body {
+        Some(Body::Length(length)) => {
+          tokio::io::copy(&mut (&mut client_reader).take(length), &mut upstream_writer).await?;
+        }
+        Some(Body::Chunked) => copy_chunks(&mut client_reader, &mut upstream_writer).await?,
+        None => {}
+      }
+
+      upstream_writer.flush().await?;
+    }
+  };
+
+  tokio::select! {
+    result = requests => result,
+    result = tokio::io::copy(&mut upstream_reader, &mut client_writer) => result.map(|_| ()),
+  }
+}
+
+/// Rewrites a request head with the header order and casing listed in `HEADER_ORDER`, and returns how its body is framed.
+fn rewrite_head(head: &str, origin_form: bool) -> (String, Option<Body>) {
+  let mut lines = head.split("\r\n").filter(|line| !line.is_empty());
+  let request_line = lines.next().unwrap_or_default();
+
+  let headers: Vec<(&str, &str)> = lines
+    .filter_map(|line| line.split_once(':'))
+    .map(|(name, value)| (name.trim(), value.trim()))
+    .collect();
+  let order = headers.iter().find(|(name, _)| name.eq_ignore_ascii_case(HEADER_ORDER)).map_or("", |(_, value)| value);
+  let headers = tunnel::order_headers(headers.into_iter().filter(|(name, _)| !tunnel::is_private_header(name)).collect(), order, true);
+
+  let mut rewritten = match request_line.split(' ').collect::<Vec<_>>()[..] {
+    [method, target, version] if origin_form => format!("{} {} {}\r\n", method, origin_form_target(target), version),
+    _ => format!("{}\r\n", request_line),
+  };
+  for (name, value) in &headers {
+    rewritten.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  rewritten.push_str("\r\n");
+
+  let header = |wanted: &str| headers.iter().find(|(name, _)| name.eq_ignore_ascii_case(wanted)).map(|(_, value)| *value);
+  let body = match (header("transfer-encoding"), header("content-length")) {
+    (Some(encoding), _) if encoding.to_ascii_lowercase().ends_with("chunked") => Some(Body::Chunked),
+    (_, Some(length)) => length.parse().ok().map(Body::Length),
+    _ => None,
+  };
+
+  (rewritten, body)
+}
+
+/// The path (and query) of an absolute URL request target.
+fn origin_form_target(target: &str) -> &str {
+  let Some((_, rest)) = target.split_once("://") else { return target };
+
+  match rest.find(['/', '?']) {
+    Some(index) if rest[index..].starts_with('/') => &rest[index..],
+    _ => "/",
+  }
+}
+
+/// Copies a chunked body (RFC 9112, 7.1) up to and including its trailer section.
+async fn copy_chunks<R, W>(reader: &mut R, writer: &mut W) -> io::Result<()>
+where
+  R: AsyncBufRead + Unpin,
+  W: AsyncWrite + Unpin,
+{
+  loop {
+    let line = read_line(reader).await?;
+    writer.write_all(line.as_bytes()).await?;
+
+    let size = line.split(';').next().unwrap_or_default().trim();
+    let size = u64::from_str_radix(size, 16).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+
+    if size == 0 {
+      break;
+    }
+
+    // The chunk data, and the line break after it.
+    tokio::io::copy(&mut reader.take(size + 2), writer).await?;
+  }
+
+  // The trailer fields, up to the empty line.
+  loop {
+    let line = read_line(reader).await?;
+    writer.write_all(line.as_bytes()).await?;
+
+    if line == "\r\n" {
+      return Ok(());
+    }
+  }
+}
+
+/// Reads a line, including its line break.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+  let mut line = Vec::new();
+  (&mut *reader).take(MAX_HEAD_LENGTH as u64).read_until(b'\n', &mut line).await?;
+
+  if !line.ends_with(b"\n") {
+    return Err(match line.len() {
+      MAX_HEAD_LENGTH => io::Error::new(io::ErrorKind::InvalidData, "chunk line too long"),
+      _ => io::ErrorKind::UnexpectedEof.into(),
+    });
+  }
+
+  Ok(String::from_utf8_lossy(&line).into_owned())
+}
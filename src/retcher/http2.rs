@@ -0,0 +1,462 @@
+use std::io;
+
+use bytes::{BufMut, BytesMut};
+use futures_util::StreamExt;
+use h2::frame::{Frame, Head, Headers, Kind, Settings, StreamId};
+use h2::Codec;
+use httlib_hpack::{Decoder, Encoder};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+use super::profile::DestinationValues;
+use super::retcher::Destination;
+use super::tunnel::{self, DESTINATION, HEADER_ORDER};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The frame size every HTTP/2 peer accepts.
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+
+/// The length of a frame header (RFC 9113, 4.1).
+const FRAME_HEAD_LENGTH: usize = 9;
+
+/// The header table size both peers start out with (RFC 9113, 6.5.2).
+const DEFAULT_HEADER_TABLE_SIZE: u32 = 4_096;
+
+// The frame types the relay looks into (RFC 9113, 6).
+const HEADERS: u8 = 0x1;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const CONTINUATION: u8 = 0x9;
+
+// The SETTINGS parameters (RFC 9113, 6.5.2).
+pub(crate) const HEADER_TABLE_SIZE: u16 = 0x1;
+pub(crate) const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+/// A pseudo-header of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PseudoHeader {
+  Method,
+  Authority,
+  Scheme,
+  Path,
+}
+
+/// The priority the browser gives its requests in the `HEADERS` frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HeadersPriority {
+  pub exclusive: bool,
+  /// The weight, 1 to 256.
+  pub weight: u16,
+}
+
+/// Http2Profile is how a browser opens its HTTP/2 connections, i.e. what the Akamai HTTP/2 fingerprint is made of.
+///
+/// Neither browser sends `PRIORITY` frames anymore, the requests only carry a priority in their `HEADERS` frames,
+/// which depends on what they're for (see `Destination`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub(crate) struct Http2Profile {
+  /// The `SETTINGS` parameters, in the browser's order.
+  pub settings: Vec<(u16, u32)>,
+  /// The connection window size, announced with a `WINDOW_UPDATE` right after the `SETTINGS`.
+  pub connection_window_size: u32,
+  pub pseudo_header_order: [PseudoHeader; 4],
+  pub priority: DestinationValues<HeadersPriority>,
+}
+
+impl Http2Profile {
+  /// Returns the value of the `SETTINGS` parameter, if the browser sends it.
+  pub fn setting(&self, id: u16) -> Option<u32> {
+    self.settings.iter().find(|(setting, _)| *setting == id).map(|(_, value)| *value)
+  }
+}
+
+/// Relays an HTTP/2 connection from `reqwest` to the server, rewriting the frames that make up the browser's fingerprint.
+///
+/// `reqwest`'s first `SETTINGS` frame is replaced with the browser's one, and the requests' `HEADERS` are re-encoded with the browser's
+/// pseudo-header order, header order, priority and HPACK encoding (see `HeaderEncoder`). The flow control windows `reqwest` is configured with
+/// (see `Retcher::engine`) match the browser's, so its `WINDOW_UPDATE`s are the browser's as well. The responses' header blocks are re-encoded
+/// for `reqwest` (see `relay_responses`), everything else passes through as is.
+///
+/// The two directions are relayed independently, so neither peer has to read for the other one's frames to go through.
+pub(crate) async fn relay<C, U>(client: C, upstream: U, profile: &Http2Profile) -> io::Result<()>
+where
+  C: AsyncRead + AsyncWrite + Unpin,
+  U: AsyncRead + AsyncWrite + Unpin,
+{
+  let (mut client_reader, client_writer) = tokio::io::split(client);
+  let (upstream_reader, upstream_writer) = tokio::io::split(upstream);
+
+  let mut preface = [0u8; PREFACE.len()];
+  client_reader.read_exact(&mut preface).await?;
+  if preface != PREFACE {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP/2 connection preface"));
+  }
+
+  let (limits, limits_changed) = watch::channel(DecodingLimits::default());
+
+  tokio::select! {
+    result = relay_requests(client_reader, upstream_writer, profile, limits_changed) => result,
+    result = relay_responses(upstream_reader, client_writer, profile, limits) => result,
+  }
+}
+
+/// The limits of the server's `SETTINGS`, which `reqwest` follows, so the relay has to decode its frames with them.
+/// The relay's `HeaderEncoder` follows the header table size too.
+#[derive(Debug, Clone, Copy, Default)]
+struct DecodingLimits {
+  header_table_size: Option<u32>,
+  max_frame_size: Option<u32>,
+}
+
+/// Decodes `reqwest`'s frames and sends them to the server the way the browser would.
+async fn relay_requests<C, U>(client: C, mut upstream: U, profile: &Http2Profile, mut limits: watch::Receiver<DecodingLimits>) -> io::Result<()>
+where
+  C: AsyncRead + Unpin,
+  U: AsyncWrite + Unpin,
+{
+  // The codec only reads, the relay encodes the frames itself.
+  let mut client: Codec<_, bytes::Bytes> = Codec::new(tokio::io::join(client, tokio::io::sink()));
+
+  // The preface goes out with the first `SETTINGS`, like the browser sends it.
+  let mut buffer = BytesMut::from(PREFACE);
+  let mut settings_sent = false;
+  let mut encoder = HeaderEncoder::new();
+
+  loop {
+    // `reqwest` only applies the limits once it got the `SETTINGS`, i.e. after `relay_responses` changed them.
+    let frame = tokio::select! {
+      biased;
+      Ok(()) = limits.changed() => {
+        let DecodingLimits { header_table_size, max_frame_size } = *limits.borrow_and_update();
+        if let Some(size) = header_table_size {
+          client.set_recv_header_table_size(size as usize);
+          encoder.set_max_table_size(size);
+        }
+        if let Some(size) = max_frame_size {
+          client.set_max_recv_frame_size(size as usize);
+        }
+        continue;
+      }
+      frame = client.next() => frame,
+    };
+    let Some(Ok(frame)) = frame else { break };
+
+    match frame {
+      Frame::Settings(settings) if settings.is_ack() => Settings::ack().encode(&mut buffer),
+      Frame::Settings(settings) if settings_sent => settings.encode(&mut buffer),
+      Frame::Settings(_) => {
+        encode_settings(&profile.settings, &mut buffer);
+        settings_sent = true;
+      }
+      Frame::Headers(headers) => encode_headers(headers, profile, &mut encoder, &mut buffer)?,
+      Frame::Data(data) => {
+        let flags = if data.is_end_stream() { END_STREAM } else { 0 };
+        Head::new(Kind::Data, flags, data.stream_id()).encode(data.payload().len(), &mut buffer);
+        buffer.put_slice(data.payload());
+      }
+      Frame::WindowUpdate(window_update) => window_update.encode(&mut buffer),
+      Frame::Ping(ping) => ping.encode(&mut buffer),
+      Frame::Reset(reset) => reset.encode(&mut buffer),
+      Frame::GoAway(go_away) => go_away.encode(&mut buffer),
+      // Clients don't send these.
+      Frame::Priority(_) | Frame::PushPromise(_) => {}
+    }
+
+    upstream.write_all(&buffer.split()).await?;
+    upstream.flush().await?;
+  }
+
+  Ok(())
+}
+
+/// Passes the server's frames to `reqwest`, and the limits of its `SETTINGS` to `relay_requests`.
+///
+/// The server compresses its header blocks for the browser's `SETTINGS_HEADER_TABLE_SIZE`, which `reqwest` never announced,
+/// so they're decoded here and re-encoded for `reqwest` without indexing. Everything else passes through as is.
+async fn relay_responses<U, C>(mut upstream: U, mut client: C, profile: &Http2Profile, limits: watch::Sender<DecodingLimits>) -> io::Result<()>
+where
+  U: AsyncRead + Unpin,
+  C: AsyncWrite + Unpin,
+{
+  // The server's table starts out with the default size, it only grows up to the browser's one with a size update.
+  let mut decoder = Decoder::with_dynamic_size(DEFAULT_HEADER_TABLE_SIZE);
+  decoder.set_max_dynamic_size(profile.setting(HEADER_TABLE_SIZE).unwrap_or(DEFAULT_HEADER_TABLE_SIZE));
+
+  let mut head = [0u8; FRAME_HEAD_LENGTH];
+  let mut block: Option<HeaderBlock> = None;
+  let mut buffer = BytesMut::new();
+
+  loop {
+    if upstream.read_exact(&mut head).await.is_err() {
+      return Ok(());
+    }
+    let length = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+    let mut payload = vec![0u8; length];
+    upstream.read_exact(&mut payload).await?;
+
+    let (kind, flags) = (head[3], head[4]);
+    match kind {
+      HEADERS | PUSH_PROMISE => {
+        let (prefix, fragment) = split_header_payload(kind, flags, payload)?;
+        let stream_id = StreamId::from(u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff);
+        block = Some(if kind == HEADERS {
+          HeaderBlock { kind: Kind::Headers, flags: flags & END_STREAM, stream_id, prefix: Vec::new(), fragment }
+        } else {
+          HeaderBlock { kind: Kind::PushPromise, flags: 0, stream_id, prefix, fragment }
+        });
+      }
+      CONTINUATION => match &mut block {
+        Some(block) => block.fragment.extend_from_slice(&payload),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected HTTP/2 CONTINUATION frame")),
+      },
+      _ => {
+        if kind == SETTINGS && flags & ACK == 0 {
+          limits.send_modify(|limits| {
+            for setting in payload.chunks_exact(6) {
+              let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+              match u16::from_be_bytes([setting[0], setting[1]]) {
+                HEADER_TABLE_SIZE => limits.header_table_size = Some(value),
+                MAX_FRAME_SIZE => limits.max_frame_size = Some(value),
+                _ => {}
+              }
+            }
+          });
+        }
+
+        client.write_all(&head).await?;
+        client.write_all(&payload).await?;
+        client.flush().await?;
+        continue;
+      }
+    }
+
+    if flags & END_HEADERS == 0 {
+      continue;
+    }
+    let Some(HeaderBlock { kind, flags, stream_id, prefix, mut fragment }) = block.take() else { continue };
+
+    let mut fields = Vec::new();
+    decoder.decode(&mut fragment, &mut fields)
+      .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("invalid HTTP/2 header block: {}", error)))?;
+
+    let mut encoded = BytesMut::new();
+    for (name, value, field_flags) in fields {
+      encode_literal(&name, &value, field_flags & Decoder::NEVER_INDEXED != 0, &mut encoded);
+    }
+    encode_block(kind, flags, stream_id, &prefix, encoded, &mut buffer);
+
+    client.write_all(&buffer.split()).await?;
+    client.flush().await?;
+  }
+}
+
+/// A header block of the server, collected until its `END_HEADERS`.
+struct HeaderBlock {
+  kind: Kind,
+  /// The flags to send it with, besides `END_HEADERS`.
+  flags: u8,
+  stream_id: StreamId,
+  /// The fields of the first frame before the block, i.e. the promised stream of a `PUSH_PROMISE`.
+  prefix: Vec<u8>,
+  fragment: Vec<u8>,
+}
+
+/// Splits the payload of a `HEADERS` or `PUSH_PROMISE` frame into the fields before the header block fragment
+/// (the priority or the promised stream) and the fragment, dropping the padding.
+fn split_header_payload(kind: u8, flags: u8, mut payload: Vec<u8>) -> io::Result<(Vec<u8>, Vec<u8>)> {
+  let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP/2 header frame");
+
+  let mut start = 0;
+  if flags & PADDED != 0 {
+    let padding = *payload.first().ok_or_else(invalid)? as usize;
+    let end = payload.len().checked_sub(padding).ok_or_else(invalid)?;
+    payload.truncate(end);
+    start = 1;
+  }
+
+  let prefix_length = match kind {
+    PUSH_PROMISE => 4,
+    _ if flags & PRIORITY != 0 => 5,
+    _ => 0,
+  };
+  if payload.len() < start + prefix_length {
+    return Err(invalid());
+  }
+
+  let fragment = payload.split_off(start + prefix_length);
+  Ok((payload.split_off(start), fragment))
+}
+
+fn encode_settings(settings: &[(u16, u32)], dst: &mut BytesMut) {
+  Head::new(Kind::Settings, 0, StreamId::zero()).encode(settings.len() * 6, dst);
+  for (id, value) in settings {
+    dst.put_u16(*id);
+    dst.put_u32(*value);
+  }
+}
+
+/// Encodes the request's `HEADERS` (and `CONTINUATION`s) with the browser's pseudo-header order, header order (see `HEADER_ORDER`)
+/// and the priority of its destination (see `DESTINATION`).
+fn encode_headers(headers: Headers, profile: &Http2Profile, encoder: &mut HeaderEncoder, dst: &mut BytesMut) -> io::Result<()> {
+  let stream_id = headers.stream_id();
+  let end_stream = headers.is_end_stream();
+  let (pseudo, fields) = headers.into_parts();
+
+  let mut header_fields = Vec::new();
+  for header in profile.pseudo_header_order {
+    let (name, value) = match header {
+      PseudoHeader::Method => (":method", pseudo.method.as_ref().map(|method| method.as_str())),
+      PseudoHeader::Authority => (":authority", pseudo.authority.as_deref()),
+      PseudoHeader::Scheme => (":scheme", pseudo.scheme.as_deref()),
+      PseudoHeader::Path => (":path", pseudo.path.as_deref()),
+    };
+    if let Some(value) = value {
+      header_fields.push((name.as_bytes().to_vec(), value.as_bytes().to_vec(), false));
+    }
+  }
+  if let Some(protocol) = &pseudo.protocol {
+    header_fields.push((b":protocol".to_vec(), protocol.as_str().as_bytes().to_vec(), false));
+  }
+
+  let private_header = |name| fields.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+  let order = private_header(HEADER_ORDER);
+  let destination = Destination::parse(private_header(DESTINATION)).unwrap_or_default();
+
+  let regular_fields: Vec<(&str, _)> = fields.iter()
+    .map(|(name, value)| (name.as_str(), value))
+    .filter(|(name, _)| !tunnel::is_private_header(name))
+    .collect();
+
+  // HTTP/2 header names are lowercase, whatever the browser's casing is on HTTP/1.1.
+  for (name, value) in tunnel::order_headers(regular_fields, order, false) {
+    header_fields.push((name.to_ascii_lowercase().into_bytes(), value.as_bytes().to_vec(), value.is_sensitive()));
+  }
+  let block = encoder.encode(header_fields)?;
+
+  let priority = profile.priority.get(destination);
+  let dependency: u32 = if priority.exclusive { 1 << 31 } else { 0 };
+  let mut prefix = dependency.to_be_bytes().to_vec();
+  prefix.push((priority.weight - 1) as u8);
+
+  let flags = if end_stream { PRIORITY | END_STREAM } else { PRIORITY };
+  encode_block(Kind::Headers, flags, stream_id, &prefix, block, dst);
+  Ok(())
+}
+
+/// HeaderEncoder HPACK-encodes the requests' header blocks the way the browsers do.
+///
+/// The fields go into the dynamic table, except for the pseudo-headers that change between requests (all but `:authority`)
+/// and the sensitive values, which are never indexed. The strings are Huffman-coded when that makes them shorter.
+struct HeaderEncoder<'a> {
+  encoder: Encoder<'a>,
+  table_size: u32,
+  /// The table size to signal at the start of the next block, after the server lowered its `SETTINGS_HEADER_TABLE_SIZE`.
+  table_size_update: Option<u32>,
+}
+
+impl HeaderEncoder<'_> {
+  fn new() -> Self {
+    HeaderEncoder { encoder: Encoder::with_dynamic_size(DEFAULT_HEADER_TABLE_SIZE), table_size: DEFAULT_HEADER_TABLE_SIZE, table_size_update: None }
+  }
+
+  /// Follows the server's `SETTINGS_HEADER_TABLE_SIZE`. Like the browsers, it never grows the table past the default size.
+  fn set_max_table_size(&mut self, size: u32) {
+    let size = size.min(DEFAULT_HEADER_TABLE_SIZE);
+    if size != self.table_size {
+      self.table_size = size;
+      self.table_size_update = Some(size);
+    }
+  }
+
+  /// Encodes the fields (the name, the value and whether it's sensitive) into a header block.
+  fn encode(&mut self, fields: Vec<(Vec<u8>, Vec<u8>, bool)>) -> io::Result<BytesMut> {
+    let error = |error| io::Error::new(io::ErrorKind::InvalidData, format!("can't encode the HTTP/2 headers: {}", error));
+
+    let mut block = Vec::new();
+    if let Some(size) = self.table_size_update.take() {
+      self.encoder.update_max_dynamic_size(size, &mut block).map_err(error)?;
+    }
+
+    for (name, value, sensitive) in fields {
+      let mut flags = Encoder::BEST_FORMAT;
+      if sensitive {
+        flags |= Encoder::NEVER_INDEXED;
+      } else if !name.starts_with(b":") || name == b":authority" {
+        flags |= Encoder::WITH_INDEXING;
+      }
+      if huffman_is_shorter(&name) {
+        flags |= Encoder::HUFFMAN_NAME;
+      }
+      if huffman_is_shorter(&value) {
+        flags |= Encoder::HUFFMAN_VALUE;
+      }
+      self.encoder.encode((name, value, flags), &mut block).map_err(error)?;
+    }
+
+    Ok(BytesMut::from(&block[..]))
+  }
+}
+
+fn huffman_is_shorter(string: &[u8]) -> bool {
+  let mut encoded = Vec::new();
+  httlib_huffman::encode(string, &mut encoded).is_ok() && encoded.len() < string.len()
+}
+
+/// Encodes a header block into a `HEADERS` or `PUSH_PROMISE` frame, followed by as many `CONTINUATION`s as it takes.
+///
+/// `prefix` goes into the first frame before the block, i.e. the priority of a `HEADERS` or the promised stream of a `PUSH_PROMISE`.
+fn encode_block(kind: Kind, flags: u8, stream_id: StreamId, prefix: &[u8], mut block: BytesMut, dst: &mut BytesMut) {
+  let first_length = block.len().min(DEFAULT_MAX_FRAME_SIZE - prefix.len());
+  let mut rest = block.split_off(first_length);
+  let flags = if rest.is_empty() { flags | END_HEADERS } else { flags };
+
+  Head::new(kind, flags, stream_id).encode(prefix.len() + block.len(), dst);
+  dst.put_slice(prefix);
+  dst.put_slice(&block);
+
+  while !rest.is_empty() {
+    let chunk = rest.split_to(rest.len().min(DEFAULT_MAX_FRAME_SIZE));
+    let flags = if rest.is_empty() { END_HEADERS } else { 0 };
+    Head::new(Kind::Continuation, flags, stream_id).encode(chunk.len(), dst);
+    dst.put_slice(&chunk);
+  }
+}
+
+/// Encodes a literal header field without indexing (or never indexed, for sensitive values), without Huffman coding.
+fn encode_literal(name: &[u8], value: &[u8], sensitive: bool, dst: &mut BytesMut) {
+  dst.put_u8(if sensitive { 0x10 } else { 0x00 });
+  encode_integer(name.len(), 7, 0, dst);
+  dst.put_slice(name);
+
+  encode_integer(value.len(), 7, 0, dst);
+  dst.put_slice(value);
+}
+
+/// Encodes an HPACK integer with an N-bit prefix (RFC 7541, 5.1) into a byte starting with `first`.
+fn encode_integer(value: usize, prefix: u8, first: u8, dst: &mut BytesMut) {
+  let max = (1usize << prefix) - 1;
+  if value < max {
+    dst.put_u8(first | value as u8);
+    return;
+  }
+
+  dst.put_u8(first | max as u8);
+  let mut value = value - max;
+  while value >= 128 {
+    dst.put_u8((value % 128) as u8 | 0x80);
+    value /= 128;
+  }
+  dst.put_u8(value as u8);
+}
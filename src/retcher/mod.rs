@@ -2,4 +2,18 @@
 /// 
 /// Note that this module is Rust-only.
 /// The Node bindings are solved higher up in the stack.
-pub mod retcher;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod retcher;
+pub mod errors;
+pub mod response;
+pub(crate) mod site;
+pub mod cookies;
+pub mod cookie_formats;
+pub mod proxy;
+pub mod proxy_pool;
+pub mod profile;
+pub(crate) mod proxy_auth;
+pub(crate) mod tunnel;
+pub(crate) mod tls;
+pub(crate) mod http1;
+pub(crate) mod http2;
\ No newline at end of file
@@ -0,0 +1,356 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use rand::Rng;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::Deserialize;
+
+use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+use super::http2::{Http2Profile, PseudoHeader};
+use super::retcher::{Browser, Destination, NavigationType};
+use super::tls::{self, TlsProfile};
+
+/// The built-in profiles, embedded in the crate. There's one for every `Browser`.
+const BUILTIN: [&str; 6] = [
+  include_str!("../../profiles/firefox.json"),
+  include_str!("../../profiles/chrome.json"),
+  include_str!("../../profiles/safari.json"),
+  include_str!("../../profiles/edge.json"),
+  include_str!("../../profiles/chrome-android.json"),
+  include_str!("../../profiles/safari-ios.json"),
+];
+
+/// The placeholders a header value can contain, filled in for every request (see `generate_headers`).
+pub(crate) const PLACEHOLDERS: [&str; 14] = [
+  "host", "userAgent", "version", "secChUa", "accept", "priority", "secFetchDest", "secFetchMode", "contentType", "contentLength", "origin",
+  "secFetchSite", "referer", "cookie",
+];
+
+/// The characters and versions Chromium builds the GREASE brand of `sec-ch-ua` from (see `sec_ch_ua`).
+const GREASE_CHARACTERS: [&str; 11] = [" ", "(", ":", "-", ".", "/", ")", ";", "=", "?", "_"];
+const GREASE_VERSIONS: [&str; 3] = ["8", "99", "24"];
+/// The orders of the GREASE, Chromium and browser brands in `sec-ch-ua`.
+const BRAND_ORDERS: [[usize; 3]; 6] = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+
+/// BrowserVersion picks the major version of the impersonated browser, see `EngineOptions::browser_version`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowserVersion {
+  /// This exact version.
+  Exact(u32),
+  /// A version picked at random from this (inclusive) range, once for every `Retcher`.
+  Range(u32, u32),
+}
+
+impl BrowserVersion {
+  /// Returns the version, picking one from the range if needed.
+  pub fn pick(&self) -> Result<u32, FetchError> {
+    match *self {
+      BrowserVersion::Exact(version) => Ok(version),
+      BrowserVersion::Range(min, max) if min <= max => Ok(rand::thread_rng().gen_range(min..=max)),
+      BrowserVersion::Range(min, max) => Err(invalid_profile(format!("Invalid browser version range {}-{}", min, max))),
+    }
+  }
+}
+
+/// BrowserProfile describes everything `Retcher` impersonates a browser with - its headers, TLS `ClientHello` and HTTP/2 connections.
+///
+/// The built-in profiles (see `BrowserProfile::builtin`) are JSON files in the `profiles` directory of the crate, and a profile in the same
+/// format can be loaded at runtime (see `BrowserProfile::from_json`) to impersonate a browser version the crate doesn't know yet.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BrowserProfile {
+  /// The profile's name, e.g. `chrome-125-linux`.
+  pub(crate) name: String,
+  /// The browser family, for the behavior that isn't described by the profile (e.g. the cookie rules or the `CONNECT` requests).
+  pub(crate) browser: Browser,
+  /// The major version of the browser, filled in for the `{version}` placeholders.
+  pub(crate) version: u32,
+  /// The `User-Agent`, possibly with a `{version}` placeholder. Without one, the profile only impersonates its `version`.
+  pub(crate) user_agent: String,
+  /// The browser's own brand in `sec-ch-ua` (e.g. `Google Chrome`), for the Chromium-based browsers' `{secChUa}` placeholder.
+  #[serde(default)]
+  pub(crate) brand: Option<String>,
+  /// The `Accept` of every request destination, for the `{accept}` placeholder.
+  pub(crate) accept: DestinationValues,
+  /// The `Priority` of every request destination, for the `{priority}` placeholder of the browsers sending one (Firefox).
+  #[serde(default)]
+  pub(crate) priority: Option<DestinationValues>,
+  /// The navigation types the browser has (e.g. only the Chromium-based ones prerender pages), all of them if not set.
+  #[serde(default)]
+  pub(crate) navigation_types: Option<Vec<NavigationType>>,
+  /// The headers, in the browser's order and casing.
+  pub(crate) headers: Vec<ProfileHeader>,
+  /// The header the custom headers (the ones not replacing any of `headers`) are sent before, where the browser puts the headers set by the page.
+  pub(crate) custom_headers_before: String,
+  pub(crate) tls: TlsProfile,
+  pub(crate) http2: Http2Profile,
+}
+
+/// ProfileHeader is a header of a `BrowserProfile`, with the conditions it's sent under.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub(crate) struct ProfileHeader {
+  pub name: String,
+  /// The value, possibly with `{placeholder}`s (see `PLACEHOLDERS`).
+  pub value: String,
+  /// Only sent over HTTPS (`true`) or plain HTTP (`false`).
+  #[serde(default)]
+  pub https: Option<bool>,
+  /// Only sent over HTTP/1.1 (`true`) or HTTP/2 (`false`).
+  #[serde(default)]
+  pub http1: Option<bool>,
+  /// Only sent for these request destinations, e.g. `Sec-Fetch-User` only for `document`.
+  #[serde(default)]
+  pub destinations: Option<Vec<Destination>>,
+  /// Only sent for these navigation types, e.g. `Cache-Control: max-age=0` only for `reload`. The subresources are `normal` ones.
+  #[serde(default)]
+  pub navigations: Option<Vec<NavigationType>>,
+  /// Not sent when the value is empty (e.g. `Referer` without a referrer), unless it's set by a custom header.
+  #[serde(default)]
+  pub optional: bool,
+}
+
+/// DestinationValues are the values of a placeholder (or of a setting) that differ between the request destinations (see `Destination`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DestinationValues<T = String> {
+  pub document: T,
+  pub iframe: T,
+  pub image: T,
+  pub script: T,
+  pub style: T,
+  pub font: T,
+  pub empty: T,
+}
+
+impl<T> DestinationValues<T> {
+  pub fn get(&self, destination: Destination) -> &T {
+    match destination {
+      Destination::Document => &self.document,
+      Destination::Iframe => &self.iframe,
+      Destination::Image => &self.image,
+      Destination::Script => &self.script,
+      Destination::Style => &self.style,
+      Destination::Font => &self.font,
+      Destination::Empty => &self.empty,
+    }
+  }
+
+  fn all(&self) -> [&T; 7] {
+    [&self.document, &self.iframe, &self.image, &self.script, &self.style, &self.font, &self.empty]
+  }
+}
+
+impl ProfileHeader {
+  /// Returns the value with the placeholders replaced by the given values.
+  ///
+  /// The value is rendered in a single pass, so a placeholder in an inserted value (e.g. a `{cookie}` in the query of the referrer) stays as it is.
+  /// Braces that aren't around a known placeholder are kept too.
+  pub fn render(&self, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(self.value.len());
+    let mut rest = self.value.as_str();
+
+    while let Some(start) = rest.find('{') {
+      rendered.push_str(&rest[..start]);
+      rest = &rest[start..];
+
+      let placeholder = rest.find('}').and_then(|end| {
+        let name = &rest[1..end];
+        values.iter().find(|(placeholder, _)| *placeholder == name).map(|(_, replacement)| (*replacement, end + 1))
+      });
+
+      match placeholder {
+        Some((replacement, length)) => {
+          rendered.push_str(replacement);
+          rest = &rest[length..];
+        }
+        None => {
+          rendered.push('{');
+          rest = &rest[1..];
+        }
+      }
+    }
+    rendered.push_str(rest);
+
+    rendered
+  }
+}
+
+fn invalid_profile(message: String) -> FetchError {
+  FetchError::new(FetchErrorKind::InvalidProfile, FetchPhase::Setup, None, message)
+}
+
+impl BrowserProfile {
+  /// Returns the built-in profile of the browser.
+  pub fn builtin(browser: &Browser) -> Arc<BrowserProfile> {
+    builtin(browser).clone()
+  }
+
+  /// Parses and validates a profile in the JSON format of the built-in ones.
+  pub fn from_json(json: &str) -> Result<BrowserProfile, FetchError> {
+    let profile: BrowserProfile = serde_json::from_str(json)
+      .map_err(|error| invalid_profile(format!("Invalid browser profile: {}", error)).with_source(error))?;
+
+    profile.validate()?;
+    Ok(profile)
+  }
+
+  /// Reads a profile from a JSON file, see `BrowserProfile::from_json`.
+  pub fn from_file(path: impl AsRef<Path>) -> Result<BrowserProfile, FetchError> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path).map_err(|error| {
+      invalid_profile(format!("Couldn't read the browser profile '{}': {}", path.display(), error)).with_source(error)
+    })?;
+
+    BrowserProfile::from_json(&json)
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn browser(&self) -> &Browser {
+    &self.browser
+  }
+
+  pub fn version(&self) -> u32 {
+    self.version
+  }
+
+  /// Returns the `User-Agent` of the profile's version.
+  pub fn user_agent(&self) -> String {
+    self.user_agent.replace("{version}", &self.version.to_string())
+  }
+
+  /// Returns the `sec-ch-ua` brand list of the profile's version, `None` if the browser isn't based on Chromium (see `brand`).
+  ///
+  /// Like Chromium, this adds a GREASE brand, and derives its name, version and position from the major version,
+  /// e.g. `"Google Chrome";v="125", "Chromium";v="125", "Not.A/Brand";v="24"` for Chrome 125.
+  pub fn sec_ch_ua(&self) -> Option<String> {
+    let brand = self.brand.as_ref()?;
+    let seed = self.version as usize;
+
+    let grease = format!(
+      "Not{}A{}Brand",
+      GREASE_CHARACTERS[seed % GREASE_CHARACTERS.len()],
+      GREASE_CHARACTERS[(seed + 1) % GREASE_CHARACTERS.len()],
+    );
+    let version = self.version.to_string();
+    let brands = [
+      (grease.as_str(), GREASE_VERSIONS[seed % GREASE_VERSIONS.len()]),
+      ("Chromium", version.as_str()),
+      (brand.as_str(), version.as_str()),
+    ];
+
+    let mut ordered = [""; 3].map(String::from);
+    for (brand, position) in brands.iter().zip(BRAND_ORDERS[seed % BRAND_ORDERS.len()]) {
+      ordered[position] = format!("\"{}\";v=\"{}\"", brand.0, brand.1);
+    }
+
+    Some(ordered.join(", "))
+  }
+
+  /// Returns the profile impersonating another major version of the browser.
+  ///
+  /// Fails if the profile's `User-Agent` has no `{version}` placeholder (as with Safari, whose versions aren't only major ones).
+  pub fn with_version(&self, version: u32) -> Result<BrowserProfile, FetchError> {
+    if version != self.version && !self.user_agent.contains("{version}") {
+      return Err(invalid_profile(format!("The browser profile '{}' can only impersonate version {}", self.name, self.version)));
+    }
+
+    // Only the headers change with the version.
+    let profile = BrowserProfile { version, ..self.clone() };
+    profile.validate_headers()?;
+    Ok(profile)
+  }
+
+  /// Checks what deserializing doesn't - the header names and values, the placeholders, and the TLS and HTTP/2 settings.
+  fn validate(&self) -> Result<(), FetchError> {
+    self.validate_headers()?;
+
+    let invalid = |reason: String| invalid_profile(format!("Invalid browser profile '{}': {}", self.name, reason));
+
+    tls::validate(&self.tls).map_err(|error| invalid(format!("invalid TLS settings ({})", error)))?;
+
+    if self.tls.alpn.is_empty() || self.tls.alpn.iter().any(|protocol| protocol.is_empty() || protocol.len() > 255) {
+      return Err(invalid("the ALPN protocols must be 1 to 255 bytes long, and there must be at least one".into()));
+    }
+
+    let pseudo_headers = [PseudoHeader::Method, PseudoHeader::Authority, PseudoHeader::Scheme, PseudoHeader::Path];
+    if !pseudo_headers.iter().all(|header| self.http2.pseudo_header_order.contains(header)) {
+      return Err(invalid("the HTTP/2 pseudo-header order must list all of method, authority, scheme and path".into()));
+    }
+
+    if let Some(priority) = self.http2.priority.all().into_iter().find(|priority| !(1..=256).contains(&priority.weight)) {
+      return Err(invalid(format!("the HTTP/2 priority weight {} isn't between 1 and 256", priority.weight)));
+    }
+
+    Ok(())
+  }
+
+  /// Checks the version, the `User-Agent` and the headers, i.e. what changes between the versions (and fingerprints) of a browser.
+  ///
+  /// Unlike the TLS settings, these are cheap to check.
+  pub(crate) fn validate_headers(&self) -> Result<(), FetchError> {
+    let invalid = |reason: String| invalid_profile(format!("Invalid browser profile '{}': {}", self.name, reason));
+
+    if self.version == 0 {
+      return Err(invalid("the version must be at least 1".into()));
+    }
+
+    if self.user_agent.replace("{version}", "").contains(['{', '}']) {
+      return Err(invalid("the user agent can only have a '{version}' placeholder".into()));
+    }
+    HeaderValue::from_str(&self.user_agent()).map_err(|_| invalid("the user agent isn't a valid header value".into()))?;
+
+    if self.headers.is_empty() {
+      return Err(invalid("it has no headers".into()));
+    }
+
+    for header in &self.headers {
+      HeaderName::from_str(&header.name).map_err(|_| invalid(format!("'{}' isn't a valid header name", header.name)))?;
+
+      let mut rest = header.value.as_str();
+      while let Some((_, placeholder)) = rest.split_once('{') {
+        let (placeholder, tail) = placeholder.split_once('}').unwrap_or((placeholder, ""));
+        if !PLACEHOLDERS.contains(&placeholder) {
+          return Err(invalid(format!("the value of '{}' has an unknown placeholder '{{{}}}'", header.name, placeholder)));
+        }
+        if placeholder == "secChUa" && self.brand.is_none() {
+          return Err(invalid(format!("the value of '{}' has a '{{secChUa}}' placeholder, but the profile has no brand", header.name)));
+        }
+        if placeholder == "priority" && self.priority.is_none() {
+          return Err(invalid(format!("the value of '{}' has a '{{priority}}' placeholder, but the profile has no priorities", header.name)));
+        }
+        rest = tail;
+      }
+
+      HeaderValue::from_str(&header.render(&PLACEHOLDERS.map(|placeholder| (placeholder, ""))))
+        .map_err(|_| invalid(format!("the value of '{}' isn't a valid header value", header.name)))?;
+    }
+
+    let destination_values = [("accept", Some(&self.accept)), ("priority", self.priority.as_ref())];
+    for (field, values) in destination_values {
+      if values.is_some_and(|values| values.all().iter().any(|value| HeaderValue::from_str(value).is_err())) {
+        return Err(invalid(format!("a '{}' value isn't a valid header value", field)));
+      }
+    }
+
+    if !self.headers.iter().any(|header| header.name.eq_ignore_ascii_case(&self.custom_headers_before)) {
+      return Err(invalid(format!("the custom headers go before '{}', which isn't one of its headers", self.custom_headers_before)));
+    }
+
+    Ok(())
+  }
+}
+
+fn builtin(browser: &Browser) -> &'static Arc<BrowserProfile> {
+  static PROFILES: OnceLock<Vec<Arc<BrowserProfile>>> = OnceLock::new();
+
+  let profiles = PROFILES.get_or_init(|| {
+    BUILTIN.iter().map(|json| Arc::new(BrowserProfile::from_json(json).expect("the built-in profiles are valid"))).collect()
+  });
+
+  profiles.iter().find(|profile| &profile.browser == browser).expect("every browser has a built-in profile")
+}
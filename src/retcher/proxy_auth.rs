@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
+use rand::Rng;
+use url::Url;
+
+/// The username and password to authenticate with a proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProxyCredentials {
+  pub username: String,
+  pub password: String,
+}
+
+impl ProxyCredentials {
+  /// Returns the (percent-decoded) credentials from the proxy URL, `None` if it has none.
+  pub(crate) fn from_url(url: &Url) -> Option<Self> {
+    if url.username().is_empty() && url.password().is_none() {
+      return None;
+    }
+
+    let decode = |value: &str| percent_decode_str(value).decode_utf8_lossy().into_owned();
+
+    Some(ProxyCredentials {
+      username: decode(url.username()),
+      password: decode(url.password().unwrap_or_default()),
+    })
+  }
+}
+
+/// An authentication challenge from a `Proxy-Authenticate` header.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Challenge {
+  /// The scheme, lowercased (e.g. `basic` or `digest`).
+  pub scheme: String,
+  /// The auth-params, with lowercased names.
+  pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+  pub(crate) fn param(&self, name: &str) -> Option<&str> {
+    self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+  }
+
+  /// Whether `Retcher` can answer this challenge - `Basic`, or `Digest` with `MD5` and the `auth` quality of protection (or none).
+  fn is_supported(&self) -> bool {
+    match self.scheme.as_str() {
+      "basic" => true,
+      "digest" => {
+        let algorithm = self.param("algorithm").unwrap_or("MD5");
+        let qop_supported = match self.param("qop") {
+          Some(qop) => qop.split(',').any(|qop| qop.trim().eq_ignore_ascii_case("auth")),
+          None => true,
+        };
+
+        self.param("nonce").is_some()
+          && qop_supported
+          && (algorithm.eq_ignore_ascii_case("MD5") || algorithm.eq_ignore_ascii_case("MD5-sess"))
+      }
+      _ => false,
+    }
+  }
+}
+
+/// Parses the challenges from `Proxy-Authenticate` header values.
+///
+/// A single value may hold several comma-separated challenges, e.g. `Basic realm="proxy", Digest realm="proxy", nonce="abc"`.
+pub(crate) fn parse_challenges<'a>(values: impl IntoIterator<Item = &'a str>) -> Vec<Challenge> {
+  let mut challenges: Vec<Challenge> = Vec::new();
+
+  for value in values {
+    let mut chars = value.chars().peekable();
+
+    loop {
+      while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+
+      let token: String = std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != ',' && *c != '=')).collect();
+      if token.is_empty() {
+        break;
+      }
+
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+      if chars.next_if_eq(&'=').is_none() {
+        challenges.push(Challenge { scheme: token.to_lowercase(), params: Vec::new() });
+        continue;
+      }
+
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+      let value = if chars.next_if_eq(&'"').is_some() {
+        let mut value = String::new();
+        while let Some(c) = chars.next() {
+          match c {
+            '"' => break,
+            '\\' => value.extend(chars.next()),
+            c => value.push(c),
+          }
+        }
+        value
+      } else {
+        std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != ',')).collect()
+      };
+
+      // Params before any scheme (or token68 credentials) can't be answered anyway.
+      if let Some(challenge) = challenges.last_mut() {
+        challenge.params.push((token.to_lowercase(), value));
+      }
+    }
+  }
+
+  challenges
+}
+
+/// Picks the challenge to answer the way browsers do - the strongest supported scheme wins.
+pub(crate) fn pick_challenge(challenges: Vec<Challenge>) -> Option<Challenge> {
+  challenges
+    .into_iter()
+    .filter(Challenge::is_supported)
+    .max_by_key(|challenge| challenge.scheme == "digest")
+}
+
+/// Returns the `Proxy-Authorization` value answering the challenge for a request with the given method and URI.
+///
+/// `nonce_count` is the number of requests sent with the challenge's nonce so far, including this one.
+fn authorization(challenge: &Challenge, credentials: &ProxyCredentials, method: &str, uri: &str, nonce_count: u32) -> String {
+  if challenge.scheme == "basic" {
+    return format!("Basic {}", BASE64_STANDARD.encode(format!("{}:{}", credentials.username, credentials.password)));
+  }
+
+  let realm = challenge.param("realm").unwrap_or_default();
+  let nonce = challenge.param("nonce").unwrap_or_default();
+  let algorithm = challenge.param("algorithm");
+  let qop = challenge.param("qop").map(|_| "auth");
+
+  // Chrome uses 16 random hex digits as the client nonce.
+  let cnonce = format!("{:016x}", rand::thread_rng().gen::<u64>());
+  let nc = format!("{:08x}", nonce_count);
+
+  let response = digest_response(&DigestParams { credentials, realm, nonce, algorithm, qop, nc: &nc, cnonce: &cnonce, method, uri });
+
+  // The same parameters in the same order as Chrome.
+  let mut header = format!(
+    "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\"",
+    quote(&credentials.username), quote(realm), quote(nonce), quote(uri),
+  );
+  if let Some(algorithm) = algorithm {
+    header.push_str(&format!(", algorithm={}", algorithm));
+  }
+  header.push_str(&format!(", response=\"{}\"", response));
+  if let Some(opaque) = challenge.param("opaque") {
+    header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+  }
+  if let Some(qop) = qop {
+    header.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+  }
+
+  header
+}
+
+/// The inputs of a `Digest` response (RFC 7616).
+pub(crate) struct DigestParams<'a> {
+  pub credentials: &'a ProxyCredentials,
+  pub realm: &'a str,
+  pub nonce: &'a str,
+  pub algorithm: Option<&'a str>,
+  pub qop: Option<&'a str>,
+  pub nc: &'a str,
+  pub cnonce: &'a str,
+  pub method: &'a str,
+  pub uri: &'a str,
+}
+
+/// Computes the `response` parameter of a `Digest` authorization.
+pub(crate) fn digest_response(params: &DigestParams) -> String {
+  let DigestParams { credentials, realm, nonce, algorithm, qop, nc, cnonce, method, uri } = params;
+
+  let mut ha1 = md5_hex(format!("{}:{}:{}", credentials.username, realm, credentials.password).as_bytes());
+  if algorithm.is_some_and(|algorithm| algorithm.eq_ignore_ascii_case("MD5-sess")) {
+    ha1 = md5_hex(format!("{}:{}:{}", ha1, nonce, cnonce).as_bytes());
+  }
+  let ha2 = md5_hex(format!("{}:{}", method, uri).as_bytes());
+
+  match qop {
+    Some(qop) => md5_hex(format!("{}:{}:{}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2).as_bytes()),
+    None => md5_hex(format!("{}:{}:{}", ha1, nonce, ha2).as_bytes()),
+  }
+}
+
+fn quote(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// A challenge answered before, with the number of times its nonce has been used.
+#[derive(Debug)]
+struct CachedChallenge {
+  challenge: Challenge,
+  nonce_count: u32,
+}
+
+/// The key of a proxy in the auth cache - its origin and the user authenticating with it.
+pub(crate) fn cache_key(proxy: &Url, credentials: &ProxyCredentials) -> String {
+  format!("{} {}", proxy.origin().ascii_serialization(), credentials.username)
+}
+
+/// AuthCache holds the challenges of the proxies a `Retcher` has authenticated with (by their `cache_key`),
+/// for both its forwarded requests and the relay's `CONNECT` requests.
+#[derive(Debug, Default)]
+pub(crate) struct AuthCache {
+  challenges: Mutex<HashMap<String, CachedChallenge>>,
+}
+
+impl AuthCache {
+  fn challenges(&self) -> MutexGuard<'_, HashMap<String, CachedChallenge>> {
+    self.challenges.lock().unwrap_or_else(|error| error.into_inner())
+  }
+
+  /// Returns the `Proxy-Authorization` value to send preemptively, if the proxy has challenged us before.
+  ///
+  /// Like browsers, only the first request to a proxy waits for the `407` challenge.
+  pub(crate) fn cached_authorization(&self, key: &str, credentials: &ProxyCredentials, method: &str, uri: &str) -> Option<String> {
+    let mut challenges = self.challenges();
+    let cached = challenges.get_mut(key)?;
+    cached.nonce_count += 1;
+
+    Some(authorization(&cached.challenge, credentials, method, uri, cached.nonce_count))
+  }
+
+  /// Answers the challenges of a `407 Proxy Authentication Required` response and remembers the one answered for the next requests.
+  ///
+  /// Returns `None` when there's no challenge `Retcher` supports.
+  pub(crate) fn answer_challenge<'a>(
+    &self,
+    key: &str,
+    credentials: &ProxyCredentials,
+    proxy_authenticate: impl IntoIterator<Item = &'a str>,
+    method: &str,
+    uri: &str,
+  ) -> Option<String> {
+    let challenge = pick_challenge(parse_challenges(proxy_authenticate))?;
+    let authorization = authorization(&challenge, credentials, method, uri, 1);
+
+    self.challenges().insert(key.to_string(), CachedChallenge { challenge, nonce_count: 1 });
+
+    Some(authorization)
+  }
+}
+
+/// Computes the MD5 digest (RFC 1321) of the data as lowercase hex, as used by the `Digest` scheme.
+pub(crate) fn md5_hex(data: &[u8]) -> String {
+  Md5::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
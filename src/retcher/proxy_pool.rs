@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use url::Url;
+
+use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+use super::proxy::ProxyOptions;
+
+/// How long a proxy is skipped after a failure, by default.
+pub const DEFAULT_PROXY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// The statuses that mean the proxy got blocked (`403 Forbidden` and `429 Too Many Requests`), by default.
+pub const DEFAULT_BLOCK_STATUSES: [u16; 2] = [403, 429];
+
+/// ProxyRotation is the way a `ProxyPool` picks a proxy for each request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProxyRotation {
+  /// Uses the proxies one after another.
+  #[default]
+  RoundRobin,
+  /// Uses a random proxy for every request.
+  Random,
+  /// Uses the same proxy for all the requests to one host.
+  StickyPerHost,
+  /// Uses the same proxy for all the requests with the same `FetchOptions::session`.
+  /// Requests without a session are rotated round-robin.
+  StickyPerSession,
+}
+
+/// ProxyPoolOptions configures a `ProxyPool`.
+#[derive(Debug, Clone)]
+pub struct ProxyPoolOptions {
+  pub proxies: Vec<ProxyOptions>,
+  pub rotation: ProxyRotation,
+  /// How long a failed proxy is skipped. Defaults to `DEFAULT_PROXY_COOLDOWN`.
+  pub cooldown: Option<Duration>,
+  /// The response statuses that mark the proxy as blocked. Defaults to `DEFAULT_BLOCK_STATUSES`.
+  pub block_statuses: Option<Vec<u16>>,
+}
+
+impl ProxyPoolOptions {
+  /// Creates `ProxyPoolOptions` rotating the proxies round-robin with the default health tracking.
+  pub fn new(proxies: Vec<ProxyOptions>) -> Self {
+    ProxyPoolOptions {
+      proxies,
+      rotation: ProxyRotation::default(),
+      cooldown: None,
+      block_statuses: None,
+    }
+  }
+}
+
+/// ProxyStatus is the health of one proxy in a `ProxyPool`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyStatus {
+  /// The proxy URL, without the credentials.
+  pub url: String,
+  /// Whether the proxy is used for new requests, i.e. it isn't cooling down after a failure.
+  pub healthy: bool,
+  /// The number of failures since the proxy last served a request successfully.
+  pub failures: u32,
+  /// How long the proxy is still skipped for.
+  pub cooldown_remaining: Duration,
+}
+
+#[derive(Default)]
+struct ProxyHealth {
+  failures: u32,
+  bad_until: Option<Instant>,
+}
+
+impl ProxyHealth {
+  fn is_healthy(&self, now: Instant) -> bool {
+    self.bad_until.is_none_or(|bad_until| bad_until <= now)
+  }
+}
+
+#[derive(Default)]
+struct PoolState {
+  next: usize,
+  health: Vec<ProxyHealth>,
+  /// The proxy assigned to each host (`StickyPerHost`) or session (`StickyPerSession`).
+  sticky: HashMap<String, usize>,
+}
+
+/// ProxyPool spreads the requests of a `Retcher` over several proxies and keeps track of their health.
+///
+/// A proxy is marked bad (and skipped for the cooldown) after a connection error or a block-like response status.
+/// When all the proxies are cooling down, the one that failed the longest time ago is used.
+pub struct ProxyPool {
+  proxies: Vec<ProxyOptions>,
+  rotation: ProxyRotation,
+  cooldown: Duration,
+  block_statuses: Vec<u16>,
+  state: Mutex<PoolState>,
+}
+
+impl ProxyPool {
+  /// Creates a new `ProxyPool` with all the proxies healthy.
+  pub fn new(options: ProxyPoolOptions) -> Self {
+    ProxyPool {
+      state: Mutex::new(PoolState {
+        health: options.proxies.iter().map(|_| ProxyHealth::default()).collect(),
+        ..PoolState::default()
+      }),
+      proxies: options.proxies,
+      rotation: options.rotation,
+      cooldown: options.cooldown.unwrap_or(DEFAULT_PROXY_COOLDOWN),
+      block_statuses: options.block_statuses.unwrap_or_else(|| DEFAULT_BLOCK_STATUSES.to_vec()),
+    }
+  }
+
+  fn state(&self) -> MutexGuard<'_, PoolState> {
+    self.state.lock().unwrap_or_else(|error| error.into_inner())
+  }
+
+  /// Returns the health of all the proxies, in the order they were given.
+  pub fn status(&self) -> Vec<ProxyStatus> {
+    let state = self.state();
+    let now = Instant::now();
+
+    self.proxies.iter().zip(state.health.iter()).map(|(proxy, health)| ProxyStatus {
+      url: proxy.display_url(),
+      healthy: health.is_healthy(now),
+      failures: health.failures,
+      cooldown_remaining: health.bad_until.map(|bad_until| bad_until.saturating_duration_since(now)).unwrap_or_default(),
+    }).collect()
+  }
+
+  /// Picks the proxy for a request to `url` and returns its index with the proxy.
+  pub(crate) fn pick(&self, url: &Url, session: Option<&str>) -> Result<(usize, ProxyOptions), FetchError> {
+    if self.proxies.is_empty() {
+      return Err(FetchError::new(
+        FetchErrorKind::Proxy,
+        FetchPhase::Setup,
+        Some(url.to_string()),
+        "The proxy pool is empty",
+      ));
+    }
+
+    let mut state = self.state();
+    let now = Instant::now();
+
+    let sticky_key = match self.rotation {
+      ProxyRotation::StickyPerHost => url.host_str().map(|host| host.to_lowercase()),
+      ProxyRotation::StickyPerSession => session.map(str::to_string),
+      _ => None,
+    };
+
+    if let Some(&index) = sticky_key.as_ref().and_then(|key| state.sticky.get(key)) {
+      if state.health[index].is_healthy(now) {
+        return Ok((index, self.proxies[index].clone()));
+      }
+    }
+
+    let healthy: Vec<usize> = (0..self.proxies.len()).filter(|&index| state.health[index].is_healthy(now)).collect();
+
+    let index = if healthy.is_empty() {
+      (0..self.proxies.len()).min_by_key(|&index| state.health[index].bad_until).unwrap_or_default()
+    } else if self.rotation == ProxyRotation::Random {
+      healthy[rand::thread_rng().gen_range(0..healthy.len())]
+    } else {
+      // The first healthy proxy from the rotation's current position.
+      let next = state.next;
+      let index = *healthy.iter().find(|&&index| index >= next).unwrap_or(&healthy[0]);
+      state.next = (index + 1) % self.proxies.len();
+      index
+    };
+
+    if let Some(key) = sticky_key {
+      state.sticky.insert(key, index);
+    }
+
+    Ok((index, self.proxies[index].clone()))
+  }
+
+  /// Records the outcome of a request sent through the proxy with the given index.
+  pub(crate) fn report(&self, index: usize, result: Result<u16, &FetchError>) {
+    let failed = match result {
+      Ok(status) => self.block_statuses.contains(&status),
+      Err(error) => is_proxy_failure(error),
+    };
+
+    let mut state = self.state();
+    let Some(health) = state.health.get_mut(index) else { return };
+
+    if failed {
+      health.failures += 1;
+      health.bad_until = Some(Instant::now() + self.cooldown);
+    } else if result.is_ok() {
+      *health = ProxyHealth::default();
+    }
+  }
+}
+
+/// Whether the error means the proxy (rather than the request or the target) is at fault.
+fn is_proxy_failure(error: &FetchError) -> bool {
+  error.phase != FetchPhase::Setup && matches!(
+    error.kind,
+    FetchErrorKind::Proxy | FetchErrorKind::ConnectionRefused | FetchErrorKind::Connection | FetchErrorKind::Timeout,
+  )
+}
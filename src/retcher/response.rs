@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use encoding_rs::{Encoding, UTF_8};
+use futures_util::{Stream, StreamExt};
+use mime::Mime;
+use serde::de::DeserializeOwned;
+
+use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+
+/// ResponseBody is the streamed body of a `FetchResponse`.
+///
+/// It yields the (already decompressed) body in chunks, as they arrive from the network.
+pub struct ResponseBody {
+  inner: Pin<Box<dyn Stream<Item = Result<Bytes, FetchError>> + Send>>,
+}
+
+impl ResponseBody {
+  pub(crate) fn from_reqwest(response: reqwest::Response) -> Self {
+    let stream = futures_util::stream::unfold(Some(response), |response| async move {
+      let mut response = response?;
+
+      match response.chunk().await {
+        Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+        Ok(None) => None,
+        // The stream ends after the first error.
+        Err(error) => Some((Err(FetchError::from_reqwest(error, FetchPhase::Response)), None)),
+      }
+    });
+
+    ResponseBody {
+      inner: Box::pin(stream),
+    }
+  }
+}
+
+impl Stream for ResponseBody {
+  type Item = Result<Bytes, FetchError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.inner.as_mut().poll_next(cx)
+  }
+}
+
+/// RedirectHop is one redirect followed on the way to the final `FetchResponse`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectHop {
+  /// The URL that responded with the redirect.
+  pub url: String,
+  /// The redirect status code (`301`, `302`, `303`, `307` or `308`).
+  pub status: u16,
+  /// The absolute URL the redirect pointed to.
+  pub location: String,
+  /// The `Set-Cookie` headers of the redirect response, in the order they were received.
+  pub set_cookies: Vec<String>,
+}
+
+enum BodyState {
+  /// Nobody has touched the body yet.
+  Unused(ResponseBody),
+  /// The body is being read chunk by chunk with `FetchResponse::chunk`.
+  Streaming(ResponseBody),
+  /// The body has been read to the end chunk by chunk.
+  Drained,
+  /// The body has been read to the end or handed out with `FetchResponse::body`.
+  Consumed,
+}
+
+/// FetchResponse is the response to a `retch` call.
+///
+/// The body is not read when the response arrives.
+/// Like in the `fetch` API, it can be consumed only once - either as a stream or all at once.
+pub struct FetchResponse {
+  body: BodyState,
+  /// The response headers by lowercase name, the values of a repeated header combined with `, ` (like in `fetch`'s `Headers`).
+  pub headers: HashMap<String, String>,
+  /// The values of the `Set-Cookie` headers, one per header (like `Headers::getSetCookie` in `fetch`).
+  pub set_cookies: Vec<String>,
+  pub ok: bool,
+  /// Whether at least one redirect was followed to get this response.
+  pub redirected: bool,
+  /// The redirects followed to get this response, in order.
+  pub redirects: Vec<RedirectHop>,
+  pub status: u16,
+  pub status_text: String,
+  pub r#type: String,
+  pub url: String,
+  /// The proxy (without the credentials) the response came through, `None` for a direct connection.
+  pub proxy: Option<String>,
+}
+
+impl FetchResponse {
+  pub(crate) fn new(body: ResponseBody, headers: HashMap<String, String>, status: u16, status_text: String, url: String) -> Self {
+    FetchResponse {
+      body: BodyState::Unused(body),
+      headers,
+      set_cookies: Vec::new(),
+      ok: (200..300).contains(&status),
+      redirected: false,
+      redirects: Vec::new(),
+      status,
+      status_text,
+      r#type: "basic".to_string(),
+      url,
+      proxy: None,
+    }
+  }
+
+  /// Returns whether the body has already been (at least partially) read.
+  pub fn body_used(&self) -> bool {
+    !matches!(self.body, BodyState::Unused(_))
+  }
+
+  fn body_used_error(&self) -> FetchError {
+    FetchError::new(
+      FetchErrorKind::BodyUsed,
+      FetchPhase::Response,
+      Some(self.url.clone()),
+      "The response body has already been used",
+    )
+  }
+
+  /// Takes the body as a `Stream` of chunks.
+  ///
+  /// Fails with `FetchErrorKind::BodyUsed` if the body has already been read.
+  pub fn body(&mut self) -> Result<ResponseBody, FetchError> {
+    match std::mem::replace(&mut self.body, BodyState::Consumed) {
+      BodyState::Unused(body) => Ok(body),
+      state => {
+        self.body = state;
+        Err(self.body_used_error())
+      }
+    }
+  }
+
+  /// Reads the next chunk of the body, `None` once the body has been read to the end.
+  ///
+  /// Fails with `FetchErrorKind::BodyUsed` if the body has been consumed in any other way.
+  pub async fn chunk(&mut self) -> Result<Option<Bytes>, FetchError> {
+    if let BodyState::Unused(_) = self.body {
+      if let BodyState::Unused(body) = std::mem::replace(&mut self.body, BodyState::Consumed) {
+        self.body = BodyState::Streaming(body);
+      }
+    }
+
+    let body = match &mut self.body {
+      BodyState::Streaming(body) => body,
+      BodyState::Drained => return Ok(None),
+      _ => return Err(self.body_used_error()),
+    };
+
+    match body.next().await {
+      Some(Ok(chunk)) => Ok(Some(chunk)),
+      Some(Err(error)) => {
+        self.body = BodyState::Consumed;
+        Err(error)
+      }
+      None => {
+        self.body = BodyState::Drained;
+        Ok(None)
+      }
+    }
+  }
+
+  /// Reads the whole body into memory.
+  ///
+  /// Fails with `FetchErrorKind::BodyUsed` if the body has already been read.
+  pub async fn bytes(&mut self) -> Result<Vec<u8>, FetchError> {
+    let mut body = self.body()?;
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.next().await {
+      bytes.extend_from_slice(&chunk?);
+    }
+
+    Ok(bytes)
+  }
+
+  /// Returns the `Content-Type` of the response, if any.
+  pub fn content_type(&self) -> Option<&str> {
+    self.headers.get("content-type").map(|content_type| content_type.as_str())
+  }
+
+  /// Reads the whole body as text.
+  ///
+  /// The body is decoded using the charset from the `Content-Type` header, UTF-8 is used if there is none (or it's unknown).
+  /// Fails with `FetchErrorKind::BodyUsed` if the body has already been read.
+  pub async fn text(&mut self) -> Result<String, FetchError> {
+    let encoding = self.content_type()
+      .and_then(|content_type| content_type.parse::<Mime>().ok())
+      .and_then(|mime| mime.get_param(mime::CHARSET).and_then(|charset| Encoding::for_label(charset.as_str().as_bytes())))
+      .unwrap_or(UTF_8);
+
+    let bytes = self.bytes().await?;
+
+    // `decode` also sniffs the BOM, which takes precedence over the declared charset (like in browsers).
+    let (text, _, _) = encoding.decode(&bytes);
+    Ok(text.into_owned())
+  }
+
+  /// Reads the whole body and parses it as JSON.
+  ///
+  /// Fails with `FetchErrorKind::InvalidJson` if the body isn't valid JSON
+  /// and with `FetchErrorKind::BodyUsed` if the body has already been read.
+  pub async fn json<T: DeserializeOwned>(&mut self) -> Result<T, FetchError> {
+    let bytes = self.bytes().await?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+
+    serde_json::from_slice(bytes).map_err(|error| FetchError::new(
+      FetchErrorKind::InvalidJson,
+      FetchPhase::Response,
+      Some(self.url.clone()),
+      format!("The response body is not valid JSON: {}", error),
+    ).with_source(error))
+  }
+}
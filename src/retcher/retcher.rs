@@ -1,77 +1,455 @@
 use std::collections::HashMap;
-use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::header_generator::header_generator::HeaderGeneratorOptions;
+use indexmap::IndexMap;
+
+use crate::header_generator::header_generator::{GeneratedHeaders, HeaderGeneratorOptions};
 
 use super::super::header_generator::header_generator::generate_headers;
 
-use reqwest::header::HeaderName;
+pub use crate::header_generator::fingerprint_generator::{generate_fingerprint, Device, Fingerprint, FingerprintOptions, OperatingSystem};
+pub use super::cookie_formats::CookieFormat;
+pub use super::cookies::{Cookie, CookieJar, SameSite};
+pub use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+pub use super::profile::{BrowserProfile, BrowserVersion};
+pub use super::proxy::ProxyOptions;
+pub use super::proxy_pool::{ProxyPool, ProxyPoolOptions, ProxyRotation, ProxyStatus};
+pub use super::response::{FetchResponse, RedirectHop, ResponseBody};
+use super::http2;
+use super::proxy::reqwest_proxies;
+use super::proxy_auth::AuthCache;
+use super::tunnel::{self, RelayRequest};
+use super::site;
+
+use reqwest::header::{HeaderValue, LOCATION, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, SET_COOKIE};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
 use url::Url;
 
-#[derive(PartialEq, Debug, Clone)]
+/// The maximum number of redirects followed by default, the same as in Chrome and Firefox.
+pub const DEFAULT_MAX_REDIRECTS: usize = 20;
+
+/// How long opening a connection may take by default (see `EngineOptions::connect_timeout`).
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many times a request is sent at most when a proxy challenges it with `407 Proxy Authentication Required`.
+const MAX_PROXY_AUTH_ATTEMPTS: usize = 3;
+
+/// How many `reqwest` clients (each with its connection pool) a `Retcher` keeps at most. The least recently used one is dropped first.
+pub(crate) const MAX_ENGINES: usize = 16;
+
+/// The browsers `Retcher` can impersonate, each with its built-in profile (see `BrowserProfile::builtin`).
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum Browser {
+  /// Firefox on Linux.
   Firefox,
+  /// Chrome on Linux.
   Chrome,
+  /// Safari on macOS.
+  Safari,
+  /// Edge on Windows.
+  Edge,
+  /// Chrome on Android.
+  ChromeAndroid,
+  /// Safari on iOS.
+  SafariIos,
+}
+
+impl Browser {
+  /// Returns whether the browser is based on Chromium, and so behaves like Chrome where the profiles don't describe it (e.g. the cookie rules).
+  pub fn is_chromium(&self) -> bool {
+    matches!(self, Browser::Chrome | Browser::Edge | Browser::ChromeAndroid)
+  }
 }
 
 /// EngineOptions is a struct holding additional options for the engine.
 /// 
 /// These are used globally for all requests made with the given `Retcher` instance.
+#[derive(Default)]
 pub struct EngineOptions{
   /// An optional `Browser` enum that holds the browser to impersonate.
   pub browser: Option<Browser>,
+  /// An optional browser profile replacing the built-in one of `browser` (whose browser it impersonates then), see `BrowserProfile`.
+  pub profile: Option<BrowserProfile>,
+  /// Optional constraints for a random browser fingerprint (see `generate_fingerprint`) to impersonate instead of `browser`.
+  /// It's generated once for the instance. An explicit `profile` takes precedence.
+  pub fingerprint: Option<FingerprintOptions>,
+  /// An optional major version of the browser to impersonate instead of the profile's one, or a range to pick it from.
+  ///
+  /// The `User-Agent`, `sec-ch-ua` and the other headers with the version are consistent with it (see `BrowserProfile::with_version`).
+  pub browser_version: Option<BrowserVersion>,
   /// An optional `bool` that holds whether to ignore TLS errors.
   pub ignore_tls_errors: Option<bool>,
+  /// An optional limit on how long opening a connection (through the proxy, TLS handshake included) may take,
+  /// `DEFAULT_CONNECT_TIMEOUT` by default. Requests exceeding it fail with `FetchErrorKind::Timeout`.
+  pub connect_timeout: Option<Duration>,
+  /// An optional proxy to send all the requests through.
+  pub proxy: Option<ProxyOptions>,
+  /// An optional pool of proxies to spread the requests over. It takes precedence over `proxy`.
+  pub proxy_pool: Option<ProxyPoolOptions>,
 }
 
-/// FetchOptions is a struct holding additional options for the fetch request.
-pub struct FetchOptions{
-  /// A `HashMap` that holds custom HTTP headers. These are added to the default headers and should never overwrite them.
-  pub headers: HashMap<String, String>
+/// RequestBody is the payload sent with a request.
+/// 
+/// The variant decides the `Content-Type` header the browser would send along with the body.
+#[derive(Debug, Clone)]
+pub enum RequestBody {
+  /// A plain-text body, sent as `text/plain;charset=UTF-8`.
+  Text(String),
+  /// Form fields, serialized and sent as `application/x-www-form-urlencoded` (like a submitted HTML form).
+  Form(Vec<(String, String)>),
+  /// A JSON value, serialized and sent as `application/json`.
+  Json(serde_json::Value),
+  /// Raw bytes. Browsers don't attach any `Content-Type` to these.
+  Bytes(Vec<u8>),
 }
 
-pub struct FetchResponse {
-  pub body: Option<Vec<u8>>,
-  pub body_used: bool,
-  pub headers: HashMap<String, String>,
-  pub ok: bool,
-  pub redirected: bool,
-  pub status: u16,
-  pub status_text: String,
-  pub r#type: String,
-  pub url: String,
+impl RequestBody {
+  /// Returns the `Content-Type` header value a browser would use for this body.
+  fn content_type(&self) -> Option<&'static str> {
+    match self {
+      RequestBody::Text(_) => Some("text/plain;charset=UTF-8"),
+      RequestBody::Form(_) => Some("application/x-www-form-urlencoded"),
+      RequestBody::Json(_) => Some("application/json"),
+      RequestBody::Bytes(_) => None,
+    }
+  }
+
+  fn into_bytes(self) -> Vec<u8> {
+    match self {
+      RequestBody::Text(text) => text.into_bytes(),
+      RequestBody::Form(fields) => url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(fields)
+        .finish()
+        .into_bytes(),
+      RequestBody::Json(value) => value.to_string().into_bytes(),
+      RequestBody::Bytes(bytes) => bytes,
+    }
+  }
 }
 
-#[derive(Debug, Clone)]
-pub struct FetchError {
-  message: String,
+/// RedirectMode tells `retch` what to do when the server responds with a redirect, like `fetch`'s `redirect` option.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum RedirectMode {
+  /// Follow the redirects (up to `FetchOptions::max_redirects` of them) and return the final response.
+  #[default]
+  Follow,
+  /// Return the redirect response itself.
+  /// 
+  /// Unlike in browsers, the response is not opaque - its status, headers and body are all available.
+  Manual,
+  /// Fail with `FetchErrorKind::Redirect` when the server responds with a redirect.
+  Error,
+}
+
+/// Credentials tells `retch` when to use the `Retcher`'s cookie jar, like `fetch`'s `credentials` option.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Credentials {
+  /// Never send or store cookies.
+  Omit,
+  /// Send and store cookies only for the origin of the requested URL (i.e. not after cross-origin redirects).
+  SameOrigin,
+  /// Always send and store cookies. This is what browsers do for navigations.
+  #[default]
+  Include,
+}
+
+/// Destination is what the requested resource is for, like `fetch`'s `RequestDestination`.
+///
+/// It decides the `Accept`, `Sec-Fetch-Dest` and the other headers that differ between navigations and the page's subresources.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Destination {
+  /// A top-level navigation, e.g. a link clicked by the user.
+  #[default]
+  Document,
+  /// A navigation of an `<iframe>`.
+  Iframe,
+  /// An `<img>`.
+  Image,
+  /// A `<script>`.
+  Script,
+  /// A stylesheet, i.e. `<link rel="stylesheet">`.
+  Style,
+  /// A web font loaded by `@font-face`.
+  Font,
+  /// A `fetch` or `XMLHttpRequest` call.
+  Empty,
+}
+
+impl Destination {
+  /// Returns the `Sec-Fetch-Dest` value.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Destination::Document => "document",
+      Destination::Iframe => "iframe",
+      Destination::Image => "image",
+      Destination::Script => "script",
+      Destination::Style => "style",
+      Destination::Font => "font",
+      Destination::Empty => "empty",
+    }
+  }
+
+  /// Parses a `Sec-Fetch-Dest` value of one of the destinations.
+  pub(crate) fn parse(value: &str) -> Option<Destination> {
+    let destinations = [
+      Destination::Document, Destination::Iframe, Destination::Image, Destination::Script, Destination::Style, Destination::Font, Destination::Empty,
+    ];
+    destinations.into_iter().find(|destination| destination.as_str() == value)
+  }
+
+  /// Returns whether requests for the destination are navigations (and not subresources of a page).
+  pub fn is_navigation(&self) -> bool {
+    matches!(self, Destination::Document | Destination::Iframe)
+  }
+
+  /// Returns the mode the browsers request the destination with, e.g. `no-cors` for `<img>` and `cors` for fonts and `fetch`.
+  pub fn default_mode(&self) -> RequestMode {
+    match self {
+      Destination::Document | Destination::Iframe => RequestMode::Navigate,
+      Destination::Image | Destination::Script | Destination::Style => RequestMode::NoCors,
+      Destination::Font | Destination::Empty => RequestMode::Cors,
+    }
+  }
+}
+
+/// NavigationType is how the user got to the page, for the headers only some navigations have (see `FetchOptions::navigation_type`).
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NavigationType {
+  /// A link clicked, a form submitted or a URL typed in.
+  #[default]
+  Normal,
+  /// A reload (F5), revalidating the cached page (`Cache-Control: max-age=0`).
+  Reload,
+  /// A hard reload (Ctrl+Shift+R), bypassing the cache (`Pragma: no-cache` and `Cache-Control: no-cache`).
+  HardReload,
+  /// Going back or forward in the history to a page that isn't cached. The browsers send the same headers as for `Normal`.
+  BackForward,
+  /// A prefetch of a likely next page, e.g. when the URL is in the address bar, but not submitted yet (`Sec-Purpose: prefetch`).
+  Prefetch,
+  /// A prerender of a likely next page (`Sec-Purpose: prefetch;prerender`), only in the Chromium-based browsers.
+  Prerender,
+}
+
+impl NavigationType {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      NavigationType::Normal => "normal",
+      NavigationType::Reload => "reload",
+      NavigationType::HardReload => "hard-reload",
+      NavigationType::BackForward => "back-forward",
+      NavigationType::Prefetch => "prefetch",
+      NavigationType::Prerender => "prerender",
+    }
+  }
+}
+
+/// ReferrerPolicy decides how much of the referrer the `Referer` (and the `Origin` of non-CORS requests) reveals, like `fetch`'s `referrerPolicy`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ReferrerPolicy {
+  /// No `Referer` at all.
+  NoReferrer,
+  /// The full URL, except on an HTTPS -> HTTP downgrade.
+  NoReferrerWhenDowngrade,
+  /// Only the origin.
+  Origin,
+  /// The full URL for the same-origin requests, the origin for the others.
+  OriginWhenCrossOrigin,
+  /// The full URL for the same-origin requests, nothing for the others.
+  SameOrigin,
+  /// Only the origin, except on an HTTPS -> HTTP downgrade.
+  StrictOrigin,
+  /// The full URL for the same-origin requests, the origin for the others, except on an HTTPS -> HTTP downgrade.
+  /// This is the default in all the browsers.
+  #[default]
+  StrictOriginWhenCrossOrigin,
+  /// Always the full URL.
+  UnsafeUrl,
+}
+
+/// RequestMode is the CORS mode of the request, like `fetch`'s `RequestMode`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RequestMode {
+  /// A navigation, only for the `Document` and `Iframe` destinations.
+  Navigate,
+  /// Fails for the cross-origin URLs (including the redirects to them).
+  SameOrigin,
+  /// A subresource request without CORS, e.g. a classic `<script>` or an `<img>`.
+  NoCors,
+  /// A CORS request, with an `Origin` when it's cross-origin.
+  Cors,
+}
+
+impl RequestMode {
+  /// Returns the `Sec-Fetch-Mode` value.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      RequestMode::Navigate => "navigate",
+      RequestMode::SameOrigin => "same-origin",
+      RequestMode::NoCors => "no-cors",
+      RequestMode::Cors => "cors",
+    }
+  }
+}
+
+/// FetchOptions is a struct holding additional options for the fetch request.
+#[derive(Default)]
+pub struct FetchOptions{
+  /// The HTTP method of the request. Defaults to `GET`.
+  pub method: Method,
+  /// Custom HTTP headers, added to the default headers (or replacing the ones with the same name).
+  ///
+  /// The default headers keep the browser's order, the new ones are sent in this map's order where the browser puts the headers set by the page.
+  pub headers: IndexMap<String, String>,
+  /// An optional `RequestBody` to send with the request.
+  pub body: Option<RequestBody>,
+  /// What to do with redirects. Defaults to `RedirectMode::Follow`.
+  pub redirect: RedirectMode,
+  /// The maximum number of redirects to follow, `DEFAULT_MAX_REDIRECTS` if not set.
+  pub max_redirects: Option<usize>,
+  /// When to send and store cookies. Defaults to `Credentials::Include`.
+  pub credentials: Credentials,
+  /// What the requested resource is for. Defaults to `Destination::Document`, i.e. a navigation.
+  pub destination: Destination,
+  /// The CORS mode of the request, `Destination::default_mode` if not set. Only the navigations use `RequestMode::Navigate`.
+  pub mode: Option<RequestMode>,
+  /// How the user got to the page, e.g. by reloading it. Only the `Destination::Document` requests can be other than `NavigationType::Normal`.
+  pub navigation_type: NavigationType,
+  /// The URL of the page making the request, e.g. the one with the clicked link. It's the request's initiator - it decides `Sec-Fetch-Site`,
+  /// the `Referer` (see `referrer_policy`), the `Origin` and the `SameSite` cookies sent.
+  ///
+  /// Without it, navigations are the user's own ones (e.g. a typed-in URL) and subresources come from a page of the URL's origin.
+  /// A custom `Referer` header is only used (as is for the first request, recomputed for the redirects) without the `referrer`.
+  pub referrer: Option<String>,
+  /// How much of the `referrer` is revealed. Defaults to `ReferrerPolicy::StrictOriginWhenCrossOrigin`, like in the browsers.
+  pub referrer_policy: ReferrerPolicy,
+  /// A proxy to send this request through instead of `EngineOptions::proxy` (or the proxy pool).
+  pub proxy: Option<ProxyOptions>,
+  /// The session this request belongs to. Requests of one session use the same proxy with `ProxyRotation::StickyPerSession`.
+  pub session: Option<String>,
 }
 
 /// Retcher is the main struct used to make (impersonated) requests.
 /// 
 /// It uses `reqwest::Client` to make requests and holds info about the impersonated browser.
 pub struct Retcher {
-  engine: reqwest::Client,
+  /// The `reqwest` clients, one per proxy (as a client is bound to its proxy), created when first needed.
+  /// They are kept in the order they were last used, up to `MAX_ENGINES` of them.
+  engines: Mutex<IndexMap<Option<ProxyOptions>, reqwest::Client>>,
+  ignore_tls_errors: bool,
+  connect_timeout: Duration,
+  proxy: Option<ProxyOptions>,
+  proxy_pool: Option<ProxyPool>,
+  cookie_jar: CookieJar,
+  /// The challenges of the proxies the requests were authenticated with, shared with the relay (see `TunnelConfig`).
+  auth_cache: Arc<AuthCache>,
   /// A `Browser` enum that holds the browser to impersonate.
   pub browser: Browser,
+  /// The profile of the impersonated browser, or why it couldn't be made for `EngineOptions::browser_version`.
+  profile: Result<Arc<BrowserProfile>, FetchError>,
 }
 
 impl Retcher {
   /// Creates a new `Retcher` instance with the given `EngineOptions`.
+  /// 
+  /// An invalid proxy in the options is reported by the first `retch` call using it,
+  /// a browser version the profile can't impersonate (or a fingerprint that can't be generated) by every `retch` call (and `Retcher::profile`).
   pub fn new(options: EngineOptions) -> Self {
+    let browser = options.browser.unwrap_or(Browser::Firefox);
+    let profile = match (options.profile, options.fingerprint) {
+      (Some(profile), _) => Ok(Arc::new(profile)),
+      (None, Some(fingerprint)) => generate_fingerprint(&fingerprint).map(|fingerprint| Arc::new(fingerprint.profile)),
+      (None, None) => Ok(BrowserProfile::builtin(&browser)),
+    };
+    let browser = profile.as_ref().map_or(browser, |profile| profile.browser.clone());
+
+    // The version is picked once, so all the requests of the instance come from the same browser.
+    let profile = match options.browser_version {
+      Some(version) => profile.and_then(|profile| version.pick().and_then(|version| profile.with_version(version))).map(Arc::new),
+      None => profile,
+    };
+
+    Retcher { 
+      engines: Mutex::new(IndexMap::new()),
+      ignore_tls_errors: options.ignore_tls_errors.unwrap_or(false),
+      connect_timeout: options.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+      proxy: options.proxy,
+      proxy_pool: options.proxy_pool.map(ProxyPool::new),
+      cookie_jar: CookieJar::new(),
+      auth_cache: Arc::new(AuthCache::default()),
+      browser,
+      profile,
+    }
+  }
+
+  /// Returns the `reqwest` client sending requests through the given proxy.
+  fn engine(&self, proxy: Option<&ProxyOptions>) -> Result<reqwest::Client, FetchError> {
+    let mut engines = self.engines.lock().unwrap_or_else(|error| error.into_inner());
+
+    if let Some(index) = engines.get_index_of(&proxy.cloned()) {
+      let last = engines.len() - 1;
+      engines.move_index(index, last);
+      return Ok(engines[last].clone());
+    }
+
+    // Redirects are followed by `retch` itself, so the headers can be generated for every hop.
     let mut engine = reqwest::ClientBuilder::new()
-      .http1_title_case_headers();
+      .http1_title_case_headers()
+      .redirect(reqwest::redirect::Policy::none());
 
-    if options.ignore_tls_errors.unwrap_or(false) {
+    if self.ignore_tls_errors {
       engine = engine
         .danger_accept_invalid_certs(true)
         .danger_accept_invalid_hostnames(true);
     }
 
-    Retcher { 
-      engine: engine.build().unwrap(), 
-      browser: options.browser.unwrap_or(Browser::Firefox),
+    // The relay replaces `reqwest`'s HTTP/2 `SETTINGS` with the browser's ones, so the flow control has to agree with them.
+    let profile = self.profile.clone()?;
+    let http2 = &profile.http2;
+    engine = engine
+      .http2_initial_stream_window_size(http2.setting(http2::INITIAL_WINDOW_SIZE))
+      .http2_initial_connection_window_size(http2.connection_window_size);
+
+    // The relay makes all the connections (and verifies the servers' certificates), `reqwest` only ever talks TLS to the relay.
+    for proxy in reqwest_proxies(proxy, &profile, self.ignore_tls_errors, self.connect_timeout, &self.auth_cache)? {
+      engine = engine.proxy(proxy);
     }
+    engine = engine
+      .tls_built_in_root_certs(false)
+      .add_root_certificate(tunnel::relay_certificate()?);
+
+    let engine = engine.build().map_err(|error| FetchError::from_reqwest(error, FetchPhase::Setup))?;
+    if engines.len() >= MAX_ENGINES {
+      engines.shift_remove_index(0);
+    }
+    engines.insert(proxy.cloned(), engine.clone());
+
+    Ok(engine)
+  }
+
+  /// Returns the proxies (`None` for the direct connections) the kept `reqwest` clients are bound to, the least recently used first.
+  #[cfg(test)]
+  pub(crate) fn engine_proxies(&self) -> Vec<Option<ProxyOptions>> {
+    self.engines.lock().unwrap_or_else(|error| error.into_inner()).keys().cloned().collect()
+  }
+
+  /// Returns the profile of the impersonated browser, or why it couldn't be made for `EngineOptions::browser_version`.
+  pub fn profile(&self) -> Result<&BrowserProfile, FetchError> {
+    self.profile.as_deref().map_err(Clone::clone)
+  }
+
+  /// Returns the cookie jar used by all the requests made with this instance.
+  pub fn cookies(&self) -> &CookieJar {
+    &self.cookie_jar
+  }
+
+  /// Returns the proxy pool from `EngineOptions::proxy_pool`, e.g. to check the health of the proxies.
+  pub fn proxy_pool(&self) -> Option<&ProxyPool> {
+    self.proxy_pool.as_ref()
   }
 
   /// Calling `retch` with an URL and optional options will make a request to the URL and return a `FetchResponse`.
@@ -83,90 +461,431 @@ impl Retcher {
   /// * `url` - A `String` that holds the URL to make a request to.
   /// * `options` - An optional `FetchOptions` struct that holds additional options for the request.
   pub async fn retch(&self, url: String, options: Option<FetchOptions>) -> Result<FetchResponse, FetchError> {
-    // TODO!!! Implement other HTTP methods
-    self.get(url, options).await
+    self.make_request(url, options.unwrap_or_default()).await
   }
 
-  /// Calling `get` with an URL and optional options will make a request to the URL and return a `FetchResponse`.
-  async fn get(&self, url: String, options: Option<FetchOptions>) -> Result<FetchResponse, FetchError> {
-    let url = Url::parse(&url).unwrap();
-
-    let host = url.host_str().unwrap();
-    let protocol = url.scheme();
+  /// Calling `make_request` with an URL and options will make a request to the URL and return a `FetchResponse`.
+  /// 
+  /// The proxy is picked here, and the outcome of the request is reported to the proxy pool it came from.
+  async fn make_request(&self, url: String, mut options: FetchOptions) -> Result<FetchResponse, FetchError> {
+    let url = Url::parse(&url).map_err(|error| FetchError::new(
+      FetchErrorKind::InvalidUrl,
+      FetchPhase::Setup,
+      Some(url.clone()),
+      format!("Invalid URL '{}': {}", url, error),
+    ).with_source(error))?;
 
-    let custom_headers = match options {
-      Some(options) => options.headers,
-      None => HashMap::new(),
+    let pooled = match (&options.proxy, &self.proxy_pool) {
+      (None, Some(pool)) => Some(pool.pick(&url, options.session.as_deref())?),
+      _ => None,
     };
+    let proxy = options.proxy.take()
+      .or_else(|| pooled.as_ref().map(|(_, proxy)| proxy.clone()))
+      .or_else(|| self.proxy.clone());
 
-    let protocol_error: Option<FetchError> = match protocol {
-      "http" => None,
-      "https" => None,
-      _ => Some(FetchError{
-        message: "Unsupported protocol".to_string(),
-      }),
-    };
+    let result = self.follow_redirects(url, options, proxy.as_ref()).await;
 
-    if protocol_error.is_some() {
-      return Err(protocol_error.unwrap());
+    if let (Some(pool), Some((index, _))) = (&self.proxy_pool, pooled) {
+      pool.report(index, result.as_ref().map(|response| response.status));
     }
 
-    let mut headers = generate_headers(HeaderGeneratorOptions {
-      host: host.to_string(), 
-      browser: self.browser.clone(), 
-      https: protocol == "https",
-      custom_headers: Some(custom_headers.clone()),
-    });
+    result
+  }
+
+  /// Sends the request through the proxy, following the redirects.
+  /// 
+  /// Redirects are handled here (and not by `reqwest`) according to `FetchOptions::redirect`.
+  /// All the hops go through the same proxy.
+  async fn follow_redirects(&self, mut url: Url, options: FetchOptions, proxy: Option<&ProxyOptions>) -> Result<FetchResponse, FetchError> {
+    let FetchOptions {
+      mut method, headers: mut custom_headers, body, redirect, max_redirects, credentials, destination, mode, navigation_type, referrer,
+      referrer_policy, ..
+    } = options;
+    let mode = mode.unwrap_or(destination.default_mode());
 
-    for (key, value) in custom_headers.iter() {
-      headers.insert(HeaderName::from_str(key).unwrap(), value.parse().unwrap());
+    if (mode == RequestMode::Navigate) != destination.is_navigation() {
+      return Err(FetchError::new(
+        FetchErrorKind::InvalidRequest,
+        FetchPhase::Setup,
+        Some(url.to_string()),
+        format!("The '{}' mode can't be used for the '{}' destination", mode.as_str(), destination.as_str()),
+      ));
     }
 
-    let response = self.engine.get(url)
-      .headers(headers)
-      .send()
-      .await;
+    if navigation_type != NavigationType::Normal && destination != Destination::Document {
+      return Err(FetchError::new(
+        FetchErrorKind::InvalidRequest,
+        FetchPhase::Setup,
+        Some(url.to_string()),
+        format!("The '{}' navigation type is only for the 'document' destination", navigation_type.as_str()),
+      ));
+    }
 
-    if response.is_err() {
-      return Err(FetchError{
-        message: format!("{:?}", response.err().unwrap()),
-      });
+    let profile = self.profile().map_err(|mut error| {
+      error.url = Some(url.to_string());
+      error
+    })?;
+    if profile.navigation_types.as_ref().is_some_and(|types| !types.contains(&navigation_type)) {
+      return Err(FetchError::new(
+        FetchErrorKind::InvalidRequest,
+        FetchPhase::Setup,
+        Some(url.to_string()),
+        format!("The browser profile '{}' has no '{}' navigations", profile.name, navigation_type.as_str()),
+      ));
     }
 
-    let response = response.unwrap();
+    let engine = self.engine(proxy).map_err(|mut error| {
+      error.url = Some(url.to_string());
+      error
+    })?;
+    let max_redirects = max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS);
+
+    let mut content_type = body.as_ref().and_then(|body| body.content_type());
+    let mut body = body.map(|body| body.into_bytes());
+
+    let referrer = referrer.map(|referrer| Url::parse(&referrer).map_err(|error| FetchError::new(
+      FetchErrorKind::InvalidUrl,
+      FetchPhase::Setup,
+      Some(url.to_string()),
+      format!("Invalid referrer '{}': {}", referrer, error),
+    ).with_source(error))).transpose()?;
+
+    // Without the `referrer`, a custom `Referer` is sent as is first, but recomputed for every redirect hop.
+    let referer_header = remove_headers(&mut custom_headers, &["referer"]).pop();
+    // Without the `referrer`, a subresource comes from a page of the URL's origin.
+    let initiator = referrer.clone().unwrap_or_else(|| url.clone());
+    // A navigation without a referrer is started by the user, so it has no initiator, whatever it's redirected to.
+    let user_initiated = referrer.is_none() && destination.is_navigation();
+
+    let mut redirects = Vec::new();
+    let mut url_chain: Vec<Url> = vec![url.clone()];
+
+    let response = loop {
+      // Without an initiator, the redirect chain is compared to the site the navigation ends up on (the site for cookies), like in Chromium.
+      let site_relation = site::redirect_site_relation(if user_initiated { &url } else { &initiator }, &url_chain);
+      let sec_fetch_site = if user_initiated { None } else { Some(site_relation.sec_fetch_site()) };
+
+      let referer = match (&referrer, &referer_header) {
+        (Some(referrer), _) => site::referer(referrer, &url, referrer_policy),
+        (None, Some(referer)) if redirects.is_empty() => Some(referer.clone()),
+        (None, Some(referer)) => Url::parse(referer).ok().and_then(|referrer| site::referer(&referrer, &url, referrer_policy)),
+        (None, None) => None,
+      };
+
+      let use_cookies = match credentials {
+        Credentials::Omit => false,
+        Credentials::SameOrigin => url.origin() == initiator.origin(),
+        Credentials::Include => true,
+      };
 
-    let mut headers = HashMap::new();
+      let cookie = if use_cookies {
+        // Chromium treats cookies without `SameSite` as `SameSite=Lax`, Firefox and Safari don't.
+        self.cookie_jar.cookie_header(&url, site_relation, &method, destination == Destination::Document, self.browser.is_chromium())
+      } else {
+        None
+      };
 
+      // Browsers attach `Origin` to the cross-origin CORS requests, and to every request that isn't a `GET` (or `HEAD`).
+      let origin = if mode == RequestMode::Cors && url.origin() != initiator.origin() {
+        Some(initiator.origin().ascii_serialization())
+      } else if method != Method::GET && method != Method::HEAD {
+        Some(site::origin(&initiator, &url, referrer_policy))
+      } else {
+        None
+      };
+
+      let response = self.send_with_proxy_auth(proxy, Hop {
+        engine: &engine,
+        url: &url,
+        method: &method,
+        custom_headers: &custom_headers,
+        content_type,
+        body: body.as_deref(),
+        destination,
+        mode,
+        navigation_type,
+        origin,
+        sec_fetch_site,
+        referer,
+        cookie,
+        proxy_authorization: None,
+      }).await?;
+      let status = response.status();
+
+      if use_cookies {
+        self.cookie_jar.store_response_cookies(&url, response.headers().get_all(SET_COOKIE).iter().map(decode_header_value));
+      }
+
+      // Only these are redirects for `fetch` - e.g. `300 Multiple Choices` or `304 Not Modified` are returned as they are.
+      let is_redirect = matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308);
+
+      let location = match response.headers().get(LOCATION) {
+        Some(location) if is_redirect && redirect != RedirectMode::Manual => decode_header_value(location),
+        _ => break response,
+      };
+
+      if redirect == RedirectMode::Error {
+        return Err(FetchError::new(
+          FetchErrorKind::Redirect,
+          FetchPhase::Request,
+          Some(url.to_string()),
+          format!("Unexpected redirect from {} to '{}' (redirect mode is 'error')", url, location),
+        ));
+      }
+
+      let next_url = url.join(&location).map_err(|error| FetchError::new(
+        FetchErrorKind::Redirect,
+        FetchPhase::Request,
+        Some(url.to_string()),
+        format!("Invalid redirect location '{}' in the response from {}: {}", location, url, error),
+      ).with_source(error))?;
+
+      if next_url.scheme() != "http" && next_url.scheme() != "https" {
+        return Err(FetchError::new(
+          FetchErrorKind::Redirect,
+          FetchPhase::Request,
+          Some(url.to_string()),
+          format!("Redirect from {} to the unsupported protocol '{}'", url, next_url.scheme()),
+        ));
+      }
+
+      if mode == RequestMode::SameOrigin && next_url.origin() != initiator.origin() {
+        return Err(FetchError::new(
+          FetchErrorKind::Redirect,
+          FetchPhase::Request,
+          Some(url.to_string()),
+          format!("Redirect from {} to the cross-origin URL '{}' (mode is 'same-origin')", url, next_url),
+        ));
+      }
+
+      if redirects.len() >= max_redirects {
+        return Err(FetchError::new(
+          FetchErrorKind::Redirect,
+          FetchPhase::Request,
+          Some(url.to_string()),
+          format!("Too many redirects (more than {}) while processing {}", max_redirects, url),
+        ));
+      }
+
+      redirects.push(RedirectHop {
+        url: url.to_string(),
+        status: status.as_u16(),
+        location: next_url.to_string(),
+        set_cookies: response.headers().get_all(SET_COOKIE).iter().map(decode_header_value).collect(),
+      });
+
+      // A `303 See Other` (and, for historical reasons, `301` and `302` after a `POST`) turns the request into a body-less `GET`.
+      if (status == StatusCode::SEE_OTHER && method != Method::HEAD)
+        || ((status == StatusCode::MOVED_PERMANENTLY || status == StatusCode::FOUND) && method == Method::POST) {
+        method = Method::GET;
+        content_type = None;
+        body = None;
+        remove_headers(&mut custom_headers, &["content-encoding", "content-language", "content-location", "content-type"]);
+      }
+
+      // Credentials meant for one origin must not leak to another one.
+      if url.origin() != next_url.origin() {
+        remove_headers(&mut custom_headers, &["authorization", "cookie"]);
+      }
+
+      url_chain.push(next_url.clone());
+      url = next_url;
+    };
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+
+    // Like `fetch`'s `Headers`, the values of a repeated header are combined into one (`set_cookies` keeps them apart for `Set-Cookie`).
     for (key, value) in response.headers().iter() {
-      headers.insert(key.to_string(), value.to_str().unwrap().to_string());
-    }
-
-    let mut result = FetchResponse {
-      body: None,
-      body_used: true,
-      headers: headers,
-      ok: response.status().is_success(),
-      redirected: response.status().is_redirection(),
-      status: response.status().as_u16(),
-      status_text: response.status().canonical_reason().unwrap().to_string(),
-      url: response.url().to_string(),
-      r#type: "basic".to_string(),
+      let value = decode_header_value(value);
+      headers
+        .entry(key.to_string())
+        .and_modify(|combined| {
+          combined.push_str(", ");
+          combined.push_str(&value);
+        })
+        .or_insert(value);
+    }
+    let set_cookies = response.headers().get_all(SET_COOKIE).iter().map(decode_header_value).collect();
+
+    let status = response.status().as_u16();
+    // The proxy didn't serve the response if it's bypassed for the final URL (e.g. because of `ProxyOptions::no_proxy`).
+    let served_by = proxy.filter(|proxy| proxy.applies_to(response.url())).map(ProxyOptions::display_url);
+    let url = response.url().to_string();
+
+    // Like `fetch`, prefer the reason phrase the server actually sent and fall back to an empty string for unknown codes.
+    let status_text = match response.extensions().get::<hyper::ext::ReasonPhrase>() {
+      Some(reason) => String::from_utf8_lossy(reason.as_bytes()).to_string(),
+      None => response.status().canonical_reason().unwrap_or_default().to_string(),
     };
 
-    let body = response.bytes().await;
+    let mut result = FetchResponse::new(
+      ResponseBody::from_reqwest(response),
+      headers,
+      status,
+      status_text,
+      url,
+    );
+    result.set_cookies = set_cookies;
+    result.redirected = !redirects.is_empty();
+    result.redirects = redirects;
+    result.proxy = served_by;
 
-    if body.is_err() {
-      return Err(FetchError {
-        message: format!("{:?}", body.err().unwrap()),
-      });
+    Ok(result)
+  }
+
+  /// Sends a single request (one hop of `make_request`), answering the `407` challenges of the proxy forwarding it.
+  ///
+  /// Like browsers, the first request waits for the challenge, the next ones send the credentials right away.
+  async fn send_with_proxy_auth(&self, proxy: Option<&ProxyOptions>, mut hop: Hop<'_>) -> Result<reqwest::Response, FetchError> {
+    if let Some(proxy) = proxy {
+      proxy.check_target(hop.url)?;
+    }
+
+    let Some((key, credentials)) = proxy.and_then(|proxy| proxy.forward_auth(hop.url)) else {
+      return self.send_request(hop).await;
+    };
+
+    // Proxied requests have the absolute URL as the request target.
+    let uri = hop.url.as_str().split('#').next().unwrap_or_default().to_string();
+    hop.proxy_authorization = self.auth_cache.cached_authorization(&key, &credentials, hop.method.as_str(), &uri);
+
+    for _ in 1..MAX_PROXY_AUTH_ATTEMPTS {
+      let response = self.send_request(hop.clone()).await?;
+      if response.status() != StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+        return Ok(response);
+      }
+
+      let challenges: Vec<String> = response.headers().get_all(PROXY_AUTHENTICATE).iter().map(decode_header_value).collect();
+      match self.auth_cache.answer_challenge(&key, &credentials, challenges.iter().map(String::as_str), hop.method.as_str(), &uri) {
+        Some(authorization) => hop.proxy_authorization = Some(authorization),
+        None => return Ok(response),
+      }
+    }
+
+    self.send_request(hop).await
+  }
+
+  /// Sends a single request (one hop of `make_request`) with freshly generated browser headers.
+  async fn send_request(&self, hop: Hop<'_>) -> Result<reqwest::Response, FetchError> {
+    let Hop {
+      engine, url, method, custom_headers, content_type, body, destination, mode, navigation_type, origin, sec_fetch_site, referer, cookie,
+      proxy_authorization,
+    } = hop;
+    let protocol = url.scheme();
+
+    if protocol != "http" && protocol != "https" {
+      return Err(FetchError::new(
+        FetchErrorKind::UnsupportedProtocol,
+        FetchPhase::Setup,
+        Some(url.to_string()),
+        format!("Unsupported protocol '{}', only 'http' and 'https' are supported", protocol),
+      ));
+    }
+
+    let host = site::authority(url).ok_or_else(|| FetchError::new(
+      FetchErrorKind::InvalidUrl,
+      FetchPhase::Setup,
+      Some(url.to_string()),
+      format!("Invalid URL '{}': missing host", url),
+    ))?;
+
+    // Browsers send `Content-Length: 0` for body-less requests with methods that expect a payload.
+    let content_length = match body {
+      Some(body) => Some(body.len()),
+      None if method == Method::POST || method == Method::PUT || method == Method::PATCH => Some(0),
+      None => None,
+    };
+
+    let GeneratedHeaders { mut headers, order } = generate_headers(HeaderGeneratorOptions {
+      host,
+      profile: self.profile.clone()?,
+      https: protocol == "https",
+      destination,
+      mode,
+      navigation_type,
+      custom_headers: Some(custom_headers.clone()),
+      content_type: content_type.map(|content_type| content_type.to_string()),
+      content_length,
+      origin,
+      sec_fetch_site: sec_fetch_site.map(|sec_fetch_site| sec_fetch_site.to_string()),
+      referer,
+      cookie,
+    }).map_err(|mut error| {
+      error.url = Some(url.to_string());
+      error
+    })?;
+
+    if let Some(proxy_authorization) = proxy_authorization {
+      let value = HeaderValue::from_str(&proxy_authorization).map_err(|error| FetchError::new(
+        FetchErrorKind::Proxy,
+        FetchPhase::Setup,
+        Some(url.to_string()),
+        format!("Invalid proxy credentials: {}", error),
+      ).with_source(error))?;
+      headers.insert(PROXY_AUTHORIZATION, value);
     }
 
-    let body = body.unwrap();
-    if body.is_empty() {
-      return Ok(result)
-    } else {
-      result.body = Some(body.into());
-      return Ok(result)
+    // `reqwest` sends the headers in its own order (and casing), the relay puts them back in the browser's one
+    // and gives the HTTP/2 requests the destination's priority.
+    headers.insert(tunnel::HEADER_ORDER, HeaderValue::from_str(&tunnel::header_order(&order)).expect("header names are valid header values"));
+    headers.insert(tunnel::DESTINATION, HeaderValue::from_static(destination.as_str()));
+
+    let mut request = engine.request(method.clone(), url.clone())
+      .headers(headers);
+
+    if let Some(body) = body {
+      request = request.body(body.to_vec());
+    }
+
+    // The relay tells why it couldn't connect to the server by the request's ID.
+    let relay_request = RelayRequest::new()?;
+    let response = relay_request
+      .send(request.header(tunnel::RELAY_REQUEST, relay_request.header()).send())
+      .await
+      .map_err(|error| relay_request.target_failure(FetchError::from_reqwest(error, FetchPhase::Request), url))?;
+
+    if response.headers().contains_key(tunnel::RELAY_FAILURE) {
+      return Err(relay_request.forward_failure(url));
     }
+
+    Ok(response)
+  }
+}
+
+/// Hop holds everything needed to send one request of a (possibly redirected) `retch` call.
+#[derive(Clone)]
+struct Hop<'a> {
+  engine: &'a reqwest::Client,
+  url: &'a Url,
+  method: &'a Method,
+  custom_headers: &'a IndexMap<String, String>,
+  content_type: Option<&'a str>,
+  body: Option<&'a [u8]>,
+  destination: Destination,
+  mode: RequestMode,
+  navigation_type: NavigationType,
+  origin: Option<String>,
+  sec_fetch_site: Option<&'static str>,
+  referer: Option<String>,
+  cookie: Option<String>,
+  /// The `Proxy-Authorization` value for a request forwarded by an HTTP proxy.
+  proxy_authorization: Option<String>,
+}
+
+/// Removes the (case-insensitively matched) headers and returns their values.
+fn remove_headers(headers: &mut IndexMap<String, String>, names: &[&str]) -> Vec<String> {
+  let keys: Vec<String> = headers.keys()
+    .filter(|key| names.iter().any(|name| key.eq_ignore_ascii_case(name)))
+    .cloned()
+    .collect();
+
+  keys.iter().filter_map(|key| headers.shift_remove(key)).collect()
+}
+
+/// Decodes a header value the way `fetch` does - byte by byte (as Latin-1), so non-ASCII values never fail.
+fn decode_header_value(value: &HeaderValue) -> String {
+  match value.to_str() {
+    Ok(value) => value.to_string(),
+    Err(_) => value.as_bytes().iter().map(|&byte| byte as char).collect(),
   }
 }
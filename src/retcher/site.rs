@@ -0,0 +1,143 @@
+use std::sync::OnceLock;
+
+use publicsuffix::{List, Psl};
+use url::{Host, Url};
+
+use super::retcher::ReferrerPolicy;
+
+/// A snapshot of the Public Suffix List (https://publicsuffix.org/list/public_suffix_list.dat).
+static PUBLIC_SUFFIX_LIST_DATA: &str = include_str!("public_suffix_list.dat");
+static PUBLIC_SUFFIX_LIST: OnceLock<List> = OnceLock::new();
+
+pub(crate) fn public_suffix_list() -> &'static List {
+  PUBLIC_SUFFIX_LIST.get_or_init(|| PUBLIC_SUFFIX_LIST_DATA.parse().expect("the bundled Public Suffix List is valid"))
+}
+
+/// Returns the registrable domain ("eTLD+1") of the host, e.g. `example.co.uk` for `www.example.co.uk`.
+///
+/// IP addresses and public suffixes themselves have no registrable domain.
+pub(crate) fn registrable_domain(host: &Host<&str>) -> Option<String> {
+  match host {
+    Host::Domain(domain) => public_suffix_list()
+      .domain(domain.trim_end_matches('.').as_bytes())
+      .map(|domain| String::from_utf8_lossy(domain.as_bytes()).to_lowercase()),
+    _ => None,
+  }
+}
+
+/// Returns the `Host` (or `:authority`) of a request to the URL, e.g. `[::1]:8080` or `xn--bcher-kva.example`.
+///
+/// IPv6 addresses are in brackets and domains in punycode (`url` converts them when parsing), the port is only there when it isn't the scheme's default one.
+pub(crate) fn authority(url: &Url) -> Option<String> {
+  let host = match url.host()? {
+    Host::Domain(domain) => domain.to_string(),
+    Host::Ipv4(address) => address.to_string(),
+    Host::Ipv6(address) => format!("[{}]", address),
+  };
+
+  Some(match url.port() {
+    Some(port) => format!("{}:{}", host, port),
+    None => host,
+  })
+}
+
+/// How two URLs relate to each other, from the closest to the furthest relation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SiteRelation {
+  SameOrigin,
+  SameSite,
+  CrossSite,
+}
+
+impl SiteRelation {
+  /// Compares the two URLs the way browsers do for `Sec-Fetch-Site` and `SameSite` cookies.
+  pub(crate) fn between(a: &Url, b: &Url) -> Self {
+    if a.origin() == b.origin() {
+      return SiteRelation::SameOrigin;
+    }
+
+    let same_site = a.scheme() == b.scheme() && match (a.host(), b.host()) {
+      (Some(a), Some(b)) => match (registrable_domain(&a), registrable_domain(&b)) {
+        (Some(a), Some(b)) => a == b,
+        // Without a registrable domain (e.g. for IP addresses), the site is the host itself.
+        _ => a.to_string().eq_ignore_ascii_case(&b.to_string()),
+      },
+      _ => false,
+    };
+
+    if same_site { SiteRelation::SameSite } else { SiteRelation::CrossSite }
+  }
+
+  /// Returns the `Sec-Fetch-Site` value for this relation.
+  pub(crate) fn sec_fetch_site(&self) -> &'static str {
+    match self {
+      SiteRelation::SameOrigin => "same-origin",
+      SiteRelation::SameSite => "same-site",
+      SiteRelation::CrossSite => "cross-site",
+    }
+  }
+}
+
+/// Returns the relation of a redirected navigation to its initiator (for `Sec-Fetch-Site` and `SameSite` cookies).
+///
+/// Like in browsers, this is the furthest relation between the initiator and any of the URLs the request went through.
+pub(crate) fn redirect_site_relation<'a>(initiator: &Url, url_chain: impl IntoIterator<Item = &'a Url>) -> SiteRelation {
+  url_chain
+    .into_iter()
+    .map(|url| SiteRelation::between(initiator, url))
+    .max()
+    .unwrap_or(SiteRelation::SameOrigin)
+}
+
+/// The longest `Referer` browsers send, longer ones are cut to the origin.
+const MAX_REFERER_LENGTH: usize = 4096;
+
+/// Returns whether a request from `referrer` to `target` leaves HTTPS.
+fn is_downgrade(referrer: &Url, target: &Url) -> bool {
+  referrer.scheme() == "https" && target.scheme() != "https"
+}
+
+/// Returns the `Referer` sent with a request from `referrer` to `target` under the referrer policy.
+pub(crate) fn referer(referrer: &Url, target: &Url, policy: ReferrerPolicy) -> Option<String> {
+  if referrer.scheme() != "http" && referrer.scheme() != "https" {
+    return None;
+  }
+
+  let origin = format!("{}/", referrer.origin().ascii_serialization());
+  let mut full = referrer.clone();
+  full.set_fragment(None);
+  let _ = full.set_username("");
+  let _ = full.set_password(None);
+  let full = if full.as_str().len() > MAX_REFERER_LENGTH { origin.clone() } else { full.to_string() };
+
+  let same_origin = referrer.origin() == target.origin();
+  let downgrade = is_downgrade(referrer, target);
+
+  match policy {
+    ReferrerPolicy::NoReferrer => None,
+    ReferrerPolicy::NoReferrerWhenDowngrade => (!downgrade).then_some(full),
+    ReferrerPolicy::Origin => Some(origin),
+    ReferrerPolicy::OriginWhenCrossOrigin => Some(if same_origin { full } else { origin }),
+    ReferrerPolicy::SameOrigin => same_origin.then_some(full),
+    ReferrerPolicy::StrictOrigin => (!downgrade).then_some(origin),
+    ReferrerPolicy::StrictOriginWhenCrossOrigin if downgrade => None,
+    ReferrerPolicy::StrictOriginWhenCrossOrigin => Some(if same_origin { full } else { origin }),
+    ReferrerPolicy::UnsafeUrl => Some(full),
+  }
+}
+
+/// Returns the `Origin` sent with a non-CORS request from `initiator` to `target` (e.g. a form `POST`) under the referrer policy.
+///
+/// Where the policy wouldn't reveal the origin in the `Referer`, it's `null`.
+pub(crate) fn origin(initiator: &Url, target: &Url, policy: ReferrerPolicy) -> String {
+  let hidden = match policy {
+    ReferrerPolicy::NoReferrer => true,
+    ReferrerPolicy::NoReferrerWhenDowngrade | ReferrerPolicy::StrictOrigin | ReferrerPolicy::StrictOriginWhenCrossOrigin => {
+      is_downgrade(initiator, target)
+    },
+    ReferrerPolicy::SameOrigin => initiator.origin() != target.origin(),
+    ReferrerPolicy::Origin | ReferrerPolicy::OriginWhenCrossOrigin | ReferrerPolicy::UnsafeUrl => false,
+  };
+
+  if hidden { "null".to_string() } else { initiator.origin().ascii_serialization() }
+}
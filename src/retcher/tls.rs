@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(all(feature = "openssl", feature = "boringssl"))]
+compile_error!("the `openssl` and `boringssl` features exclude each other, disable the default features for BoringSSL");
+#[cfg(not(any(feature = "openssl", feature = "boringssl")))]
+compile_error!("either the `openssl` or the `boringssl` feature is needed");
+
+/// The BoringSSL bindings are a fork of the `openssl` crate, so everything but the `ClientHello` is the same code for both.
+#[cfg(feature = "boringssl")]
+pub(crate) use boring2 as openssl;
+#[cfg(feature = "openssl")]
+pub(crate) use ::openssl;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+  self, AlpnError, ErrorCode, SslAcceptor, SslConnector, SslConnectorBuilder, SslMethod, SslStream, SslVerifyMode, SslVersion, StatusType,
+};
+#[cfg(feature = "openssl")]
+use openssl::ssl::SslOptions;
+#[cfg(feature = "boringssl")]
+use openssl::ssl::{CertCompressionAlgorithm, ExtensionType};
+use openssl::x509::extension::{
+  AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName, SubjectKeyIdentifier,
+};
+use openssl::x509::{X509NameBuilder, X509};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// `SSL_OP_TLSEXT_PADDING`, which the `openssl` crate doesn't name.
+#[cfg(feature = "openssl")]
+const TLSEXT_PADDING: SslOptions = SslOptions::from_bits_retain(0x10);
+
+/// TlsProfile is the TLS `ClientHello` of a browser.
+///
+/// The cipher suites, groups (with the key shares), signature algorithms and ALPN protocols are the browser's ones, in its order.
+/// The rest is only reproduced with the `boringssl` feature: OpenSSL decides which extensions are sent and in what order, it can't send
+/// GREASE values (nor permute the extensions like Chrome does) and it doesn't know Chrome's post-quantum key share (`X25519Kyber768Draft00`).
+/// Firefox's SHA-1 signature algorithms are left out since OpenSSL refuses them at its default security level.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub(crate) struct TlsProfile {
+  /// The TLS 1.3 cipher suites.
+  pub tls13_cipher_suites: String,
+  /// The TLS 1.2 cipher suites, with their OpenSSL names.
+  pub cipher_list: String,
+  /// The supported groups. The ones prefixed with `*` get a key share (OpenSSL 3.5+, older versions send the first one only),
+  /// the ones prefixed with `?` are left out by the TLS libraries that don't know them.
+  pub groups: String,
+  pub signature_algorithms: String,
+  pub alpn: Vec<String>,
+  /// Whether the `ClientHello` is padded (RFC 7685) when it is 256 to 511 bytes long, BoringSSL always does.
+  pub padding: bool,
+  /// Whether GREASE values (RFC 8701) are sent, like Chromium and Safari do.
+  #[serde(default)]
+  pub grease: bool,
+  /// Whether the extensions are sent in a random order, like Chrome does since version 110.
+  #[serde(default)]
+  pub permute_extensions: bool,
+  /// The extensions in the order they are sent, the ones left out aren't sent at all. BoringSSL's (Chromium's) order when empty.
+  #[serde(default)]
+  pub extension_order: Vec<u16>,
+  /// Whether a GREASE `encrypted_client_hello` extension is sent.
+  #[serde(default)]
+  pub ech_grease: bool,
+  /// The certificate compression algorithms (RFC 8879), in their order.
+  #[serde(default)]
+  pub certificate_compression: Vec<CertificateCompression>,
+  /// Whether ALPS (`application_settings`) is offered along with HTTP/2, like Chromium does.
+  #[serde(default)]
+  pub application_settings: bool,
+  /// The `record_size_limit` (RFC 8449) sent, if any.
+  #[serde(default)]
+  pub record_size_limit: Option<u16>,
+  /// The signature algorithms offered for delegated credentials (RFC 9345), if any.
+  #[serde(default)]
+  pub delegated_credentials: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CertificateCompression {
+  Zlib,
+  Brotli,
+  Zstd,
+}
+
+/// The connectors, one per TLS profile and `ignore_tls_errors` (loading the root certificates is expensive).
+static CONNECTORS: OnceLock<Mutex<Vec<(TlsProfile, bool, SslConnector)>>> = OnceLock::new();
+
+/// Configures the connector with the browser's `ClientHello`.
+#[cfg(feature = "openssl")]
+fn configure(builder: &mut SslConnectorBuilder, profile: &TlsProfile) -> Result<(), ErrorStack> {
+  builder.set_min_proto_version(Some(SslVersion::TLS1_2))?;
+  builder.set_ciphersuites(&profile.tls13_cipher_suites)?;
+  builder.set_cipher_list(&profile.cipher_list)?;
+  if builder.set_groups_list(&profile.groups).is_err() {
+    // Older versions know neither prefix, the optional groups are newer than them anyway.
+    let groups: Vec<&str> = profile.groups.split(':').filter(|group| !group.contains('?')).map(|group| group.trim_start_matches('*')).collect();
+    builder.set_groups_list(&groups.join(":"))?;
+  }
+  builder.set_sigalgs_list(&profile.signature_algorithms)?;
+  // Servers often close the connection without a `close_notify`, browsers don't mind (see `TlsStream::poll_ssl` for BoringSSL).
+  builder.set_options(SslOptions::IGNORE_UNEXPECTED_EOF);
+  if profile.padding {
+    builder.set_options(TLSEXT_PADDING);
+  }
+
+  Ok(())
+}
+
+/// Configures the connector with the browser's `ClientHello`.
+#[cfg(feature = "boringssl")]
+fn configure(builder: &mut SslConnectorBuilder, profile: &TlsProfile) -> Result<(), ErrorStack> {
+  builder.set_min_proto_version(Some(SslVersion::TLS1_2))?;
+  // BoringSSL takes the TLS 1.3 cipher suites from the same list, in the given order (`boring2` patches it to).
+  builder.set_cipher_list(&format!("{}:{}", profile.tls13_cipher_suites, profile.cipher_list))?;
+  let groups: Vec<&str> = profile.groups.split(':').collect();
+  builder.set_curves_list(&groups.iter().map(|group| group.trim_start_matches(['?', '*'])).collect::<Vec<_>>().join(":"))?;
+  builder.set_key_shares_limit(groups.iter().filter(|group| group.contains('*')).count().max(1) as u8);
+  builder.set_sigalgs_list(&profile.signature_algorithms)?;
+
+  builder.set_grease_enabled(profile.grease);
+  builder.set_permute_extensions(profile.permute_extensions);
+  if !profile.extension_order.is_empty() {
+    let order: Vec<ExtensionType> = profile.extension_order.iter().copied().map(ExtensionType::from).collect();
+    builder.set_extension_permutation(&order)?;
+  }
+
+  // Sent by every browser, unless the extension order leaves them out.
+  builder.enable_ocsp_stapling();
+  builder.enable_signed_cert_timestamps();
+  for algorithm in &profile.certificate_compression {
+    builder.add_cert_compression_alg(match algorithm {
+      CertificateCompression::Zlib => CertCompressionAlgorithm::Zlib,
+      CertificateCompression::Brotli => CertCompressionAlgorithm::Brotli,
+      CertificateCompression::Zstd => CertCompressionAlgorithm::Zstd,
+    })?;
+  }
+  if let Some(limit) = profile.record_size_limit {
+    builder.set_record_size_limit(limit);
+  }
+  if let Some(signature_algorithms) = &profile.delegated_credentials {
+    builder.set_delegated_credentials(signature_algorithms)?;
+  }
+
+  Ok(())
+}
+
+/// Checks that the TLS library knows the profile's cipher suites, groups, signature algorithms (and extensions).
+pub(crate) fn validate(profile: &TlsProfile) -> Result<(), String> {
+  #[cfg(feature = "boringssl")]
+  if let Some(extension) = profile.extension_order.iter().find(|extension| ExtensionType::index_of((**extension).into()).is_none()) {
+    return Err(format!("BoringSSL can't send the extension {}", extension));
+  }
+
+  SslConnector::builder(SslMethod::tls_client())
+    .and_then(|mut builder| configure(&mut builder, profile))
+    .map_err(|error| error.to_string())
+}
+
+fn connector(profile: &TlsProfile, ignore_tls_errors: bool) -> Result<SslConnector, ErrorStack> {
+  let mut connectors = CONNECTORS.get_or_init(Default::default).lock().unwrap_or_else(|error| error.into_inner());
+
+  if let Some((_, _, connector)) = connectors.iter().find(|(p, i, _)| p == profile && *i == ignore_tls_errors) {
+    return Ok(connector.clone());
+  }
+
+  let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+  configure(&mut builder, profile)?;
+
+  // Like `native-tls`, also look for the root certificates where the distribution keeps them.
+  let probe = openssl_probe::probe();
+  #[cfg(feature = "openssl")]
+  {
+    if let Some(file) = probe.cert_file {
+      let _ = builder.load_verify_locations(Some(&file), None);
+    }
+    for dir in probe.cert_dir {
+      let _ = builder.load_verify_locations(None, Some(&dir));
+    }
+  }
+  // BoringSSL only loads a bundle, which the distributions keep next to the hashed directory.
+  #[cfg(feature = "boringssl")]
+  {
+    let _ = builder.set_default_verify_paths();
+    if let Some(file) = probe.cert_file {
+      let _ = builder.set_ca_file(&file);
+    }
+  }
+
+  if ignore_tls_errors {
+    builder.set_verify(SslVerifyMode::NONE);
+  }
+
+  let connector = builder.build();
+  connectors.push((profile.clone(), ignore_tls_errors, connector.clone()));
+
+  Ok(connector)
+}
+
+/// Opens a TLS connection to `host` over the stream with the browser's `ClientHello`, offering the given ALPN protocols.
+pub(crate) async fn connect<S>(stream: S, host: &str, profile: &TlsProfile, ignore_tls_errors: bool, alpn: &[&str]) -> Result<TlsStream<S>, String>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  let mut config = connector(profile, ignore_tls_errors)
+    .and_then(|connector| connector.configure())
+    .map_err(|error| error.to_string())?;
+
+  config.set_verify_hostname(!ignore_tls_errors);
+  config.set_status_type(StatusType::OCSP).map_err(|error| error.to_string())?;
+  if !alpn.is_empty() {
+    config.set_alpn_protos(&alpn_wire_format(alpn)).map_err(|error| error.to_string())?;
+  }
+  #[cfg(feature = "boringssl")]
+  {
+    config.set_enable_ech_grease(profile.ech_grease);
+    // Chromium only offers ALPS for HTTP/2, so there's none on the HTTP/1.1 connections to HTTPS proxies.
+    if profile.application_settings && alpn.contains(&"h2") {
+      config.add_application_settings(b"h2").map_err(|error| error.to_string())?;
+    }
+  }
+
+  let ssl = config.into_ssl(host).map_err(|error| error.to_string())?;
+  let mut stream = SslStream::new(ssl, SyncStream { inner: stream, waker: None }).map_err(|error| error.to_string())?;
+
+  let result = std::future::poll_fn(|cx| {
+    stream.get_mut().waker = Some(cx.waker().clone());
+    match stream.connect() {
+      Ok(()) => Poll::Ready(Ok(())),
+      Err(error) if would_block(&error) => Poll::Pending,
+      Err(error) => Poll::Ready(Err(error)),
+    }
+  }).await;
+
+  match result {
+    Ok(()) => Ok(TlsStream { inner: stream }),
+    // The verification result tells why the certificate was rejected, the error itself doesn't.
+    Err(error) => match verify_error(stream.ssl()) {
+      None => Err(error.to_string()),
+      Some(reason) => Err(format!("{}: {}", error, reason)),
+    },
+  }
+}
+
+#[cfg(feature = "openssl")]
+fn verify_error(ssl: &ssl::SslRef) -> Option<&'static str> {
+  match ssl.verify_result() {
+    openssl::x509::X509VerifyResult::OK => None,
+    verify_result => Some(verify_result.error_string()),
+  }
+}
+
+#[cfg(feature = "boringssl")]
+fn verify_error(ssl: &ssl::SslRef) -> Option<&'static str> {
+  ssl.verify_result().err().map(|error| error.error_string())
+}
+
+/// Finds `protocol` in the client's length-prefixed ALPN list, returning it as a slice of that list.
+fn select_protocol<'a>(offered: &'a [u8], protocol: &[u8]) -> Option<&'a [u8]> {
+  let mut rest = offered;
+  while let Some((&length, tail)) = rest.split_first() {
+    let (candidate, tail) = tail.split_at_checked(length as usize)?;
+    if candidate == protocol {
+      return Some(candidate);
+    }
+    rest = tail;
+  }
+  None
+}
+
+fn alpn_wire_format(protocols: &[&str]) -> Vec<u8> {
+  protocols.iter().flat_map(|protocol| std::iter::once(protocol.len() as u8).chain(protocol.bytes())).collect()
+}
+
+/// CertificateAuthority issues the certificates for TLS servers run by `Retcher` itself.
+pub(crate) struct CertificateAuthority {
+  certificate: X509,
+  key: PKey<Private>,
+  /// The key of all the issued certificates.
+  leaf_key: PKey<Private>,
+  issued: Mutex<HashMap<String, X509>>,
+}
+
+impl CertificateAuthority {
+  /// Creates a new certificate authority with a fresh key.
+  pub(crate) fn new(name: &str) -> Result<Self, ErrorStack> {
+    let key = generate_key()?;
+    let certificate = build_certificate(name, &key, None)?;
+
+    Ok(CertificateAuthority {
+      certificate,
+      key,
+      leaf_key: generate_key()?,
+      issued: Mutex::new(HashMap::new()),
+    })
+  }
+
+  /// The (self-signed) certificate of the authority.
+  pub(crate) fn certificate(&self) -> &X509 {
+    &self.certificate
+  }
+
+  fn issue(&self, host: &str) -> Result<X509, ErrorStack> {
+    let mut issued = self.issued.lock().unwrap_or_else(|error| error.into_inner());
+
+    if let Some(certificate) = issued.get(host) {
+      return Ok(certificate.clone());
+    }
+
+    let certificate = build_certificate(host, &self.leaf_key, Some((&self.certificate, &self.key)))?;
+    issued.insert(host.to_string(), certificate.clone());
+
+    Ok(certificate)
+  }
+
+  /// Accepts a TLS connection over the stream with a certificate for `host`, selecting the ALPN protocol if the client offers it.
+  pub(crate) async fn accept<S>(&self, stream: S, host: &str, alpn: Option<&[u8]>) -> Result<TlsStream<S>, String>
+  where
+    S: AsyncRead + AsyncWrite + Unpin,
+  {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).map_err(|error| error.to_string())?;
+    builder.set_private_key(&self.leaf_key).map_err(|error| error.to_string())?;
+    let certificate = self.issue(host).map_err(|error| error.to_string())?;
+    builder.set_certificate(&certificate).map_err(|error| error.to_string())?;
+    #[cfg(feature = "openssl")]
+    builder.set_options(SslOptions::IGNORE_UNEXPECTED_EOF);
+
+    if let Some(protocol) = alpn {
+      let protocol = protocol.to_vec();
+      builder.set_alpn_select_callback(move |_, offered| select_protocol(offered, &protocol).ok_or(AlpnError::NOACK));
+    }
+
+    let ssl = ssl::Ssl::new(builder.build().context()).map_err(|error| error.to_string())?;
+    let mut stream = SslStream::new(ssl, SyncStream { inner: stream, waker: None }).map_err(|error| error.to_string())?;
+
+    std::future::poll_fn(|cx| {
+      stream.get_mut().waker = Some(cx.waker().clone());
+      match stream.accept() {
+        Ok(()) => Poll::Ready(Ok(())),
+        Err(error) if would_block(&error) => Poll::Pending,
+        Err(error) => Poll::Ready(Err(error.to_string())),
+      }
+    }).await?;
+
+    Ok(TlsStream { inner: stream })
+  }
+}
+
+fn generate_key() -> Result<PKey<Private>, ErrorStack> {
+  let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+  PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+/// Builds a certificate authority's certificate (without an issuer), or a server certificate for the host `name`.
+fn build_certificate(name: &str, key: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>) -> Result<X509, ErrorStack> {
+  let mut subject = X509NameBuilder::new()?;
+  subject.append_entry_by_nid(Nid::COMMONNAME, name)?;
+  let subject = subject.build();
+
+  let mut serial = BigNum::new()?;
+  serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+  let serial = serial.to_asn1_integer()?;
+
+  // Backdated a day, in case the clocks differ.
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() as i64).unwrap_or_default();
+  let not_before = Asn1Time::from_unix(now - 24 * 60 * 60)?;
+  let not_after = Asn1Time::days_from_now(365)?;
+
+  let mut builder = X509::builder()?;
+  builder.set_version(2)?;
+  builder.set_serial_number(&serial)?;
+  builder.set_subject_name(&subject)?;
+  builder.set_issuer_name(issuer.map_or(subject.as_ref(), |(certificate, _)| certificate.subject_name()))?;
+  builder.set_pubkey(key)?;
+  builder.set_not_before(&not_before)?;
+  builder.set_not_after(&not_after)?;
+
+  let subject_key_identifier = SubjectKeyIdentifier::new().build(&builder.x509v3_context(issuer.map(|(certificate, _)| certificate.as_ref()), None))?;
+  builder.append_extension(subject_key_identifier)?;
+
+  match issuer {
+    None => {
+      builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+      builder.append_extension(KeyUsage::new().critical().key_cert_sign().crl_sign().build()?)?;
+      builder.sign(key, MessageDigest::sha256())?;
+    }
+    Some((certificate, issuer_key)) => {
+      let mut alternative_name = SubjectAlternativeName::new();
+      match name.parse::<IpAddr>() {
+        Ok(_) => alternative_name.ip(name),
+        Err(_) => alternative_name.dns(name),
+      };
+
+      let context = builder.x509v3_context(Some(certificate), None);
+      let alternative_name = alternative_name.build(&context)?;
+      let authority_key_identifier = AuthorityKeyIdentifier::new().keyid(false).build(&context)?;
+
+      builder.append_extension(BasicConstraints::new().critical().build()?)?;
+      builder.append_extension(KeyUsage::new().critical().digital_signature().build()?)?;
+      builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+      builder.append_extension(alternative_name)?;
+      builder.append_extension(authority_key_identifier)?;
+      builder.sign(issuer_key, MessageDigest::sha256())?;
+    }
+  }
+
+  Ok(builder.build())
+}
+
+fn would_block(error: &ssl::Error) -> bool {
+  error.code() == ErrorCode::WANT_READ || error.code() == ErrorCode::WANT_WRITE
+}
+
+/// SyncStream lets OpenSSL (or BoringSSL) read and write the async stream, waking the task that polled the `TlsStream` when the stream is ready.
+struct SyncStream<S> {
+  inner: S,
+  waker: Option<Waker>,
+}
+
+impl<S> SyncStream<S> {
+  fn with_context<T>(&mut self, poll: impl FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>) -> io::Result<T>
+  where
+    S: Unpin,
+  {
+    let Some(waker) = self.waker.clone() else {
+      return Err(io::ErrorKind::WouldBlock.into());
+    };
+
+    match poll(Pin::new(&mut self.inner), &mut Context::from_waker(&waker)) {
+      Poll::Ready(result) => result,
+      Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+    }
+  }
+}
+
+impl<S: AsyncRead + Unpin> Read for SyncStream<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.with_context(|stream, cx| {
+      let mut buf = ReadBuf::new(buf);
+      stream.poll_read(cx, &mut buf).map_ok(|_| buf.filled().len())
+    })
+  }
+}
+
+impl<S: AsyncWrite + Unpin> Write for SyncStream<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.with_context(|stream, cx| stream.poll_write(cx, buf))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.with_context(|stream, cx| stream.poll_flush(cx))
+  }
+}
+
+/// TlsStream is an established OpenSSL connection over an async stream.
+pub(crate) struct TlsStream<S> {
+  inner: SslStream<SyncStream<S>>,
+}
+
+impl<S> TlsStream<S> {
+  /// The ALPN protocol selected by the server.
+  pub(crate) fn alpn_protocol(&self) -> Option<&[u8]> {
+    self.inner.ssl().selected_alpn_protocol()
+  }
+
+  fn poll_ssl<T>(&mut self, cx: &mut Context<'_>, operation: impl FnOnce(&mut SslStream<SyncStream<S>>) -> Result<T, ssl::Error>) -> Poll<io::Result<Option<T>>> {
+    self.inner.get_mut().waker = Some(cx.waker().clone());
+
+    match operation(&mut self.inner) {
+      Ok(value) => Poll::Ready(Ok(Some(value))),
+      Err(error) if error.code() == ErrorCode::ZERO_RETURN => Poll::Ready(Ok(None)),
+      // BoringSSL has no `SSL_OP_IGNORE_UNEXPECTED_EOF`, it reports a missing `close_notify` as a syscall error without an IO error.
+      #[cfg(feature = "boringssl")]
+      Err(error) if error.code() == ErrorCode::SYSCALL && error.io_error().is_none() => Poll::Ready(Ok(None)),
+      Err(error) if would_block(&error) => Poll::Pending,
+      Err(error) => Poll::Ready(Err(error.into_io_error().unwrap_or_else(io::Error::other))),
+    }
+  }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+
+    this.poll_ssl(cx, |stream| stream.ssl_read(buf.initialize_unfilled())).map_ok(|read| {
+      // `None` is the end of the stream.
+      buf.advance(read.unwrap_or(0));
+    })
+  }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    self.get_mut().poll_ssl(cx, |stream| stream.ssl_write(buf)).map_ok(|written| written.unwrap_or(0))
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    this.inner.get_mut().waker = Some(cx.waker().clone());
+
+    match this.inner.get_mut().flush() {
+      Ok(()) => Poll::Ready(Ok(())),
+      Err(error) if error.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+      Err(error) => Poll::Ready(Err(error)),
+    }
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+
+    match this.poll_ssl(cx, |stream| stream.shutdown()) {
+      Poll::Pending => return Poll::Pending,
+      // The peer may be gone already, the stream is closed either way.
+      Poll::Ready(_) => {}
+    }
+
+    Pin::new(&mut this.inner.get_mut().inner).poll_shutdown(cx)
+  }
+}
@@ -0,0 +1,799 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use reqwest::header::{HeaderMap, HeaderValue};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use url::Url;
+
+use super::errors::{FetchError, FetchErrorKind, FetchPhase};
+use super::{http1, http2};
+use super::proxy_auth::{self, AuthCache, ProxyCredentials};
+use super::profile::BrowserProfile;
+use super::tls::{self, CertificateAuthority};
+
+/// How many `CONNECT` requests are sent for one tunnel at most - the first one, and the answers to (possibly stale nonce) `407` challenges.
+const MAX_CONNECT_ATTEMPTS: usize = 3;
+
+/// The longest request or response head (or chunk line, see `http1::relay`) the relay reads.
+pub(crate) const MAX_HEAD_LENGTH: usize = 64 * 1024;
+
+/// The longest `407` body the relay skips to answer the challenge on the same connection. It opens a new one past it.
+const MAX_CHALLENGE_BODY_LENGTH: usize = 1024 * 1024;
+
+/// The header with the tunnel config credential (see `Registration`) of the plain HTTP requests `reqwest` forwards to the relay.
+pub(crate) const RELAY_CONFIG: &str = "x-retcher-relay-config";
+
+/// The header `Retcher` lists the names of the request headers in, in the browser's order and casing.
+///
+/// Only the relay knows the HTTP version the server negotiated, so the names of the headers the browser only sends over one version are
+/// prefixed with it, e.g. `h1:Host` (see `header_order`). The relay puts the headers in this order (see `order_headers`), drops the ones
+/// of the other version, and removes it, like the other private headers.
+pub(crate) const HEADER_ORDER: &str = "x-retcher-header-order";
+
+/// The header with the request's destination (see `Destination::as_str`), which the HTTP/2 relay gives the browser's priority for.
+pub(crate) const DESTINATION: &str = "x-retcher-destination";
+
+/// The header marking the response the relay sends when it can't forward a plain HTTP request (see `RelayRequest::forward_failure`).
+pub(crate) const RELAY_FAILURE: &str = "x-retcher-relay-failure";
+
+/// The header with the ID of the `RelayRequest` a plain HTTP request is forwarded for.
+pub(crate) const RELAY_REQUEST: &str = "x-retcher-relay-request";
+
+tokio::task_local! {
+  /// The ID of the `RelayRequest` being sent, for the tunnels `reqwest` opens meanwhile (see `Registration::tunnel_url`).
+  static REQUEST_ID: u64;
+}
+
+/// TunnelConfig describes how the relay connects to the servers.
+#[derive(Debug, Clone)]
+pub(crate) struct TunnelConfig {
+  /// The HTTP(S) or SOCKS5 proxy URL (including the credentials) to connect through, `None` to connect directly.
+  pub proxy: Option<Url>,
+  /// The profile of the browser whose `CONNECT` requests, TLS `ClientHello` and HTTP/2 connections are impersonated.
+  pub profile: Arc<BrowserProfile>,
+  pub ignore_tls_errors: bool,
+  /// How long opening a connection to the server (see `connect_within`) may take.
+  pub connect_timeout: Duration,
+  /// The proxy challenges of the `Retcher` the config is for.
+  pub auth_cache: Arc<AuthCache>,
+}
+
+/// The relay makes the connections for `reqwest`.
+///
+/// `reqwest` can't shape the TLS `ClientHello` nor the `CONNECT` request (it sends lowercase headers in its own order), and it can't answer a `Digest` challenge.
+/// So `reqwest` tunnels all the HTTPS requests through this loopback relay instead.
+/// The relay connects to the server (through the real proxy, if any) and opens the TLS connection like the browser would.
+/// It then terminates `reqwest`'s TLS connection with a certificate from its own authority (the only one `reqwest` trusts), and passes the decrypted data through.
+///
+/// `reqwest` doesn't keep the order (nor the casing) of the request headers either, so plain HTTP requests are forwarded to the relay as to a proxy,
+/// and the relay rewrites the requests of both kinds with the browser's header order (see `HEADER_ORDER`).
+///
+/// The relay runs on its own thread and runtime, as it's shared by all the `Retcher` instances.
+/// It listens on the loopback, where any local process can connect to it, so it only uses the configs `reqwest` has the credentials of.
+struct Relay {
+  address: SocketAddr,
+  /// The registered tunnel configs by their ID (see `Registration`).
+  configs: Mutex<HashMap<u64, RegisteredConfig>>,
+  next_id: AtomicU64,
+  authority: CertificateAuthority,
+  /// The certificate of `authority`, for `reqwest`.
+  certificate: reqwest::Certificate,
+  next_request_id: AtomicU64,
+  /// Why the connections of the pending requests (by their ID, see `RelayRequest`) failed, if they did.
+  /// `reqwest` only sees a failed tunnel (or a `RELAY_FAILURE` response).
+  failures: Mutex<HashMap<u64, Option<TargetFailure>>>,
+}
+
+/// TargetFailure is the reason the relay couldn't connect to the server (as opposed to a failing proxy, unless the request was forwarded).
+#[derive(Debug, Clone)]
+struct TargetFailure {
+  kind: FetchErrorKind,
+  message: String,
+  source: Option<Arc<std::io::Error>>,
+}
+
+impl TargetFailure {
+  fn new(kind: FetchErrorKind, message: impl ToString) -> Self {
+    TargetFailure { kind, message: message.to_string(), source: None }
+  }
+
+  fn into_error(self, url: &Url) -> FetchError {
+    let error = FetchError::from_relay(self.kind, url.as_str(), &self.message);
+
+    match self.source {
+      Some(source) => error.with_source(source),
+      None => error,
+    }
+  }
+
+  fn from_io(error: std::io::Error) -> Self {
+    let kind = match error.kind() {
+      std::io::ErrorKind::ConnectionRefused => FetchErrorKind::ConnectionRefused,
+      std::io::ErrorKind::TimedOut => FetchErrorKind::Timeout,
+      _ => FetchErrorKind::Connection,
+    };
+
+    TargetFailure { kind, message: error.to_string(), source: Some(Arc::new(error)) }
+  }
+}
+
+/// TunnelError is the reason the relay couldn't open a tunnel.
+enum TunnelError {
+  /// The proxy failed, `reqwest` gets its status line.
+  Proxy(String),
+  /// The server couldn't be reached (or the proxy reported so).
+  Target(TargetFailure),
+}
+
+const BAD_GATEWAY: &str = "HTTP/1.1 502 Bad Gateway";
+
+/// The response to a request without the credential of a registered config.
+const UNAUTHORIZED: &str = "HTTP/1.1 407 Proxy Authentication Required";
+
+/// How many random bytes the secret of a `Registration` has.
+const SECRET_LENGTH: usize = 16;
+
+struct RegisteredConfig {
+  /// The secret of the registration, in hex.
+  secret: String,
+  config: TunnelConfig,
+}
+
+static RELAY: OnceLock<Result<Relay, String>> = OnceLock::new();
+
+fn relay() -> Result<&'static Relay, FetchError> {
+  let relay = RELAY.get_or_init(|| {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")
+      .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+      .map_err(|error| error.to_string())?;
+    let address = listener.local_addr().map_err(|error| error.to_string())?;
+    let authority = CertificateAuthority::new("Retcher relay").map_err(|error| error.to_string())?;
+    let certificate = authority.certificate().to_der()
+      .map_err(|error| error.to_string())
+      .and_then(|der| reqwest::Certificate::from_der(&der).map_err(|error| error.to_string()))?;
+
+    std::thread::Builder::new()
+      .name("retcher-proxy-relay".into())
+      .spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().expect("the proxy relay runtime can be created");
+        runtime.block_on(serve(listener));
+      })
+      .map_err(|error| error.to_string())?;
+
+    Ok(Relay {
+      address,
+      configs: Mutex::new(HashMap::new()),
+      next_id: AtomicU64::new(0),
+      authority,
+      certificate,
+      next_request_id: AtomicU64::new(0),
+      failures: Mutex::new(HashMap::new()),
+    })
+  });
+
+  relay.as_ref().map_err(|error| FetchError::new(
+    FetchErrorKind::Request,
+    FetchPhase::Setup,
+    None,
+    format!("Couldn't start the HTTPS relay: {}", error),
+  ))
+}
+
+/// Returns the certificate of the relay's authority, the root `reqwest` has to trust.
+pub(crate) fn relay_certificate() -> Result<reqwest::Certificate, FetchError> {
+  Ok(relay()?.certificate.clone())
+}
+
+/// Registration is a tunnel config registered with the relay. The config is unregistered when the registration is dropped.
+///
+/// `reqwest` sends the registration's credential - the config ID and a random 128-bit secret - with every tunnel and forwarded request,
+/// the relay refuses the requests without a valid one.
+pub(crate) struct Registration {
+  id: u64,
+  /// The secret, in hex.
+  secret: String,
+  address: SocketAddr,
+}
+
+impl Registration {
+  /// The credential identifying the config to the relay, `<ID>.<secret>`.
+  fn credential(&self) -> String {
+    format!("{}.{}", self.id, self.secret)
+  }
+
+  /// Returns the relay URL `reqwest` should use as the proxy for HTTPS requests made with the config.
+  ///
+  /// `reqwest` picks the proxy when it opens a connection, while sending the request that needs it (see `RelayRequest::send`),
+  /// so the URL has the request's ID as the password.
+  pub(crate) fn tunnel_url(&self) -> Url {
+    let credentials = match REQUEST_ID.try_with(|id| *id) {
+      Ok(request_id) => format!("{}:{}", self.credential(), request_id),
+      Err(_) => self.credential(),
+    };
+
+    Url::parse(&format!("http://{}@{}", credentials, self.address)).expect("the relay URL is valid")
+  }
+
+  /// Returns the relay URL `reqwest` should use as the proxy for plain HTTP requests made with the config, and the headers to send it.
+  ///
+  /// The forwarded requests can't carry the credential as the proxy credentials, `Retcher` sends the real proxy's ones in `Proxy-Authorization`.
+  pub(crate) fn forwarding(&self) -> (Url, HeaderMap) {
+    let mut headers = HeaderMap::new();
+    headers.insert(RELAY_CONFIG, HeaderValue::from_str(&self.credential()).expect("the credential is a valid header value"));
+
+    (Url::parse(&format!("http://{}", self.address)).expect("the relay URL is valid"), headers)
+  }
+}
+
+impl Drop for Registration {
+  fn drop(&mut self) {
+    if let Some(Ok(relay)) = RELAY.get() {
+      relay.configs.lock().unwrap_or_else(|error| error.into_inner()).remove(&self.id);
+    }
+  }
+}
+
+/// Registers the config with the relay, for as long as the returned registration is kept.
+pub(crate) fn register(config: TunnelConfig) -> Result<Arc<Registration>, FetchError> {
+  let relay = relay()?;
+
+  let id = relay.next_id.fetch_add(1, Ordering::Relaxed);
+  let secret: String = rand::random::<[u8; SECRET_LENGTH]>().iter().map(|byte| format!("{:02x}", byte)).collect();
+  relay.configs.lock().unwrap_or_else(|error| error.into_inner()).insert(id, RegisteredConfig { secret: secret.clone(), config });
+
+  Ok(Arc::new(Registration { id, secret, address: relay.address }))
+}
+
+/// Returns the config registered with the credential, `None` if the credential isn't a valid one.
+fn tunnel_config(credential: &str) -> Option<TunnelConfig> {
+  let Some(Ok(relay)) = RELAY.get() else { return None };
+  let (id, secret) = credential.split_once('.')?;
+
+  let configs = relay.configs.lock().unwrap_or_else(|error| error.into_inner());
+  let registered = configs.get(&id.parse().ok()?)?;
+
+  // The secret is compared in constant time, so its prefixes can't be guessed by timing the relay.
+  let valid = secret.len() == registered.secret.len() && tls::openssl::memcmp::eq(secret.as_bytes(), registered.secret.as_bytes());
+  valid.then(|| registered.config.clone())
+}
+
+async fn serve(listener: std::net::TcpListener) {
+  let Ok(listener) = TcpListener::from_std(listener) else { return };
+
+  loop {
+    if let Ok((socket, _)) = listener.accept().await {
+      tokio::spawn(relay_connection(socket));
+    }
+  }
+}
+
+/// RelayRequest ties the failures of the relay's connections to the request they were made for.
+///
+/// The relay records why it couldn't connect to the server under the request's ID, which `reqwest` sends in the tunnel's proxy credentials
+/// (see `Registration::tunnel_url`) or in the `RELAY_REQUEST` header of a forwarded request. The record is dropped with the request.
+pub(crate) struct RelayRequest {
+  id: u64,
+}
+
+impl RelayRequest {
+  pub(crate) fn new() -> Result<Self, FetchError> {
+    let relay = relay()?;
+
+    let id = relay.next_request_id.fetch_add(1, Ordering::Relaxed);
+    relay.failures.lock().unwrap_or_else(|error| error.into_inner()).insert(id, None);
+
+    Ok(RelayRequest { id })
+  }
+
+  /// The `RELAY_REQUEST` header value.
+  pub(crate) fn header(&self) -> HeaderValue {
+    HeaderValue::from(self.id)
+  }
+
+  /// Sends the request, with the request's ID in the tunnels `reqwest` opens for it.
+  pub(crate) async fn send<F: Future>(&self, request: F) -> F::Output {
+    REQUEST_ID.scope(self.id, request).await
+  }
+
+  /// Takes the reason the relay couldn't connect to the server for the request.
+  fn take_failure(&self) -> Option<TargetFailure> {
+    let relay = RELAY.get()?.as_ref().ok()?;
+    relay.failures.lock().unwrap_or_else(|error| error.into_inner()).get_mut(&self.id)?.take()
+  }
+
+  /// Replaces a failed tunnel error of an HTTPS request with the reason the relay couldn't connect to the server, if it was the server's fault.
+  pub(crate) fn target_failure(&self, error: FetchError, url: &Url) -> FetchError {
+    if error.kind != FetchErrorKind::Proxy || url.scheme() != "https" {
+      return error;
+    }
+
+    match self.take_failure() {
+      Some(failure) => failure.into_error(url),
+      None => error,
+    }
+  }
+
+  /// Returns the reason the relay couldn't forward a plain HTTP request, for its `RELAY_FAILURE` response.
+  pub(crate) fn forward_failure(&self, url: &Url) -> FetchError {
+    match self.take_failure() {
+      Some(failure) => failure.into_error(url),
+      None => FetchError::from_relay(FetchErrorKind::Connection, url.as_str(), "the relay couldn't forward the request"),
+    }
+  }
+}
+
+impl Drop for RelayRequest {
+  fn drop(&mut self) {
+    if let Some(Ok(relay)) = RELAY.get() {
+      relay.failures.lock().unwrap_or_else(|error| error.into_inner()).remove(&self.id);
+    }
+  }
+}
+
+/// Records why the relay couldn't connect to the server for the request with the ID, unless it's no longer pending.
+fn record_failure(relay: &Relay, request_id: Option<u64>, failure: TargetFailure) {
+  let Some(request_id) = request_id else { return };
+
+  if let Some(pending) = relay.failures.lock().unwrap_or_else(|error| error.into_inner()).get_mut(&request_id) {
+    *pending = Some(failure);
+  }
+}
+
+/// Handles one `CONNECT` request (or a connection with forwarded requests) from `reqwest`.
+async fn relay_connection(client: TcpStream) {
+  // The buffered bytes after the head (e.g. the body of a forwarded request) stay in the reader for the rest of the relay.
+  let mut client = BufReader::new(client);
+  let Some(head) = read_head(&mut client).await else { return };
+  let request = parse_head(&head);
+
+  if request.target().is_none() {
+    return forward_connection(client, head, request).await;
+  }
+
+  let (Some(target), Some(Ok(relay))) = (request.target(), RELAY.get()) else {
+    let _ = client.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+    return;
+  };
+  let (credential, request_id) = request.relay_credentials().unzip();
+  let Some(config) = credential.as_deref().and_then(tunnel_config) else {
+    let _ = client.write_all(format!("{}\r\n\r\n", UNAUTHORIZED).as_bytes()).await;
+    return;
+  };
+  let host = target_host(&target);
+
+  let upstream = connect_within(&config, async {
+    let upstream = open_tunnel(&config, &target).await?;
+    let alpn: Vec<&str> = config.profile.tls.alpn.iter().map(String::as_str).collect();
+    tls::connect(upstream, host, &config.profile.tls, config.ignore_tls_errors, &alpn)
+      .await
+      .map_err(|message| TunnelError::Target(TargetFailure::new(FetchErrorKind::Tls, message)))
+  }).await;
+
+  // `reqwest` reports a failed tunnel as a proxy error either way, `RelayRequest::target_failure` tells it what happened to the server.
+  let upstream = match upstream {
+    Ok(upstream) => upstream,
+    Err(TunnelError::Proxy(status_line)) => {
+      let _ = client.write_all(format!("{}\r\n\r\n", status_line).as_bytes()).await;
+      return;
+    }
+    Err(TunnelError::Target(failure)) => {
+      record_failure(relay, request_id.flatten(), failure);
+      let _ = client.write_all(format!("{}\r\n\r\n", BAD_GATEWAY).as_bytes()).await;
+      return;
+    }
+  };
+
+  if client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await.is_err() {
+    return;
+  }
+
+  // `reqwest` has to speak the protocol the server selected.
+  let protocol = upstream.alpn_protocol().map(<[u8]>::to_vec);
+  let Ok(client) = relay.authority.accept(client, host, protocol.as_deref()).await else { return };
+  if protocol.as_deref() == Some(b"h2") {
+    let _ = http2::relay(client, upstream, &config.profile.http2).await;
+  } else {
+    let _ = http1::relay(client, upstream, None, false).await;
+  }
+}
+
+/// Handles a connection with plain HTTP requests `reqwest` forwards to the relay, starting with the one with the given head.
+///
+/// The requests go to the HTTP(S) proxy as they are (with the absolute URL as the target), or to the server (directly or through
+/// the SOCKS5 proxy) with the path as the target. `reqwest` keeps one connection per server, so they all go to the first request's one.
+///
+/// Like the browsers' (which don't speak h2c), the plain HTTP requests are HTTP/1.1 ones, so their `HEADER_ORDER` is applied as such.
+async fn forward_connection(mut client: BufReader<TcpStream>, head: String, request: Head) {
+  let url = request.start_line.split(' ').nth(1).and_then(|target| Url::parse(target).ok());
+
+  let (Some(url), Some(Ok(relay))) = (url, RELAY.get()) else {
+    let _ = client.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+    return;
+  };
+  let Some(config) = request.header_values(RELAY_CONFIG).next().and_then(tunnel_config) else {
+    let _ = client.write_all(format!("{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", UNAUTHORIZED).as_bytes()).await;
+    return;
+  };
+  let target = format!("{}:{}", url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or(80));
+
+  let upstream = connect_within(&config, async {
+    match &config.proxy {
+      Some(proxy) if !proxy.scheme().starts_with("socks") => connect_proxy(&config, proxy)
+        .await
+        .map_err(|error| TunnelError::Target(TargetFailure { kind: FetchErrorKind::Proxy, ..TargetFailure::from_io(error) })),
+      _ => open_tunnel(&config, &target).await,
+    }
+  }).await;
+
+  // `reqwest` takes any response for the server's one, `RelayRequest::forward_failure` tells it what happened instead.
+  let upstream = match upstream {
+    Ok(upstream) => upstream,
+    Err(error) => {
+      let failure = match error {
+        TunnelError::Proxy(status_line) => TargetFailure::new(FetchErrorKind::Proxy, status_line),
+        TunnelError::Target(failure) => failure,
+      };
+      let request_id = request.header_values(RELAY_REQUEST).next().and_then(|id| id.parse().ok());
+      record_failure(relay, request_id, failure);
+
+      let response = format!("{}\r\n{}: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", BAD_GATEWAY, RELAY_FAILURE);
+      let _ = client.write_all(response.as_bytes()).await;
+      return;
+    }
+  };
+
+  let origin_form = config.proxy.as_ref().is_none_or(|proxy| proxy.scheme().starts_with("socks"));
+  let _ = http1::relay(client, upstream, Some(head), origin_form).await;
+}
+
+/// Opens the connection (through the proxy, TLS handshake included), giving up after the config's `connect_timeout`.
+///
+/// Without it, a blackholed server or proxy would leave the request hanging until the OS gives up on the connection, if it ever does.
+async fn connect_within<T>(config: &TunnelConfig, connect: impl Future<Output = Result<T, TunnelError>>) -> Result<T, TunnelError> {
+  tokio::time::timeout(config.connect_timeout, connect).await.unwrap_or_else(|_| {
+    let message = format!("the connection wasn't open within {} ms", config.connect_timeout.as_millis());
+    Err(TunnelError::Target(TargetFailure::new(FetchErrorKind::Timeout, message)))
+  })
+}
+
+/// The host of a `host:port` target, without the brackets of an IPv6 address.
+fn target_host(target: &str) -> &str {
+  let host = target.rsplit_once(':').map_or(target, |(host, _)| host);
+  host.trim_start_matches('[').trim_end_matches(']')
+}
+
+trait ProxyStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ProxyStream for T {}
+
+/// Opens a connection to `target` (`host:port`), through the proxy if there's one.
+async fn open_tunnel(config: &TunnelConfig, target: &str) -> Result<Box<dyn ProxyStream>, TunnelError> {
+  match &config.proxy {
+    None => Ok(Box::new(connect_directly(target).await.map_err(TunnelError::Target)?)),
+    Some(proxy) if proxy.scheme().starts_with("socks") => socks_tunnel(proxy, target).await,
+    Some(proxy) => http_tunnel(config, proxy, target).await.map_err(TunnelError::Proxy),
+  }
+}
+
+async fn connect_directly(target: &str) -> Result<TcpStream, TargetFailure> {
+  let addresses = lookup_host(target).await.map_err(|error| TargetFailure::new(FetchErrorKind::Dns, error))?;
+
+  let mut last_error = None;
+  for address in addresses {
+    match TcpStream::connect(address).await {
+      Ok(stream) => return Ok(stream),
+      Err(error) => last_error = Some(error),
+    }
+  }
+
+  match last_error {
+    Some(error) => Err(TargetFailure::from_io(error)),
+    None => Err(TargetFailure::new(FetchErrorKind::Dns, format!("no addresses found for {}", target))),
+  }
+}
+
+/// Opens a tunnel to `target` through the HTTP(S) proxy, answering its authentication challenges.
+///
+/// Returns the status line to report to `reqwest` on failure.
+async fn http_tunnel(config: &TunnelConfig, proxy: &Url, target: &str) -> Result<Box<dyn ProxyStream>, String> {
+  let credentials = ProxyCredentials::from_url(proxy);
+  let cache_key = credentials.as_ref().map(|credentials| proxy_auth::cache_key(proxy, credentials));
+
+  let mut authorization = match (&credentials, &cache_key) {
+    (Some(credentials), Some(key)) => config.auth_cache.cached_authorization(key, credentials, "CONNECT", target),
+    _ => None,
+  };
+  let mut connection: Option<BufReader<Box<dyn ProxyStream>>> = None;
+
+  for _ in 0..MAX_CONNECT_ATTEMPTS {
+    // The tunnel keeps the reader, in case the proxy sent more than the response head at once.
+    let mut stream = match connection.take() {
+      Some(stream) => stream,
+      None => BufReader::new(connect_proxy(config, proxy).await.map_err(|_| BAD_GATEWAY.to_string())?),
+    };
+
+    let request = connect_request(&config.profile, target, authorization.as_deref());
+    stream.write_all(request.as_bytes()).await.map_err(|_| BAD_GATEWAY.to_string())?;
+
+    let head = read_head(&mut stream).await.ok_or_else(|| BAD_GATEWAY.to_string())?;
+    let response = parse_head(&head);
+
+    match response.status() {
+      Some(200..=299) => return Ok(Box::new(stream)),
+      Some(407) => {}
+      _ => return Err(response.start_line.clone()),
+    }
+
+    let (Some(credentials), Some(key)) = (&credentials, &cache_key) else {
+      return Err(response.start_line.clone());
+    };
+
+    authorization = config.auth_cache.answer_challenge(key, credentials, response.header_values("proxy-authenticate"), "CONNECT", target);
+    if authorization.is_none() {
+      return Err(response.start_line.clone());
+    }
+
+    // The challenge can be answered on the same connection, if the proxy keeps it open and the body can be skipped.
+    if let Some(length) = response.content_length().filter(|&length| response.keeps_alive() && length <= MAX_CHALLENGE_BODY_LENGTH) {
+      let length = length as u64;
+      if tokio::io::copy(&mut (&mut stream).take(length), &mut tokio::io::sink()).await.ok() == Some(length) {
+        connection = Some(stream);
+      }
+    }
+  }
+
+  Err("HTTP/1.1 407 Proxy Authentication Required".to_string())
+}
+
+async fn connect_proxy(config: &TunnelConfig, proxy: &Url) -> std::io::Result<Box<dyn ProxyStream>> {
+  let host = proxy.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+  let port = proxy.port_or_known_default().unwrap_or(80);
+  let stream = TcpStream::connect((host, port)).await?;
+
+  if proxy.scheme() != "https" {
+    return Ok(Box::new(stream));
+  }
+
+  // The browser talks to an HTTPS proxy with its own `ClientHello` as well, but the tunnels are HTTP/1.1 ones.
+  let stream = tls::connect(stream, host, &config.profile.tls, config.ignore_tls_errors, &["http/1.1"])
+    .await
+    .map_err(std::io::Error::other)?;
+
+  Ok(Box::new(stream))
+}
+
+/// Opens a tunnel to `target` through the SOCKS5 proxy (RFC 1928), authenticating with the username and password (RFC 1929) from its URL.
+///
+/// With `socks5`, the target is resolved locally, with `socks5h` by the proxy.
+async fn socks_tunnel(proxy: &Url, target: &str) -> Result<Box<dyn ProxyStream>, TunnelError> {
+  let failed = |_| TunnelError::Proxy(BAD_GATEWAY.to_string());
+  // `ProxyOptions` doesn't let longer ones through, see `MAX_SOCKS_FIELD_LENGTH`.
+  let length = |field: &str| u8::try_from(field.len())
+    .map_err(|_| TunnelError::Target(TargetFailure::new(FetchErrorKind::Proxy, "a SOCKS5 request field is longer than 255 bytes")));
+
+  let host = target_host(target);
+  let port: u16 = target.rsplit_once(':').and_then(|(_, port)| port.parse().ok()).unwrap_or(443);
+
+  let mut address = match host.parse::<std::net::IpAddr>() {
+    Ok(std::net::IpAddr::V4(ip)) => [&[1][..], &ip.octets()].concat(),
+    Ok(std::net::IpAddr::V6(ip)) => [&[4][..], &ip.octets()].concat(),
+    Err(_) if proxy.scheme() == "socks5h" => [&[3, length(host)?][..], host.as_bytes()].concat(),
+    Err(_) => {
+      let resolved = lookup_host((host, port)).await
+        .map_err(|error| TunnelError::Target(TargetFailure::new(FetchErrorKind::Dns, error)))?
+        .next()
+        .ok_or_else(|| TunnelError::Target(TargetFailure::new(FetchErrorKind::Dns, format!("no addresses found for {}", target))))?;
+
+      match resolved.ip() {
+        std::net::IpAddr::V4(ip) => [&[1][..], &ip.octets()].concat(),
+        std::net::IpAddr::V6(ip) => [&[4][..], &ip.octets()].concat(),
+      }
+    }
+  };
+  address.extend_from_slice(&port.to_be_bytes());
+
+  let proxy_host = proxy.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+  let mut stream = TcpStream::connect((proxy_host, proxy.port_or_known_default().unwrap_or(1080))).await.map_err(failed)?;
+
+  let credentials = ProxyCredentials::from_url(proxy);
+  let method = if credentials.is_some() { 2 } else { 0 };
+  stream.write_all(&[5, 1, method]).await.map_err(failed)?;
+
+  let mut reply = [0u8; 2];
+  stream.read_exact(&mut reply).await.map_err(failed)?;
+  if reply != [5, method] {
+    return Err(TunnelError::Proxy(BAD_GATEWAY.to_string()));
+  }
+
+  if let Some(credentials) = credentials {
+    let mut request = vec![1, length(&credentials.username)?];
+    request.extend_from_slice(credentials.username.as_bytes());
+    request.push(length(&credentials.password)?);
+    request.extend_from_slice(credentials.password.as_bytes());
+    stream.write_all(&request).await.map_err(failed)?;
+
+    stream.read_exact(&mut reply).await.map_err(failed)?;
+    if reply[1] != 0 {
+      return Err(TunnelError::Proxy("HTTP/1.1 407 Proxy Authentication Required".to_string()));
+    }
+  }
+
+  stream.write_all(&[&[5, 1, 0][..], &address].concat()).await.map_err(failed)?;
+
+  // The reply has the same shape as the request, with the address the proxy bound.
+  let mut reply = [0u8; 4];
+  stream.read_exact(&mut reply).await.map_err(failed)?;
+  let bound_length = match reply[3] {
+    1 => 4,
+    4 => 16,
+    _ => stream.read_u8().await.map_err(failed)? as usize,
+  };
+  let mut bound = vec![0u8; bound_length + 2];
+  stream.read_exact(&mut bound).await.map_err(failed)?;
+
+  if reply[1] != 0 {
+    return Err(TunnelError::Proxy(BAD_GATEWAY.to_string()));
+  }
+
+  Ok(Box::new(stream))
+}
+
+/// Builds the `CONNECT` request the browser would send to open a tunnel to `target`.
+pub(crate) fn connect_request(profile: &BrowserProfile, target: &str, authorization: Option<&str>) -> String {
+  let user_agent = profile.user_agent();
+  let mut headers: Vec<(&str, &str)> = if profile.browser.is_chromium() {
+    vec![
+      ("Host", target),
+      ("Proxy-Connection", "keep-alive"),
+      ("User-Agent", &user_agent),
+    ]
+  } else {
+    vec![
+      ("User-Agent", &user_agent),
+      ("Proxy-Connection", "keep-alive"),
+      ("Connection", "keep-alive"),
+      ("Host", target),
+    ]
+  };
+
+  // All the browsers append the credentials last.
+  if let Some(authorization) = authorization {
+    headers.push(("Proxy-Authorization", authorization));
+  }
+
+  let mut request = format!("CONNECT {} HTTP/1.1\r\n", target);
+  for (name, value) in headers {
+    request.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  request.push_str("\r\n");
+
+  request
+}
+
+/// Reads a request or response head (up to and including the empty line), leaving anything after it in the reader.
+pub(crate) async fn read_head<S: AsyncBufRead + Unpin + ?Sized>(stream: &mut S) -> Option<String> {
+  let mut head = Vec::new();
+
+  while !head.ends_with(b"\r\n\r\n") {
+    // Past the limit, the head is too long even if the line ends right after it.
+    let limit = (MAX_HEAD_LENGTH + 1 - head.len()) as u64;
+    match (&mut *stream).take(limit).read_until(b'\n', &mut head).await {
+      Ok(0) | Err(_) => return None,
+      Ok(_) if head.len() > MAX_HEAD_LENGTH => return None,
+      Ok(_) => {}
+    }
+  }
+
+  Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// A parsed HTTP/1.1 message head.
+struct Head {
+  start_line: String,
+  headers: Vec<(String, String)>,
+}
+
+fn parse_head(head: &str) -> Head {
+  let mut lines = head.split("\r\n");
+  let start_line = lines.next().unwrap_or_default().to_string();
+
+  let headers = lines
+    .filter_map(|line| line.split_once(':'))
+    .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+    .collect();
+
+  Head { start_line, headers }
+}
+
+impl Head {
+  fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+    self.headers.iter().filter(move |(key, _)| key == name).map(|(_, value)| value.as_str())
+  }
+
+  /// The request target of a `CONNECT` request.
+  fn target(&self) -> Option<String> {
+    match self.start_line.split(' ').collect::<Vec<_>>()[..] {
+      ["CONNECT", target, _] => Some(target.to_string()),
+      _ => None,
+    }
+  }
+
+  /// The relay tunnel config credential (see `Registration`) and the ID of the `RelayRequest` (if any),
+  /// sent by `reqwest` as the proxy username and password.
+  fn relay_credentials(&self) -> Option<(String, Option<u64>)> {
+    let credentials = self.header_values("proxy-authorization").next()?.strip_prefix("Basic ")?;
+    let credentials = String::from_utf8(BASE64_STANDARD.decode(credentials).ok()?).ok()?;
+
+    match credentials.split_once(':') {
+      Some((credential, request_id)) => Some((credential.to_string(), request_id.parse().ok())),
+      None => Some((credentials, None)),
+    }
+  }
+
+  fn status(&self) -> Option<u16> {
+    self.start_line.split(' ').nth(1)?.parse().ok()
+  }
+
+  fn content_length(&self) -> Option<usize> {
+    self.header_values("content-length").next()?.parse().ok()
+  }
+
+  fn keeps_alive(&self) -> bool {
+    !self.header_values("connection")
+      .chain(self.header_values("proxy-connection"))
+      .any(|value| value.eq_ignore_ascii_case("close"))
+      && self.start_line.starts_with("HTTP/1.1")
+  }
+}
+
+/// Returns the `HEADER_ORDER` value listing the header names, with the HTTP version of the ones only sent over HTTP/1.1 (`Some(true)`)
+/// or HTTP/2 (`Some(false)`).
+pub(crate) fn header_order(order: &[(String, Option<bool>)]) -> String {
+  order
+    .iter()
+    .map(|(name, http1)| match http1 {
+      Some(true) => format!("h1:{}", name),
+      Some(false) => format!("h2:{}", name),
+      None => name.clone(),
+    })
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Puts the headers in the order of the names listed in the `HEADER_ORDER` header, with their casing.
+///
+/// The listed headers of the other HTTP version than the connection's one (see `http1`) are dropped.
+/// The headers that aren't listed (added by `reqwest`, e.g. `Proxy-Authorization`) follow in their original order.
+pub(crate) fn order_headers<'a, V>(mut headers: Vec<(&'a str, V)>, order: &'a str, http1: bool) -> Vec<(&'a str, V)> {
+  let mut ordered = Vec::with_capacity(headers.len());
+
+  for entry in order.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+    // Header names can't have a colon in them.
+    let (name, only_http1) = match entry.split_once(':') {
+      Some(("h1", name)) => (name, Some(true)),
+      Some(("h2", name)) => (name, Some(false)),
+      _ => (entry, None),
+    };
+    let sent = only_http1.is_none_or(|only_http1| only_http1 == http1);
+
+    while let Some(index) = headers.iter().position(|(header, _)| header.eq_ignore_ascii_case(name)) {
+      let (_, value) = headers.remove(index);
+      if sent {
+        ordered.push((name, value));
+      }
+    }
+  }
+
+  ordered.extend(headers);
+  ordered
+}
+
+/// Whether the header is one of the private ones `Retcher` sends to the relay.
+pub(crate) fn is_private_header(name: &str) -> bool {
+  [RELAY_CONFIG, RELAY_REQUEST, HEADER_ORDER, DESTINATION].iter().any(|private| name.eq_ignore_ascii_case(private))
+}
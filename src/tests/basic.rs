@@ -5,6 +5,7 @@ fn browser_param_passing() {
     let retcher = Retcher::new(EngineOptions {
         browser: Some(Browser::Chrome),
         ignore_tls_errors: None,
+        ..Default::default()
     });
 
     assert_eq!(retcher.browser, Browser::Chrome);
@@ -12,6 +13,7 @@ fn browser_param_passing() {
     let retcher = Retcher::new(EngineOptions {
         browser: Some(Browser::Firefox),
         ignore_tls_errors: None,
+        ..Default::default()
     });
 
     assert_eq!(retcher.browser, Browser::Firefox);
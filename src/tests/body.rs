@@ -0,0 +1,76 @@
+use crate::retcher::retcher::{Browser, EngineOptions, FetchErrorKind, FetchPhase, FetchResponse, Retcher};
+use super::server::get_server;
+use super::server::body::CZECH_TEXT;
+
+async fn get(path: &str) -> FetchResponse {
+    get_server().await;
+
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Firefox),
+        ignore_tls_errors: None,
+        ..Default::default()
+    });
+
+    retcher.retch(format!("http://127.0.0.1:8000{}", path), None).await.unwrap()
+}
+
+#[tokio::test]
+async fn text_uses_charset_from_content_type() {
+    for charset in ["utf-8", "iso-8859-2", "windows-1250"] {
+        let mut response = get(&format!("/body/text/{}", charset)).await;
+
+        assert_eq!(response.text().await.unwrap(), CZECH_TEXT, "charset {}", charset);
+    }
+}
+
+#[tokio::test]
+async fn text_defaults_to_utf8() {
+    let mut response = get("/").await;
+
+    assert_eq!(response.content_type(), Some("text/plain; charset=utf-8"));
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+}
+
+#[tokio::test]
+async fn json() {
+    let mut response = get("/body/json").await;
+
+    let value: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(value, serde_json::json!({ "name": "retch", "tags": ["http", "fetch"], "stars": 5 }));
+}
+
+#[tokio::test]
+async fn json_into_struct() {
+    #[derive(serde::Deserialize)]
+    struct Repo {
+        name: String,
+        stars: u32,
+    }
+
+    let mut response = get("/body/json").await;
+
+    let repo: Repo = response.json().await.unwrap();
+    assert_eq!(repo.name, "retch");
+    assert_eq!(repo.stars, 5);
+}
+
+#[tokio::test]
+async fn invalid_json() {
+    let mut response = get("/body/json/invalid").await;
+
+    let error = response.json::<serde_json::Value>().await.err().unwrap();
+    assert_eq!(error.kind, FetchErrorKind::InvalidJson);
+    assert_eq!(error.phase, FetchPhase::Response);
+    assert_eq!(error.url.as_deref(), Some("http://127.0.0.1:8000/body/json/invalid"));
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn accessors_share_the_body() {
+    let mut response = get("/body/json").await;
+
+    response.text().await.unwrap();
+
+    assert_eq!(response.json::<serde_json::Value>().await.err().unwrap().kind, FetchErrorKind::BodyUsed);
+    assert_eq!(response.text().await.err().unwrap().kind, FetchErrorKind::BodyUsed);
+}
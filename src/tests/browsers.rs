@@ -0,0 +1,165 @@
+use crate::retcher::retcher::{Browser, BrowserProfile, BrowserVersion, EngineOptions, FetchErrorKind, Retcher};
+use super::server::{get_server, request_headers::RequestHeaders};
+use super::common::{self, header, retcher};
+
+/// Sends a request to the `/headers` echo route and returns the headers it got.
+async fn echoed_headers(browser: Browser) -> Vec<(String, String)> {
+    get_server().await;
+
+    let mut response = retcher(browser, None).retch("http://127.0.0.1:8000/headers".into(), None).await.unwrap();
+    let headers: RequestHeaders = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    headers.0
+}
+
+fn versioned_retcher(browser: Browser, version: BrowserVersion) -> Retcher {
+    Retcher::new(EngineOptions {
+        browser: Some(browser),
+        browser_version: Some(version),
+        ignore_tls_errors: Some(true),
+        ..Default::default()
+    })
+}
+
+/// Sends a request over HTTPS (and HTTP/1.1) and returns the headers as sent.
+async fn https_headers(browser: Browser) -> Vec<(String, String)> {
+    common::https_headers(&retcher(browser, None), None).await
+}
+
+#[tokio::test]
+async fn safari_http_headers() {
+    let headers = echoed_headers(Browser::Safari).await;
+    let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+
+    // No client hints and no `Upgrade-Insecure-Requests`.
+    assert_eq!(names, vec!["host", "accept", "accept-encoding", "user-agent", "accept-language", "connection"]);
+    assert_eq!(
+        header(&headers, "user-agent"),
+        Some("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Safari/605.1.15"),
+    );
+    assert_eq!(header(&headers, "accept"), Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"));
+}
+
+#[tokio::test]
+async fn mobile_http_headers() {
+    let headers = echoed_headers(Browser::SafariIos).await;
+    assert!(header(&headers, "user-agent").unwrap().contains("(iPhone; CPU iPhone OS 17_5 like Mac OS X)"));
+    assert!(header(&headers, "user-agent").unwrap().contains("Mobile/15E148"));
+
+    let headers = echoed_headers(Browser::ChromeAndroid).await;
+    assert!(header(&headers, "user-agent").unwrap().contains("(Linux; Android 10; K)"));
+    assert!(header(&headers, "user-agent").unwrap().contains("Mobile Safari/537.36"));
+    // Client hints are only sent over HTTPS.
+    assert_eq!(header(&headers, "sec-ch-ua-mobile"), None);
+}
+
+#[tokio::test]
+async fn edge_http_headers() {
+    let edge = echoed_headers(Browser::Edge).await;
+    let chrome = echoed_headers(Browser::Chrome).await;
+
+    assert!(header(&edge, "user-agent").unwrap().ends_with("Edg/125.0.0.0"));
+    assert!(header(&edge, "user-agent").unwrap().contains("(Windows NT 10.0; Win64; x64)"));
+
+    // Apart from the `User-Agent`, Edge sends what Chrome does.
+    let without_user_agent = |headers: Vec<(String, String)>| headers.into_iter().filter(|(name, _)| name != "user-agent").collect::<Vec<_>>();
+    assert_eq!(without_user_agent(edge), without_user_agent(chrome));
+}
+
+#[tokio::test]
+async fn client_hints() {
+    let edge = https_headers(Browser::Edge).await;
+    assert_eq!(header(&edge, "sec-ch-ua"), Some("\"Microsoft Edge\";v=\"125\", \"Chromium\";v=\"125\", \"Not.A/Brand\";v=\"24\""));
+    assert_eq!(header(&edge, "sec-ch-ua-mobile"), Some("?0"));
+    assert_eq!(header(&edge, "sec-ch-ua-platform"), Some("\"Windows\""));
+
+    let android = https_headers(Browser::ChromeAndroid).await;
+    assert_eq!(header(&android, "sec-ch-ua"), Some("\"Google Chrome\";v=\"125\", \"Chromium\";v=\"125\", \"Not.A/Brand\";v=\"24\""));
+    assert_eq!(header(&android, "sec-ch-ua-mobile"), Some("?1"));
+    assert_eq!(header(&android, "sec-ch-ua-platform"), Some("\"Android\""));
+
+    let chrome = https_headers(Browser::Chrome).await;
+    assert_eq!(header(&chrome, "sec-ch-ua-mobile"), Some("?0"));
+    assert_eq!(header(&chrome, "sec-ch-ua-platform"), Some("\"Linux\""));
+
+    // Safari sends no client hints, and no `Sec-Fetch-User` either.
+    for browser in [Browser::Safari, Browser::SafariIos] {
+        let safari = https_headers(browser).await;
+        assert!(safari.iter().all(|(name, _)| !name.starts_with("sec-ch-ua")), "{:?}", safari);
+        assert_eq!(header(&safari, "Sec-Fetch-Site"), Some("none"));
+        assert_eq!(header(&safari, "Sec-Fetch-User"), None);
+    }
+}
+
+#[test]
+fn builtin_profile_per_browser() {
+    for browser in [Browser::Firefox, Browser::Chrome, Browser::Safari, Browser::Edge, Browser::ChromeAndroid, Browser::SafariIos] {
+        assert_eq!(BrowserProfile::builtin(&browser).browser(), &browser);
+    }
+
+    assert!(Browser::Edge.is_chromium());
+    assert!(Browser::ChromeAndroid.is_chromium());
+    assert!(!Browser::Safari.is_chromium());
+    assert!(!Browser::SafariIos.is_chromium());
+}
+
+#[test]
+fn grease_brands() {
+    let sec_ch_ua = |browser: Browser, version: u32| BrowserProfile::builtin(&browser).with_version(version).unwrap().sec_ch_ua().unwrap();
+
+    // The brand lists of the real browsers.
+    assert_eq!(sec_ch_ua(Browser::Chrome, 124), "\"Chromium\";v=\"124\", \"Google Chrome\";v=\"124\", \"Not-A.Brand\";v=\"99\"");
+    assert_eq!(sec_ch_ua(Browser::Chrome, 125), "\"Google Chrome\";v=\"125\", \"Chromium\";v=\"125\", \"Not.A/Brand\";v=\"24\"");
+    assert_eq!(sec_ch_ua(Browser::Chrome, 130), "\"Chromium\";v=\"130\", \"Google Chrome\";v=\"130\", \"Not?A_Brand\";v=\"99\"");
+    assert_eq!(sec_ch_ua(Browser::Chrome, 131), "\"Google Chrome\";v=\"131\", \"Chromium\";v=\"131\", \"Not_A Brand\";v=\"24\"");
+    assert_eq!(sec_ch_ua(Browser::Edge, 126), "\"Not/A)Brand\";v=\"8\", \"Chromium\";v=\"126\", \"Microsoft Edge\";v=\"126\"");
+
+    // Only the Chromium-based browsers send them.
+    assert_eq!(BrowserProfile::builtin(&Browser::Firefox).sec_ch_ua(), None);
+    assert_eq!(BrowserProfile::builtin(&Browser::Safari).sec_ch_ua(), None);
+}
+
+#[tokio::test]
+async fn exact_browser_version() {
+    let chrome = common::https_headers(&versioned_retcher(Browser::Chrome, BrowserVersion::Exact(131)), None).await;
+    assert_eq!(
+        header(&chrome, "User-Agent"),
+        Some("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36"),
+    );
+    assert_eq!(header(&chrome, "sec-ch-ua"), Some("\"Google Chrome\";v=\"131\", \"Chromium\";v=\"131\", \"Not_A Brand\";v=\"24\""));
+
+    let edge = common::https_headers(&versioned_retcher(Browser::Edge, BrowserVersion::Exact(126)), None).await;
+    assert!(header(&edge, "User-Agent").unwrap().ends_with("Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0"));
+
+    let firefox = common::https_headers(&versioned_retcher(Browser::Firefox, BrowserVersion::Exact(115)), None).await;
+    assert_eq!(header(&firefox, "User-Agent"), Some("Mozilla/5.0 (X11; Linux x86_64; rv:115.0) Gecko/20100101 Firefox/115.0"));
+}
+
+#[tokio::test]
+async fn browser_version_range() {
+    let retcher = versioned_retcher(Browser::Chrome, BrowserVersion::Range(120, 122));
+    let version = retcher.profile().unwrap().version();
+    assert!((120..=122).contains(&version));
+
+    // The version is picked once for the instance, and the headers agree on it.
+    for _ in 0..2 {
+        let headers = common::https_headers(&retcher, None).await;
+        assert!(header(&headers, "User-Agent").unwrap().contains(&format!("Chrome/{}.0.0.0", version)));
+        assert!(header(&headers, "sec-ch-ua").unwrap().contains(&format!("\"Google Chrome\";v=\"{}\"", version)));
+    }
+}
+
+#[tokio::test]
+async fn unsupported_browser_version() {
+    // Safari's user agent has the minor version too, so its profile only impersonates its own version.
+    let retcher = versioned_retcher(Browser::Safari, BrowserVersion::Exact(16));
+    let error = retcher.retch("http://127.0.0.1:8000/".into(), None).await.err().unwrap();
+    assert_eq!(error.kind, FetchErrorKind::InvalidProfile);
+    assert_eq!(error.message, "The browser profile 'safari-17-macos' can only impersonate version 17");
+    assert_eq!(retcher.profile().unwrap_err().kind, FetchErrorKind::InvalidProfile);
+
+    assert!(versioned_retcher(Browser::Safari, BrowserVersion::Exact(17)).profile().is_ok());
+
+    let error = versioned_retcher(Browser::Chrome, BrowserVersion::Range(130, 120)).profile().unwrap_err();
+    assert_eq!(error.kind, FetchErrorKind::InvalidProfile);
+    assert_eq!(error.message, "Invalid browser version range 130-120");
+}
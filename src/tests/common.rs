@@ -0,0 +1,34 @@
+use crate::retcher::retcher::{Browser, EngineOptions, FetchOptions, ProxyOptions, Retcher};
+use super::server::tls::tls_server;
+
+/// Returns a `Retcher` impersonating the browser (through the proxy, if any) that trusts the stand-ins' certificates.
+pub fn retcher(browser: Browser, proxy: Option<ProxyOptions>) -> Retcher {
+    Retcher::new(EngineOptions {
+        browser: Some(browser),
+        ignore_tls_errors: Some(true),
+        proxy,
+        ..Default::default()
+    })
+}
+
+/// Returns the headers of a request head, in the order and casing they were sent.
+pub fn parse_head(head: &str) -> Vec<(String, String)> {
+    head.trim_end()
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Returns the value of the (case-insensitively matched) header.
+pub fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+/// Sends a request to a fresh HTTPS (and HTTP/1.1) stand-in and returns the headers as sent.
+pub async fn https_headers(retcher: &Retcher, options: Option<FetchOptions>) -> Vec<(String, String)> {
+    let server = tls_server().await;
+    retcher.retch(format!("{}/", server.url), options).await.unwrap();
+    parse_head(&server.heads()[0])
+}
@@ -1,9 +1,9 @@
-use std::{collections::HashMap, iter::zip};
+use indexmap::IndexMap;
 
 use serde_json::json;
 
 use crate::retcher::retcher::{Browser, EngineOptions, FetchOptions, Retcher};
-use super::server::{get_server, request_headers::RequestHeaders};
+use super::server::get_server;
 use super::server::compression::{Payload, BODY, CompressionMethod};
 
 
@@ -17,16 +17,18 @@ macro_rules! compression_tests {
             let retcher = Retcher::new(EngineOptions {
                 browser: Some(Browser::Chrome),
                 ignore_tls_errors: Some(true),
+                ..Default::default()
             });
         
             let response = retcher.retch("http://127.0.0.1:8000/compression".into(), Some(FetchOptions{
-                headers: HashMap::from_iter(vec![
+                headers: IndexMap::from_iter(vec![
                     ("accept-encoding".to_string(), format!("{:?}", CompressionMethod::$name)),
                 ]),
+                ..Default::default()
             })).await;
         
             let response = match response {
-                Ok(response) => response.body.unwrap_or(Vec::new()),
+                Ok(mut response) => response.bytes().await.unwrap(),
                 Err(e) => panic!("{:?}", e),
             };
         
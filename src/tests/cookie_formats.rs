@@ -0,0 +1,182 @@
+use crate::retcher::retcher::{Browser, Cookie, CookieFormat, EngineOptions, FetchErrorKind, Retcher, SameSite};
+use super::server::get_server;
+use super::server::request_headers::RequestHeaders;
+
+static SET_COOKIES: &str = "set_cookie=session%3Dabc%3B+Path%3D%2F\
+    &set_cookie=remember%3D1%3B+Path%3D%2F%3B+Max-Age%3D3600%3B+HttpOnly\
+    &set_cookie=scoped%3Dyes%3B+Path%3D%2Fheaders%3B+SameSite%3DStrict";
+
+fn retcher() -> Retcher {
+    Retcher::new(EngineOptions {
+        browser: Some(Browser::Firefox),
+        ignore_tls_errors: None,
+        ..Default::default()
+    })
+}
+
+async fn sent_cookie(retcher: &Retcher) -> Option<String> {
+    let mut response = retcher.retch("http://127.0.0.1:8000/headers".into(), None).await.unwrap();
+
+    let headers: RequestHeaders = response.json().await.unwrap();
+    headers.0.into_iter().find(|(key, _)| key == "cookie").map(|(_, value)| value)
+}
+
+/// `cookies.txt` has no column for `SameSite`, so that's the only thing allowed to get lost.
+async fn round_trip(format: CookieFormat, keeps_same_site: bool) {
+    get_server().await;
+
+    let original = retcher();
+    original.retch(format!("http://127.0.0.1:8000/cookies/set?{}", SET_COOKIES), None).await.unwrap();
+    assert_eq!(original.cookies().cookies().len(), 3);
+
+    let exported = original.cookies().export(format);
+
+    let restored = retcher();
+    assert_eq!(restored.cookies().import(&exported, format).unwrap(), 3);
+
+    let mut expected = original.cookies().cookies();
+    if !keeps_same_site {
+        expected.iter_mut().for_each(|cookie| cookie.same_site = None);
+    }
+
+    assert_eq!(restored.cookies().cookies(), expected);
+    assert_eq!(sent_cookie(&restored).await, sent_cookie(&original).await);
+    assert_eq!(sent_cookie(&restored).await.as_deref(), Some("scoped=yes; session=abc; remember=1"));
+}
+
+#[tokio::test]
+async fn netscape_round_trip() {
+    round_trip(CookieFormat::Netscape, false).await;
+}
+
+#[tokio::test]
+async fn json_round_trip() {
+    round_trip(CookieFormat::Json, true).await;
+}
+
+#[test]
+fn netscape_export() {
+    let retcher = retcher();
+    retcher.cookies().set_cookie("id=1; Domain=example.com; Path=/app; Secure; HttpOnly; Expires=Fri, 01 Jan 2100 00:00:00 GMT", "https://www.example.com/").unwrap();
+    retcher.cookies().set_cookie("theme=dark", "http://example.org/").unwrap();
+
+    assert_eq!(retcher.cookies().export(CookieFormat::Netscape), "# Netscape HTTP Cookie File\n\n\
+        #HttpOnly_.example.com\tTRUE\t/app\tTRUE\t4102444800\tid\t1\n\
+        example.org\tFALSE\t/\tFALSE\t0\ttheme\tdark\n");
+}
+
+#[test]
+fn curl_cookies_txt_import() {
+    let cookies_txt = "# Netscape HTTP Cookie File\r\n\
+        # https://curl.se/docs/http-cookies.html\r\n\
+        # This file was generated by libcurl! Edit at your own risk.\r\n\
+        \r\n\
+        .example.com\tTRUE\t/\tFALSE\t4102444800\tlang\ten\r\n\
+        #HttpOnly_shop.example.com\tFALSE\t/cart\tTRUE\t0\tcart\t42\r\n\
+        shop.example.com\tFALSE\t/\tFALSE\t0\tempty\t\r\n";
+
+    let retcher = retcher();
+    assert_eq!(retcher.cookies().import(cookies_txt, CookieFormat::Netscape).unwrap(), 3);
+
+    let cookies = retcher.cookies().cookies_for_url("https://shop.example.com/cart/item").unwrap();
+    assert_eq!(cookies.iter().map(|cookie| (cookie.name.as_str(), cookie.value.as_str())).collect::<Vec<_>>(), vec![
+        ("cart", "42"),
+        ("lang", "en"),
+        ("empty", ""),
+    ]);
+
+    assert!(cookies[0].http_only && cookies[0].secure && cookies[0].host_only);
+    assert_eq!(cookies[0].expires, None);
+    assert_eq!(cookies[1].domain, "example.com");
+    assert!(!cookies[1].host_only);
+    assert_eq!(cookies[1].expires, Some(4102444800));
+}
+
+#[test]
+fn puppeteer_and_playwright_import() {
+    let puppeteer = r#"[
+        {"name":"sid","value":"s1","domain":".example.com","path":"/","expires":4102444800.5,"size":5,"httpOnly":true,"secure":true,"session":false,"sameSite":"Lax","priority":"Medium"},
+        {"name":"tmp","value":"t","domain":"www.example.com","path":"/","expires":-1,"size":4,"httpOnly":false,"secure":false,"session":true}
+    ]"#;
+    let playwright = r#"[
+        {"name":"pw","value":"1","domain":"www.example.com","path":"/","expires":-1,"httpOnly":false,"secure":true,"sameSite":"None"}
+    ]"#;
+
+    let retcher = retcher();
+    assert_eq!(retcher.cookies().import(puppeteer, CookieFormat::Json).unwrap(), 2);
+    assert_eq!(retcher.cookies().import(playwright, CookieFormat::Json).unwrap(), 1);
+
+    let cookies = retcher.cookies().cookies();
+    assert_eq!(cookies[0], Cookie {
+        name: "sid".into(),
+        value: "s1".into(),
+        domain: "example.com".into(),
+        host_only: false,
+        path: "/".into(),
+        expires: Some(4102444800),
+        secure: true,
+        http_only: true,
+        same_site: Some(SameSite::Lax),
+    });
+    assert_eq!((cookies[1].host_only, cookies[1].expires), (true, None));
+    assert_eq!(cookies[2].same_site, Some(SameSite::None));
+}
+
+#[test]
+fn invalid_input() {
+    let retcher = retcher();
+
+    for (data, format) in [
+        ("example.com\tTRUE\t/\tFALSE\t0\tok\t1\nexample.com\tMAYBE\t/\tFALSE\t0\tbad\t1", CookieFormat::Netscape),
+        ("example.com\t/\tvalue", CookieFormat::Netscape),
+        ("{\"name\":\"a\"}", CookieFormat::Json),
+        ("[{\"name\":\"a\",\"value\":\"1\"}]", CookieFormat::Json),
+        ("[{\"name\":\"a\",\"value\":\"1\",\"domain\":\"example.com\",\"sameSite\":\"Sometimes\"}]", CookieFormat::Json),
+    ] {
+        let error = retcher.cookies().import(data, format).err().unwrap();
+        assert_eq!(error.kind, FetchErrorKind::InvalidCookie, "{}", data);
+    }
+
+    // A syntax error anywhere means nothing is imported.
+    assert!(retcher.cookies().cookies().is_empty());
+}
+
+#[test]
+fn rejected_cookie_imports_nothing() {
+    let retcher = retcher();
+
+    // The second cookie is valid syntax, but the jar rejects a `__Host-` cookie shared with subdomains.
+    let data = "[
+        {\"name\":\"ok\",\"value\":\"1\",\"domain\":\"example.com\"},
+        {\"name\":\"__Host-bad\",\"value\":\"1\",\"domain\":\".example.com\",\"secure\":true}
+    ]";
+    let error = retcher.cookies().import(data, CookieFormat::Json).err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::InvalidCookie);
+    assert!(error.message.contains("'__Host-bad'"), "{}", error.message);
+    assert!(retcher.cookies().cookies().is_empty());
+}
+
+#[test]
+fn insecure_same_site_none_imports_as_lax() {
+    let retcher = retcher();
+
+    // Firefox and Playwright export `"sameSite": "None"` for insecure cookies set without `SameSite`.
+    let data = r#"[
+        {"name":"legacy","value":"1","domain":"example.com","path":"/","expires":-1,"httpOnly":false,"secure":false,"sameSite":"None"}
+    ]"#;
+
+    assert_eq!(retcher.cookies().import(data, CookieFormat::Json).unwrap(), 1);
+    assert_eq!(retcher.cookies().cookies()[0].same_site, Some(SameSite::Lax));
+}
+
+#[test]
+fn expired_cookies_are_not_counted() {
+    let retcher = retcher();
+
+    let cookies_txt = "example.com\tFALSE\t/\tFALSE\t4102444800\tfresh\t1\n\
+        example.com\tFALSE\t/\tFALSE\t1\tstale\t1\n";
+
+    assert_eq!(retcher.cookies().import(cookies_txt, CookieFormat::Netscape).unwrap(), 1);
+    assert_eq!(retcher.cookies().cookies().len(), 1);
+}
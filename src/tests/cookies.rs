@@ -0,0 +1,222 @@
+use indexmap::IndexMap;
+
+use reqwest::Method;
+use url::Url;
+
+use crate::retcher::retcher::{Browser, Cookie, Credentials, EngineOptions, FetchErrorKind, FetchOptions, Retcher, SameSite};
+use crate::retcher::site::SiteRelation;
+use super::server::get_server;
+use super::server::request_headers::RequestHeaders;
+
+async fn retcher() -> Retcher {
+    get_server().await;
+
+    Retcher::new(EngineOptions {
+        browser: Some(Browser::Firefox),
+        ignore_tls_errors: None,
+        ..Default::default()
+    })
+}
+
+async fn set_cookies(retcher: &Retcher, set_cookies: &[&str], credentials: Credentials) {
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(set_cookies.iter().map(|set_cookie| ("set_cookie", set_cookie)))
+        .finish();
+
+    retcher.retch(format!("http://127.0.0.1:8000/cookies/set?{}", query), Some(FetchOptions {
+        credentials,
+        ..Default::default()
+    })).await.unwrap();
+}
+
+async fn sent_cookie(retcher: &Retcher, url: &str, credentials: Credentials) -> Option<String> {
+    let mut response = retcher.retch(url.into(), Some(FetchOptions {
+        credentials,
+        ..Default::default()
+    })).await.unwrap();
+
+    let headers: RequestHeaders = response.json().await.unwrap();
+    headers.0.into_iter().find(|(key, _)| key == "cookie").map(|(_, value)| value)
+}
+
+#[tokio::test]
+async fn cookies_persist_between_requests() {
+    let retcher = retcher().await;
+
+    set_cookies(&retcher, &["session=abc; Path=/", "theme=dark; Path=/; HttpOnly"], Credentials::Include).await;
+
+    assert_eq!(
+        sent_cookie(&retcher, "http://127.0.0.1:8000/headers", Credentials::Include).await.as_deref(),
+        Some("session=abc; theme=dark"),
+    );
+
+    let cookies = retcher.cookies().cookies();
+    assert_eq!(cookies.len(), 2);
+    assert_eq!(cookies[0], Cookie {
+        name: "session".into(),
+        value: "abc".into(),
+        domain: "127.0.0.1".into(),
+        host_only: true,
+        path: "/".into(),
+        expires: None,
+        secure: false,
+        http_only: false,
+        same_site: None,
+    });
+    assert!(cookies[1].http_only);
+}
+
+#[tokio::test]
+async fn path_and_expiration() {
+    let retcher = retcher().await;
+
+    // Without `Path`, the cookie belongs to the "directory" of the URL (`/cookies`).
+    set_cookies(&retcher, &["scoped=1", "persistent=1; Path=/; Max-Age=3600"], Credentials::Include).await;
+
+    let cookies = retcher.cookies().cookies_for_url("http://127.0.0.1:8000/cookies/other").unwrap();
+    assert_eq!(cookies.iter().map(|cookie| cookie.name.as_str()).collect::<Vec<_>>(), vec!["scoped", "persistent"]);
+    assert_eq!(cookies[0].path, "/cookies");
+
+    let expires = cookies[1].expires.unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    assert!((now + 3590..=now + 3610).contains(&expires), "{}", expires);
+
+    assert_eq!(sent_cookie(&retcher, "http://127.0.0.1:8000/headers", Credentials::Include).await.as_deref(), Some("persistent=1"));
+
+    // A cookie with `Max-Age=0` deletes the stored one.
+    set_cookies(&retcher, &["persistent=; Path=/; Max-Age=0"], Credentials::Include).await;
+    assert_eq!(sent_cookie(&retcher, "http://127.0.0.1:8000/headers", Credentials::Include).await, None);
+}
+
+#[tokio::test]
+async fn insecure_origins_cannot_set_secure_cookies() {
+    let retcher = retcher().await;
+
+    set_cookies(&retcher, &["secure=1; Path=/; Secure", "__Secure-id=1; Path=/", "none=1; Path=/; SameSite=None"], Credentials::Include).await;
+
+    assert!(retcher.cookies().cookies().is_empty());
+
+    let error = retcher.cookies().set_cookie("secure=1; Secure", "http://example.com/").err().unwrap();
+    assert_eq!(error.kind, FetchErrorKind::InvalidCookie);
+
+    retcher.cookies().set_cookie("secure=1; Secure", "https://example.com/").unwrap();
+    assert!(retcher.cookies().cookies_for_url("http://example.com/").unwrap().is_empty());
+    assert_eq!(retcher.cookies().cookies_for_url("https://example.com/").unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn public_suffixes_are_rejected() {
+    let retcher = retcher().await;
+    let jar = retcher.cookies();
+
+    for domain in ["co.uk", "github.io"] {
+        let error = jar.set_cookie(&format!("super=1; Domain={}", domain), &format!("https://example.{}/", domain)).err().unwrap();
+        assert_eq!(error.kind, FetchErrorKind::InvalidCookie);
+    }
+
+    jar.set_cookie("site=1; Domain=example.co.uk", "https://www.example.co.uk/").unwrap();
+
+    assert_eq!(jar.cookies()[0].domain, "example.co.uk");
+    assert!(!jar.cookies()[0].host_only);
+    assert_eq!(jar.cookies_for_url("https://shop.example.co.uk/").unwrap().len(), 1);
+    assert!(jar.cookies_for_url("https://other.co.uk/").unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn same_site() {
+    let retcher = retcher().await;
+    let jar = retcher.cookies();
+
+    for set_cookie in ["strict=1; SameSite=Strict", "lax=1; SameSite=Lax", "none=1; SameSite=None; Secure", "default=1"] {
+        jar.set_cookie(set_cookie, "https://example.com/").unwrap();
+    }
+
+    let url = Url::parse("https://example.com/").unwrap();
+    let header = |relation, method, lax_by_default| jar.cookie_header(&url, relation, &method, true, lax_by_default).unwrap_or_default();
+
+    assert_eq!(header(SiteRelation::SameSite, Method::POST, true), "strict=1; lax=1; none=1; default=1");
+    assert_eq!(header(SiteRelation::CrossSite, Method::GET, true), "lax=1; none=1; default=1");
+    assert_eq!(header(SiteRelation::CrossSite, Method::POST, true), "none=1");
+    assert_eq!(header(SiteRelation::CrossSite, Method::POST, false), "none=1; default=1");
+
+    // Cross-site subresources (and iframes) only get the `SameSite=None` cookies.
+    assert_eq!(jar.cookie_header(&url, SiteRelation::CrossSite, &Method::GET, false, true).as_deref(), Some("none=1"));
+    assert_eq!(jar.cookie_header(&url, SiteRelation::SameSite, &Method::GET, false, true).as_deref(), Some("strict=1; lax=1; none=1; default=1"));
+}
+
+#[tokio::test]
+async fn credentials_mode() {
+    let retcher = retcher().await;
+
+    set_cookies(&retcher, &["ignored=1; Path=/"], Credentials::Omit).await;
+    assert!(retcher.cookies().cookies().is_empty());
+
+    set_cookies(&retcher, &["session=1; Path=/"], Credentials::SameOrigin).await;
+    retcher.cookies().set_cookie("other=1", "http://localhost:8000/").unwrap();
+
+    assert_eq!(sent_cookie(&retcher, "http://127.0.0.1:8000/headers", Credentials::Omit).await, None);
+    assert_eq!(sent_cookie(&retcher, "http://127.0.0.1:8000/headers", Credentials::SameOrigin).await.as_deref(), Some("session=1"));
+
+    // After a cross-origin redirect, `same-origin` stops sending cookies.
+    let redirect = "http://127.0.0.1:8000/redirect/to?status=302&location=http%3A%2F%2Flocalhost%3A8000%2Fheaders";
+    assert_eq!(sent_cookie(&retcher, redirect, Credentials::SameOrigin).await, None);
+    assert_eq!(sent_cookie(&retcher, redirect, Credentials::Include).await.as_deref(), Some("other=1"));
+}
+
+#[tokio::test]
+async fn repeated_set_cookie_headers() {
+    let retcher = retcher().await;
+
+    let response = retcher.retch("http://127.0.0.1:8000/cookies/set?set_cookie=a%3D1&set_cookie=b%3D2".into(), None).await.unwrap();
+
+    // Like `fetch`, `headers` combines the repeated values and `set_cookies` keeps them apart.
+    assert_eq!(response.headers.get("set-cookie").map(String::as_str), Some("a=1, b=2"));
+    assert_eq!(response.set_cookies, vec!["a=1".to_string(), "b=2".to_string()]);
+}
+
+#[tokio::test]
+async fn cookies_from_redirects_are_stored() {
+    let retcher = retcher().await;
+
+    retcher.retch("http://127.0.0.1:8000/redirect/3".into(), None).await.unwrap();
+
+    let cookies = retcher.cookies().cookies();
+    assert_eq!(cookies.len(), 1);
+    assert_eq!((cookies[0].name.as_str(), cookies[0].value.as_str()), ("hop", "1"));
+}
+
+#[tokio::test]
+async fn add_and_remove() {
+    let retcher = retcher().await;
+    let jar = retcher.cookies();
+
+    jar.add(Cookie {
+        name: "token".into(),
+        value: "xyz".into(),
+        domain: ".127.0.0.1".into(),
+        host_only: true,
+        path: "/".into(),
+        expires: Some(4102444800),
+        secure: false,
+        http_only: true,
+        same_site: Some(SameSite::Lax),
+    }).unwrap();
+
+    let cookie = &jar.cookies()[0];
+    assert_eq!(cookie.domain, "127.0.0.1");
+    assert_eq!(cookie.expires, Some(4102444800));
+    assert_eq!(cookie.same_site, Some(SameSite::Lax));
+
+    let headers = IndexMap::from([("X-Test".to_string(), "1".to_string())]);
+    let mut response = retcher.retch("http://127.0.0.1:8000/headers".into(), Some(FetchOptions { headers, ..Default::default() })).await.unwrap();
+    let headers: RequestHeaders = response.json().await.unwrap();
+    assert!(headers.0.contains(&("cookie".to_string(), "token=xyz".to_string())));
+
+    assert!(!jar.remove("token", "127.0.0.1", "/other"));
+    assert!(jar.remove("token", "127.0.0.1", "/"));
+    assert!(jar.cookies().is_empty());
+
+    jar.set_cookie("a=1", "http://127.0.0.1/").unwrap();
+    jar.clear();
+    assert!(jar.cookies().is_empty());
+}
@@ -0,0 +1,109 @@
+use crate::retcher::retcher::{Browser, Destination, FetchErrorKind, FetchOptions, RequestMode};
+use super::server::{get_server, request_headers::RequestHeaders};
+use super::common::{header, https_headers, retcher};
+
+fn options(destination: Destination, mode: Option<RequestMode>) -> FetchOptions {
+    FetchOptions { destination, mode, ..Default::default() }
+}
+
+/// Sends a request for the destination over HTTPS (and HTTP/1.1) and returns the headers as sent.
+async fn sent_headers(browser: Browser, destination: Destination) -> Vec<(String, String)> {
+    https_headers(&retcher(browser, None), Some(options(destination, None))).await
+}
+
+#[tokio::test]
+async fn fetch_headers() {
+    let chrome = sent_headers(Browser::Chrome, Destination::Empty).await;
+    assert_eq!(header(&chrome, "Accept"), Some("*/*"));
+    assert_eq!(header(&chrome, "Sec-Fetch-Dest"), Some("empty"));
+    assert_eq!(header(&chrome, "Sec-Fetch-Mode"), Some("cors"));
+    // A `fetch` call comes from a page, of the URL's origin without a referrer.
+    assert_eq!(header(&chrome, "Sec-Fetch-Site"), Some("same-origin"));
+    assert_eq!(header(&chrome, "Sec-Fetch-User"), None);
+    assert_eq!(header(&chrome, "Upgrade-Insecure-Requests"), None);
+    // Same-origin `GET`s don't get an `Origin`.
+    assert_eq!(header(&chrome, "Origin"), None);
+
+    let firefox = sent_headers(Browser::Firefox, Destination::Empty).await;
+    assert_eq!(header(&firefox, "Accept"), Some("*/*"));
+    assert_eq!(header(&firefox, "Priority"), Some("u=4"));
+    assert_eq!(header(&firefox, "Upgrade-Insecure-Requests"), None);
+}
+
+#[tokio::test]
+async fn subresource_headers() {
+    let image = sent_headers(Browser::Chrome, Destination::Image).await;
+    assert_eq!(header(&image, "Accept"), Some("image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8"));
+    assert_eq!(header(&image, "Sec-Fetch-Dest"), Some("image"));
+    assert_eq!(header(&image, "Sec-Fetch-Mode"), Some("no-cors"));
+
+    let image = sent_headers(Browser::Firefox, Destination::Image).await;
+    assert_eq!(header(&image, "Accept"), Some("image/avif,image/webp,image/png,image/svg+xml,image/*;q=0.8,*/*;q=0.5"));
+    assert_eq!(header(&image, "Priority"), Some("u=5, i"));
+
+    let style = sent_headers(Browser::Safari, Destination::Style).await;
+    assert_eq!(header(&style, "Accept"), Some("text/css,*/*;q=0.1"));
+    assert_eq!(header(&style, "Sec-Fetch-Dest"), Some("style"));
+
+    let script = sent_headers(Browser::Edge, Destination::Script).await;
+    assert_eq!(header(&script, "Accept"), Some("*/*"));
+    assert_eq!(header(&script, "Sec-Fetch-Mode"), Some("no-cors"));
+
+    // Fonts are always requested with CORS.
+    let font = sent_headers(Browser::Firefox, Destination::Font).await;
+    assert_eq!(header(&font, "Accept"), Some("application/font-woff2;q=1.0,application/font-woff;q=0.9,*/*;q=0.8"));
+    assert_eq!(header(&font, "Sec-Fetch-Mode"), Some("cors"));
+    assert_eq!(header(&font, "Priority"), Some("u=3"));
+}
+
+#[tokio::test]
+async fn navigation_headers() {
+    let document = sent_headers(Browser::Chrome, Destination::Document).await;
+    assert_eq!(header(&document, "Sec-Fetch-Dest"), Some("document"));
+    assert_eq!(header(&document, "Sec-Fetch-Mode"), Some("navigate"));
+    assert_eq!(header(&document, "Sec-Fetch-Site"), Some("none"));
+    assert_eq!(header(&document, "Sec-Fetch-User"), Some("?1"));
+    assert_eq!(header(&document, "Upgrade-Insecure-Requests"), Some("1"));
+
+    // Frames are navigations too, but not the user's ones.
+    let iframe = sent_headers(Browser::Chrome, Destination::Iframe).await;
+    assert_eq!(header(&iframe, "Accept"), header(&document, "Accept"));
+    assert_eq!(header(&iframe, "Sec-Fetch-Dest"), Some("iframe"));
+    assert_eq!(header(&iframe, "Sec-Fetch-Mode"), Some("navigate"));
+    assert_eq!(header(&iframe, "Sec-Fetch-User"), None);
+    assert_eq!(header(&iframe, "Upgrade-Insecure-Requests"), Some("1"));
+
+    let iframe = sent_headers(Browser::Firefox, Destination::Iframe).await;
+    assert_eq!(header(&iframe, "Priority"), Some("u=4, i"));
+}
+
+#[tokio::test]
+async fn invalid_modes() {
+    let retcher = retcher(Browser::Chrome, None);
+
+    for (destination, mode) in [(Destination::Image, RequestMode::Navigate), (Destination::Document, RequestMode::Cors)] {
+        let error = retcher.retch("http://127.0.0.1:8000/".into(), Some(options(destination, Some(mode)))).await.err().unwrap();
+        assert_eq!(error.kind, FetchErrorKind::InvalidRequest);
+        assert_eq!(error.message, format!("The '{}' mode can't be used for the '{}' destination", mode.as_str(), destination.as_str()));
+    }
+}
+
+#[tokio::test]
+async fn cross_origin_modes() {
+    get_server().await;
+    let retcher = retcher(Browser::Chrome, None);
+    let redirect = "http://127.0.0.1:8000/redirect/to?status=302&location=http%3A%2F%2Flocalhost%3A8000%2Fheaders";
+
+    // A cross-origin CORS request gets the `Origin` of its initiator.
+    let mut response = retcher.retch(redirect.into(), Some(options(Destination::Empty, None))).await.unwrap();
+    let headers: RequestHeaders = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(header(&headers.0, "origin"), Some("http://127.0.0.1:8000"));
+
+    let mut response = retcher.retch(redirect.into(), Some(options(Destination::Image, None))).await.unwrap();
+    let headers: RequestHeaders = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(header(&headers.0, "origin"), None);
+
+    let error = retcher.retch(redirect.into(), Some(options(Destination::Empty, Some(RequestMode::SameOrigin)))).await.err().unwrap();
+    assert_eq!(error.kind, FetchErrorKind::Redirect);
+    assert!(error.message.contains("(mode is 'same-origin')"), "{}", error.message);
+}
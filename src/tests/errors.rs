@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use crate::retcher::retcher::{Browser, EngineOptions, FetchError, FetchErrorKind, FetchPhase, ProxyOptions, Retcher};
+use super::server::get_server;
+use super::server::raw::silent_server;
+
+async fn retch_error(url: &str) -> FetchError {
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Chrome),
+        ignore_tls_errors: Some(true),
+        ..Default::default()
+    });
+
+    match retcher.retch(url.into(), None).await {
+        Ok(response) => panic!("Expected an error, got status {}", response.status),
+        Err(e) => e,
+    }
+}
+
+#[tokio::test]
+async fn unsupported_protocol() {
+    let error = retch_error("ftp://127.0.0.1/file.txt").await;
+
+    assert_eq!(error.kind, FetchErrorKind::UnsupportedProtocol);
+    assert_eq!(error.phase, FetchPhase::Setup);
+    assert_eq!(error.url.as_deref(), Some("ftp://127.0.0.1/file.txt"));
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn connection_refused() {
+    let error = retch_error("http://127.0.0.1:1/").await;
+
+    assert_eq!(error.kind, FetchErrorKind::ConnectionRefused);
+    assert_eq!(error.phase, FetchPhase::Connect);
+    assert!(error.is_retryable());
+    assert!(error.source().is_some());
+    assert_eq!(error.kind.code(), "ERR_CONNECTION_REFUSED");
+}
+
+#[tokio::test]
+async fn dns_failure() {
+    let error = retch_error("http://this-host-does-not-exist.invalid/").await;
+
+    assert_eq!(error.kind, FetchErrorKind::Dns);
+    assert_eq!(error.phase, FetchPhase::Connect);
+    assert_eq!(error.url.as_deref(), Some("http://this-host-does-not-exist.invalid/"));
+}
+
+#[tokio::test]
+async fn tls_handshake_failure() {
+    get_server().await;
+
+    // The test server only speaks plain HTTP.
+    let error = retch_error("https://127.0.0.1:8000/").await;
+
+    assert_eq!(error.kind, FetchErrorKind::Tls);
+    assert_eq!(error.phase, FetchPhase::Connect);
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn connect_timeout() {
+    let silent = silent_server().await;
+
+    // The server never answers the `ClientHello`, the proxy never answers the `CONNECT` request.
+    for proxy in [None, Some(ProxyOptions::new(format!("http://{}", silent)))] {
+        let retcher = Retcher::new(EngineOptions {
+            browser: Some(Browser::Chrome),
+            connect_timeout: Some(Duration::from_millis(300)),
+            proxy: proxy.clone(),
+            ..Default::default()
+        });
+
+        let started = Instant::now();
+        let error = retcher.retch(format!("https://{}/", silent), None).await.err().unwrap();
+
+        assert_eq!(error.kind, FetchErrorKind::Timeout, "{:?}: {}", proxy, error);
+        assert_eq!(error.phase, FetchPhase::Connect);
+        assert!(error.is_retryable());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
+
+#[tokio::test]
+async fn body_decode_failure() {
+    get_server().await;
+
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Chrome),
+        ignore_tls_errors: None,
+        ..Default::default()
+    });
+
+    // The body is only decoded once it's read.
+    let mut response = retcher.retch("http://127.0.0.1:8000/compression/broken".into(), None).await.unwrap();
+    let error = response.bytes().await.err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::Decode);
+    assert_eq!(error.phase, FetchPhase::Response);
+    assert!(!error.is_retryable());
+}
+
+#[test]
+fn display_includes_kind_and_phase() {
+    let error = FetchError::new(FetchErrorKind::Timeout, FetchPhase::Request, None, "Request timed out");
+
+    assert_eq!(error.to_string(), "Request timed out [ERR_TIMEOUT, request phase]");
+}
@@ -0,0 +1,180 @@
+use crate::retcher::retcher::{
+    Browser, BrowserProfile, Device, EngineOptions, FetchErrorKind, Fingerprint, FingerprintOptions, OperatingSystem, Retcher, generate_fingerprint,
+};
+use super::server::raw::recording_server;
+
+fn fingerprint(options: FingerprintOptions) -> Fingerprint {
+    generate_fingerprint(&options).unwrap()
+}
+
+fn header<'a>(fingerprint: &'a Fingerprint, name: &str) -> Option<&'a str> {
+    fingerprint.profile.headers.iter().find(|header| header.name.eq_ignore_ascii_case(name)).map(|header| header.value.as_str())
+}
+
+#[test]
+fn seeded_fingerprints() {
+    let first = fingerprint(FingerprintOptions { seed: Some(42), ..Default::default() });
+    let second = fingerprint(FingerprintOptions { seed: Some(42), ..Default::default() });
+
+    assert_eq!(first.profile, second.profile);
+    assert_eq!((first.browser, first.version, first.locale), (second.browser, second.version, second.locale));
+
+    // Different seeds spread over different identities.
+    let user_agents: std::collections::HashSet<String> = (0..50)
+        .map(|seed| fingerprint(FingerprintOptions { seed: Some(seed), ..Default::default() }).profile.user_agent())
+        .collect();
+    assert!(user_agents.len() > 10, "{:?}", user_agents);
+}
+
+#[test]
+fn consistent_fingerprints() {
+    for seed in 0..500 {
+        let fingerprint = fingerprint(FingerprintOptions { seed: Some(seed), ..Default::default() });
+        let user_agent = fingerprint.profile.user_agent();
+        let platform = header(&fingerprint, "sec-ch-ua-platform");
+
+        assert_eq!(fingerprint.profile.browser(), &fingerprint.browser);
+        assert_eq!(fingerprint.profile.version(), fingerprint.version);
+        assert_eq!(fingerprint.device, fingerprint.operating_system.device());
+        assert_eq!(header(&fingerprint, "Accept-Language"), Some(fingerprint.accept_language.as_str()));
+        assert!(fingerprint.accept_language.starts_with(&fingerprint.locale));
+
+        // Only the Chromium-based browsers send client hints, and they agree with the `User-Agent`.
+        assert_eq!(fingerprint.browser.is_chromium(), platform.is_some(), "{}", user_agent);
+        assert_eq!(fingerprint.browser.is_chromium(), fingerprint.profile.sec_ch_ua().is_some());
+
+        let (system, platform_name) = match fingerprint.operating_system {
+            OperatingSystem::Windows => ("Windows NT 10.0", "\"Windows\""),
+            OperatingSystem::MacOs => ("Macintosh", "\"macOS\""),
+            OperatingSystem::Linux => ("X11; Linux", "\"Linux\""),
+            OperatingSystem::Android => ("Android", "\"Android\""),
+            OperatingSystem::Ios => ("iPhone", "\"iOS\""),
+        };
+        assert!(user_agent.contains(system), "{:?} {}", fingerprint.operating_system, user_agent);
+        if let Some(platform) = platform {
+            assert_eq!(platform, platform_name);
+        }
+
+        // No impossible combinations.
+        match fingerprint.browser {
+            Browser::Safari => assert_eq!(fingerprint.operating_system, OperatingSystem::MacOs),
+            Browser::SafariIos => assert_eq!(fingerprint.operating_system, OperatingSystem::Ios),
+            Browser::ChromeAndroid => assert_eq!(fingerprint.operating_system, OperatingSystem::Android),
+            Browser::Edge => assert!([OperatingSystem::Windows, OperatingSystem::MacOs].contains(&fingerprint.operating_system)),
+            Browser::Chrome | Browser::Firefox => assert_eq!(fingerprint.device, Device::Desktop),
+        }
+    }
+}
+
+#[test]
+fn constrained_fingerprints() {
+    for seed in 0..50 {
+        let fingerprint = fingerprint(FingerprintOptions {
+            browsers: vec![Browser::Firefox, Browser::Chrome],
+            operating_systems: vec![OperatingSystem::Windows],
+            locales: vec!["de-DE".into()],
+            min_version: Some(120),
+            max_version: Some(122),
+            seed: Some(seed),
+            ..Default::default()
+        });
+
+        assert!([Browser::Firefox, Browser::Chrome].contains(&fingerprint.browser));
+        assert_eq!(fingerprint.operating_system, OperatingSystem::Windows);
+        assert!((120..=122).contains(&fingerprint.version));
+        assert_eq!(fingerprint.locale, "de-DE");
+    }
+
+    let mobile = fingerprint(FingerprintOptions { devices: vec![Device::Mobile], seed: Some(1), ..Default::default() });
+    assert!([Browser::ChromeAndroid, Browser::SafariIos].contains(&mobile.browser));
+    assert!(mobile.profile.user_agent().contains("Mobile"));
+
+    let firefox = fingerprint(FingerprintOptions {
+        browsers: vec![Browser::Firefox],
+        operating_systems: vec![OperatingSystem::MacOs],
+        locales: vec!["fr-FR".into()],
+        max_version: Some(127),
+        min_version: Some(127),
+        seed: Some(7),
+        ..Default::default()
+    });
+    assert_eq!(firefox.profile.user_agent(), "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:127.0) Gecko/20100101 Firefox/127.0");
+    assert_eq!(firefox.accept_language, "fr-FR,fr;q=0.8,en-US;q=0.5,en;q=0.3");
+    assert_eq!(firefox.profile.name(), "firefox-127-macos");
+
+    let edge = fingerprint(FingerprintOptions {
+        browsers: vec![Browser::Edge],
+        operating_systems: vec![OperatingSystem::MacOs],
+        locales: vec!["en-US".into()],
+        seed: Some(7),
+        ..Default::default()
+    });
+    assert!(edge.profile.user_agent().starts_with("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36"));
+    assert_eq!(edge.accept_language, "en-US,en;q=0.9");
+    assert_eq!(header(&edge, "sec-ch-ua-platform"), Some("\"macOS\""));
+
+    let safari = fingerprint(FingerprintOptions { browsers: vec![Browser::Safari], locales: vec!["cs-CZ".into()], ..Default::default() });
+    assert_eq!(safari.accept_language, "cs-CZ,cs;q=0.9");
+}
+
+#[test]
+fn impossible_fingerprints() {
+    let impossible = [
+        // Safari only runs on macOS (and iOS).
+        FingerprintOptions { browsers: vec![Browser::Safari], operating_systems: vec![OperatingSystem::Linux], ..Default::default() },
+        FingerprintOptions { browsers: vec![Browser::Chrome], devices: vec![Device::Mobile], ..Default::default() },
+        // The Safari profile only impersonates its own version.
+        FingerprintOptions { browsers: vec![Browser::Safari], min_version: Some(18), ..Default::default() },
+        // The Chrome profile doesn't know these versions.
+        FingerprintOptions { browsers: vec![Browser::Chrome], min_version: Some(200), ..Default::default() },
+        FingerprintOptions { browsers: vec![Browser::Chrome], max_version: Some(50), ..Default::default() },
+        FingerprintOptions { browsers: vec![Browser::Chrome], min_version: Some(122), max_version: Some(120), ..Default::default() },
+    ];
+
+    for options in impossible {
+        let error = generate_fingerprint(&options).unwrap_err();
+        assert_eq!(error.kind, FetchErrorKind::InvalidProfile);
+        assert_eq!(error.message, "No browser fingerprint matches the constraints");
+    }
+
+    // Every locale is checked, not only the one picked.
+    for seed in 0..10 {
+        let options = FingerprintOptions { locales: vec!["de-DE".into(), "german".into()], seed: Some(seed), ..Default::default() };
+        let error = generate_fingerprint(&options).unwrap_err();
+        assert_eq!(error.message, "'german' is not a valid locale, e.g. 'de-DE'");
+    }
+}
+
+#[test]
+fn fingerprint_versions_are_clamped() {
+    let chrome = BrowserProfile::builtin(&Browser::Chrome).version();
+
+    for seed in 0..20 {
+        let fingerprint = fingerprint(FingerprintOptions {
+            browsers: vec![Browser::Chrome],
+            min_version: Some(1),
+            max_version: Some(1000),
+            seed: Some(seed),
+            ..Default::default()
+        });
+
+        assert!((chrome - 11..=chrome).contains(&fingerprint.version), "{}", fingerprint.version);
+    }
+}
+
+#[tokio::test]
+async fn retcher_with_fingerprint() {
+    let server = recording_server().await;
+    let options = FingerprintOptions { browsers: vec![Browser::Firefox], locales: vec!["cs-CZ".into()], seed: Some(3), ..Default::default() };
+    let expected = fingerprint(options.clone());
+
+    let retcher = Retcher::new(EngineOptions { fingerprint: Some(options), ..Default::default() });
+    assert_eq!(retcher.browser, Browser::Firefox);
+    assert_eq!(retcher.profile().unwrap(), &expected.profile);
+
+    retcher.retch(format!("{}/", server.url), None).await.unwrap();
+
+    let head = &server.heads()[0];
+    assert!(head.contains(&format!("\r\nUser-Agent: {}\r\n", expected.profile.user_agent())), "{}", head);
+    assert!(head.contains("\r\nAccept-Language: cs-CZ,cs;q=0.8,en-US;q=0.5,en;q=0.3\r\n"), "{}", head);
+}
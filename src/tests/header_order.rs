@@ -0,0 +1,99 @@
+use indexmap::IndexMap;
+use reqwest::Method;
+
+use crate::retcher::retcher::{Browser, FetchOptions, ProxyOptions, RequestBody};
+use super::common::{parse_head, retcher};
+use super::server::get_server;
+use super::server::proxy::http_proxy;
+use super::server::raw::recording_server;
+use super::server::tls::tls_server;
+
+fn custom_headers(headers: &[(&str, &str)]) -> IndexMap<String, String> {
+    headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+/// Splits a request head into the request line and the header names, as sent.
+fn request_line_and_names(head: &str) -> (String, Vec<String>) {
+    let request_line = head.split("\r\n").next().unwrap_or_default().to_string();
+    let names = parse_head(head).into_iter().map(|(name, _)| name).collect();
+
+    (request_line, names)
+}
+
+#[tokio::test]
+async fn chrome_http1_header_order() {
+    let server = recording_server().await;
+
+    let mut response = retcher(Browser::Chrome, None).retch(format!("{}/path?query=1", server.url), Some(FetchOptions {
+        headers: custom_headers(&[("X-First", "1"), ("x-second", "2"), ("accept-language", "cs-CZ")]),
+        ..Default::default()
+    })).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+
+    let heads = server.heads();
+    let (request_line, names) = request_line_and_names(&heads[0]);
+
+    // The request goes to the server with the path, even though `reqwest` forwards it to the relay.
+    assert_eq!(request_line, "GET /path?query=1 HTTP/1.1");
+    // The headers set by the page go before `User-Agent`, in their order and casing.
+    assert_eq!(names, vec![
+        "Host", "Connection", "Upgrade-Insecure-Requests", "X-First", "x-second", "User-Agent", "Accept", "Accept-Encoding", "Accept-Language",
+    ]);
+    assert!(heads[0].contains("\r\nAccept-Language: cs-CZ\r\n"), "{}", heads[0]);
+}
+
+#[tokio::test]
+async fn firefox_http1_header_order() {
+    let server = recording_server().await;
+
+    retcher(Browser::Firefox, None).retch(format!("{}/", server.url), Some(FetchOptions {
+        method: Method::POST,
+        headers: custom_headers(&[("X-Requested-With", "XMLHttpRequest")]),
+        body: Some(RequestBody::Text("Hello!".into())),
+        ..Default::default()
+    })).await.unwrap();
+
+    let (_, names) = request_line_and_names(&server.heads()[0]);
+
+    // The headers set by the page go after `Accept-Encoding`.
+    assert_eq!(names, vec![
+        "Host", "User-Agent", "Accept", "Accept-Language", "Accept-Encoding", "X-Requested-With", "Content-Type", "Content-Length", "Origin",
+        "Connection", "Upgrade-Insecure-Requests", "Priority",
+    ]);
+}
+
+#[tokio::test]
+async fn https_http1_header_casing() {
+    let server = tls_server().await;
+
+    retcher(Browser::Chrome, None).retch(format!("{}/", server.url), None).await.unwrap();
+
+    let (request_line, names) = request_line_and_names(&server.heads()[0]);
+    assert_eq!(request_line, "GET / HTTP/1.1");
+
+    // Over HTTP/1.1, `Host` is where the browser puts it, whether it's HTTPS or not.
+    assert_eq!(names, vec![
+        "Host", "Connection", "sec-ch-ua", "sec-ch-ua-mobile", "sec-ch-ua-platform", "Upgrade-Insecure-Requests", "User-Agent", "Accept",
+        "Sec-Fetch-Site", "Sec-Fetch-Mode", "Sec-Fetch-User", "Sec-Fetch-Dest", "Accept-Encoding", "Accept-Language",
+    ]);
+}
+
+#[tokio::test]
+async fn forwarded_header_order() {
+    get_server().await;
+    let proxy = http_proxy().await;
+
+    let response = retcher(Browser::Firefox, Some(ProxyOptions::new(&proxy.url))).retch("http://127.0.0.1:8000/".into(), Some(FetchOptions {
+        headers: custom_headers(&[("DNT", "1")]),
+        ..Default::default()
+    })).await.unwrap();
+    assert_eq!(response.status, 200);
+
+    let requests = proxy.requests();
+    assert_eq!(requests[0].request_line, "GET http://127.0.0.1:8000/ HTTP/1.1");
+
+    let names: Vec<&str> = requests[0].headers.iter().filter_map(|line| line.split_once(':')).map(|(name, _)| name).collect();
+    assert_eq!(names, vec![
+        "Host", "User-Agent", "Accept", "Accept-Language", "Accept-Encoding", "DNT", "Connection", "Upgrade-Insecure-Requests", "Priority",
+    ]);
+}
@@ -0,0 +1,49 @@
+use crate::retcher::retcher::{Browser, ProxyOptions};
+use super::common::{header, parse_head, retcher};
+use super::server::proxy::http_proxy;
+use super::server::raw::recording_server;
+use super::server::tls::{http2_server, tls_server};
+
+fn host_header(head: &str) -> Option<String> {
+    header(&parse_head(head), "Host").map(str::to_string)
+}
+
+#[tokio::test]
+async fn non_default_ports() {
+    let server = recording_server().await;
+    retcher(Browser::Chrome, None).retch(format!("{}/", server.url), None).await.unwrap();
+    assert_eq!(host_header(&server.heads()[0]).as_deref(), Some(server.url.trim_start_matches("http://")));
+
+    let server = tls_server().await;
+    retcher(Browser::Chrome, None).retch(format!("{}/", server.url), None).await.unwrap();
+    assert_eq!(host_header(&server.heads()[0]).as_deref(), Some(server.url.trim_start_matches("https://")));
+
+    let server = http2_server().await;
+    retcher(Browser::Chrome, None).retch(format!("{}/", server.url), None).await.unwrap();
+    let fields = &server.connections()[0].headers[0].fields;
+    let authority = fields.iter().find(|(name, _)| name == ":authority").map(|(_, value)| value.as_str());
+    assert_eq!(authority, Some(server.url.trim_start_matches("https://")));
+}
+
+/// The hosts that don't resolve locally are checked through the proxy, which sees the `Host` header as sent.
+#[tokio::test]
+async fn proxied_hosts() {
+    let proxy = http_proxy().await;
+    let retcher = retcher(Browser::Chrome, Some(ProxyOptions::new(&proxy.url)));
+
+    for (url, expected) in [
+        ("http://example.com:80/", "example.com"),
+        ("http://example.com:443/", "example.com:443"),
+        ("http://[::1]:8080/", "[::1]:8080"),
+        ("http://[2001:DB8::1]/", "[2001:db8::1]"),
+        ("http://bücher.example/", "xn--bcher-kva.example"),
+        ("http://ÄÖÜ.example:8080/", "xn--4ca0bs.example:8080"),
+    ] {
+        // The proxy can't reach them and answers `502 Bad Gateway`.
+        let _ = retcher.retch(url.into(), None).await;
+
+        let request = proxy.requests().pop().unwrap();
+        let host = request.headers.iter().find_map(|line| line.strip_prefix("Host: ")).map(str::to_string);
+        assert_eq!(host.as_deref(), Some(expected), "{}", url);
+    }
+}
@@ -0,0 +1,171 @@
+use indexmap::IndexMap;
+
+use crate::retcher::retcher::{Browser, Destination, FetchOptions, RequestBody};
+use super::common::retcher;
+use super::server::tls::{http2_server, Http2Connection};
+
+/// Sends a request to a fresh HTTP/2 stand-in and returns the connection it got.
+async fn connection(browser: Browser) -> Http2Connection {
+    let server = http2_server().await;
+    let mut response = retcher(browser, None).retch(format!("{}/", server.url), None).await.unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+
+    let connections = server.connections();
+    assert_eq!(connections.len(), 1);
+    connections[0].clone()
+}
+
+/// Computes the Akamai HTTP/2 fingerprint (`SETTINGS|WINDOW_UPDATE|PRIORITY frames|pseudo-header order`) of the connection's first request.
+fn akamai_fingerprint(connection: &Http2Connection) -> String {
+    let settings: Vec<String> = connection.settings.iter().map(|(id, value)| format!("{}:{}", id, value)).collect();
+    let pseudo_headers: Vec<&str> = connection.headers[0].fields.iter()
+        .filter_map(|(name, _)| name.strip_prefix(':'))
+        .map(|name| &name[..1])
+        .collect();
+
+    format!("{}|{}|0|{}", settings.join(";"), connection.window_update.unwrap_or(0), pseudo_headers.join(","))
+}
+
+#[tokio::test]
+async fn chrome_http2_fingerprint() {
+    let connection = connection(Browser::Chrome).await;
+
+    assert_eq!(akamai_fingerprint(&connection), "1:65536;2:0;4:6291456;6:262144|15663105|0|m,a,s,p");
+    assert_eq!(connection.headers[0].priority, Some((true, 0, 256)));
+}
+
+#[tokio::test]
+async fn firefox_http2_fingerprint() {
+    let connection = connection(Browser::Firefox).await;
+
+    assert_eq!(akamai_fingerprint(&connection), "1:65536;2:0;4:131072;5:16384|12517377|0|m,p,a,s");
+    assert_eq!(connection.headers[0].priority, Some((false, 0, 42)));
+}
+
+#[tokio::test]
+async fn http2_request_headers() {
+    let connection = connection(Browser::Chrome).await;
+    let headers = &connection.headers[0];
+
+    // END_STREAM | END_HEADERS | PRIORITY
+    assert_eq!(headers.flags, 0x1 | 0x4 | 0x20);
+
+    let authority = headers.fields.iter().find(|(name, _)| name == ":authority").unwrap();
+    assert!(authority.1.starts_with("127.0.0.1:"));
+    // The profile's `Host` is only sent over HTTP/1.1, on HTTP/2 it's the `:authority`.
+    assert!(headers.fields.iter().all(|(name, _)| name != "host"));
+    assert_eq!(headers.fields[0], (":method".into(), "GET".into()));
+    assert_eq!(headers.fields[2], (":scheme".into(), "https".into()));
+    assert_eq!(headers.fields[3], (":path".into(), "/".into()));
+
+    // The regular headers follow the pseudo-headers.
+    assert!(headers.fields[4..].iter().all(|(name, _)| !name.starts_with(':')));
+    assert!(headers.fields.iter().any(|(name, value)| name == "user-agent" && value.contains("Chrome")));
+}
+
+#[tokio::test]
+async fn http2_connection_reuse() {
+    let server = http2_server().await;
+    let retcher = retcher(Browser::Firefox, None);
+
+    for path in ["/first", "/second?query=1"] {
+        let mut response = retcher.retch(format!("{}{}", server.url, path), Some(FetchOptions {
+            method: reqwest::Method::POST,
+            body: Some(RequestBody::Text("Hello!".into())),
+            ..Default::default()
+        })).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "Hello, world!");
+    }
+
+    let connections = server.connections();
+    assert_eq!(connections.len(), 1);
+
+    let requests: Vec<(&str, &str)> = connections[0].headers.iter()
+        .map(|headers| (headers.fields[0].1.as_str(), headers.fields[1].1.as_str()))
+        .collect();
+    assert_eq!(requests, vec![("POST", "/first"), ("POST", "/second?query=1")]);
+    // The body follows in DATA frames.
+    assert_eq!(connections[0].headers[0].flags & 0x1, 0);
+}
+
+#[tokio::test]
+async fn http2_destination_priority() {
+    let server = http2_server().await;
+    let retcher = retcher(Browser::Chrome, None);
+
+    for destination in [Destination::Image, Destination::Script, Destination::Style] {
+        retcher.retch(format!("{}/", server.url), Some(FetchOptions { destination, ..Default::default() })).await.unwrap();
+    }
+
+    let priorities: Vec<_> = server.connections()[0].headers.iter().map(|headers| headers.priority).collect();
+    assert_eq!(priorities, vec![Some((true, 0, 147)), Some((true, 0, 220)), Some((true, 0, 256))]);
+}
+
+#[tokio::test]
+async fn http2_header_compression() {
+    let server = http2_server().await;
+    let retcher = retcher(Browser::Chrome, None);
+
+    for _ in 0..2 {
+        retcher.retch(format!("{}/", server.url), None).await.unwrap();
+    }
+
+    let connections = server.connections();
+    let [first, second] = &connections[0].headers[..] else { panic!("expected two requests") };
+    assert_eq!(first.fields, second.fields);
+
+    // Like Chrome, the pseudo-headers but `:authority` aren't indexed, the other fields are, and the strings are Huffman-coded.
+    assert!(first.indexed.iter().all(|name| !name.starts_with(':') || name == ":authority"));
+    assert!(first.indexed.iter().any(|name| name == ":authority"));
+    assert!(first.indexed.iter().any(|name| name == "user-agent"));
+    assert!(!first.block.windows(7).any(|window| window == b"Mozilla"));
+
+    // The second request refers to the indexed fields.
+    assert!(second.indexed.is_empty());
+    assert!(second.block.len() * 4 < first.block.len());
+}
+
+#[tokio::test]
+async fn http2_browser_header_table_size() {
+    let server = http2_server().await;
+    let retcher = retcher(Browser::Chrome, None);
+
+    // The server indexes a header that only fits the 64 KiB table Chrome announces, not the 4 KiB one `reqwest` expects, then refers to it.
+    for _ in 0..2 {
+        let response = retcher.retch(format!("{}/large-header", server.url), None).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("x-large"), Some(&"a".repeat(5000)));
+    }
+    assert_eq!(server.connections().len(), 1);
+}
+
+#[tokio::test]
+async fn http2_header_order() {
+    let server = http2_server().await;
+
+    retcher(Browser::Chrome, None).retch(format!("{}/", server.url), Some(FetchOptions {
+        headers: IndexMap::from_iter([("X-First".to_string(), "1".to_string()), ("x-second".to_string(), "2".to_string())]),
+        ..Default::default()
+    })).await.unwrap();
+
+    let connections = server.connections();
+    let names: Vec<&str> = connections[0].headers[0].fields.iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| !name.starts_with(':'))
+        .collect();
+
+    // Lowercase, in the browser's order, without the relay's private headers.
+    assert_eq!(names, vec![
+        "sec-ch-ua", "sec-ch-ua-mobile", "sec-ch-ua-platform", "upgrade-insecure-requests", "x-first", "x-second", "user-agent", "accept",
+        "sec-fetch-site", "sec-fetch-mode", "sec-fetch-user", "sec-fetch-dest", "accept-encoding", "accept-language",
+    ]);
+}
+
+#[tokio::test]
+async fn safari_http2_fingerprint() {
+    let connection = connection(Browser::Safari).await;
+
+    assert_eq!(akamai_fingerprint(&connection), "2:0;3:100;4:4194304|10485760|0|m,s,p,a");
+    assert_eq!(connection.headers[0].priority, Some((false, 0, 255)));
+}
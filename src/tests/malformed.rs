@@ -0,0 +1,86 @@
+use indexmap::IndexMap;
+
+use crate::retcher::retcher::{Browser, EngineOptions, FetchError, FetchErrorKind, FetchOptions, FetchPhase, FetchResponse, Retcher};
+use super::server::raw::raw_server;
+
+async fn retch(url: &str, headers: Vec<(&str, &str)>) -> Result<FetchResponse, FetchError> {
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Firefox),
+        ignore_tls_errors: None,
+        ..Default::default()
+    });
+
+    retcher.retch(url.into(), Some(FetchOptions {
+        headers: IndexMap::from_iter(headers.into_iter().map(|(key, value)| (key.to_string(), value.to_string()))),
+        ..Default::default()
+    })).await
+}
+
+#[tokio::test]
+async fn invalid_urls() {
+    for url in ["", "not a url", "http://", "http://exa mple.com/", "http://[::1/", "https://example.com:99999/"] {
+        let error = retch(url, vec![]).await.err().unwrap();
+
+        assert_eq!(error.kind, FetchErrorKind::InvalidUrl, "{}", url);
+        assert_eq!(error.phase, FetchPhase::Setup);
+        assert_eq!(error.url.as_deref(), Some(url));
+    }
+}
+
+#[tokio::test]
+async fn hostless_urls() {
+    for url in ["data:text/plain,hello", "mailto:jindrich@example.com", "file:///etc/passwd"] {
+        let error = retch(url, vec![]).await.err().unwrap();
+
+        assert_eq!(error.kind, FetchErrorKind::UnsupportedProtocol, "{}", url);
+    }
+}
+
+#[tokio::test]
+async fn invalid_custom_headers() {
+    let url = raw_server(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+
+    for (key, value) in [("bad header", "value"), ("X-Multiline", "first\nsecond"), ("", "value")] {
+        let error = retch(&url, vec![(key, value)]).await.err().unwrap();
+
+        assert_eq!(error.kind, FetchErrorKind::InvalidRequest, "{:?}", key);
+        assert_eq!(error.phase, FetchPhase::Setup);
+        assert!(error.url.is_some());
+    }
+}
+
+#[tokio::test]
+async fn non_utf8_response_headers() {
+    let url = raw_server(b"HTTP/1.1 200 OK\r\nX-Latin-1: caf\xe9\r\nX-Utf-8: \xc5\xa1\r\nContent-Length: 2\r\n\r\nok").await;
+
+    let mut response = retch(&url, vec![]).await.unwrap();
+
+    // Header values are decoded byte by byte, like `fetch` does.
+    assert_eq!(response.headers.get("x-latin-1").unwrap(), "caf\u{e9}");
+    assert_eq!(response.headers.get("x-utf-8").unwrap(), "\u{c5}\u{a1}");
+    assert_eq!(response.bytes().await.unwrap(), b"ok");
+}
+
+#[tokio::test]
+async fn unknown_status_codes() {
+    let url = raw_server(b"HTTP/1.1 520 Web Server Returned an Unknown Error\r\nContent-Length: 0\r\n\r\n").await;
+    let response = retch(&url, vec![]).await.unwrap();
+
+    assert_eq!(response.status, 520);
+    assert_eq!(response.status_text, "Web Server Returned an Unknown Error");
+    assert!(!response.ok);
+
+    let url = raw_server(b"HTTP/1.1 599 \r\nContent-Length: 0\r\n\r\n").await;
+    let response = retch(&url, vec![]).await.unwrap();
+
+    assert_eq!(response.status, 599);
+    assert_eq!(response.status_text, "");
+}
+
+#[tokio::test]
+async fn custom_reason_phrase() {
+    let url = raw_server(b"HTTP/1.1 200 Alright\r\nContent-Length: 0\r\n\r\n").await;
+    let response = retch(&url, vec![]).await.unwrap();
+
+    assert_eq!(response.status_text, "Alright");
+}
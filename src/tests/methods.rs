@@ -0,0 +1,140 @@
+use std::iter::zip;
+
+use reqwest::Method;
+use serde_json::json;
+
+use crate::retcher::retcher::{Browser, EngineOptions, FetchOptions, RequestBody, Retcher};
+use super::server::{get_server, echo::RequestEcho};
+
+async fn echo_request(browser: Browser, method: Method, body: Option<RequestBody>) -> RequestEcho {
+    get_server().await;
+
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(browser),
+        ignore_tls_errors: Some(true),
+        ..Default::default()
+    });
+
+    let response = retcher.retch("http://127.0.0.1:8000/echo".into(), Some(FetchOptions {
+        method,
+        body,
+        ..Default::default()
+    })).await;
+
+    match response {
+        Ok(mut response) => serde_json::from_str(String::from_utf8(response.bytes().await.unwrap()).unwrap().as_str()).unwrap(),
+        Err(e) => panic!("{:?}", e),
+    }
+}
+
+fn assert_headers(echo: &RequestEcho, expected: Vec<(&str, &str)>) {
+    assert_eq!(echo.headers.len(), expected.len());
+
+    for pair in zip(echo.headers.iter(), expected) {
+        let (header, (key, value)) = pair;
+        assert_eq!(header.0, key);
+        assert_eq!(header.1, value);
+    }
+}
+
+#[tokio::test]
+async fn chrome_form_post() {
+    let echo = echo_request(Browser::Chrome, Method::POST, Some(RequestBody::Form(vec![
+        ("user".into(), "jindrich".into()),
+        ("password".into(), "s3cr3t & more".into()),
+    ]))).await;
+
+    assert_eq!(echo.method, "POST");
+    assert_eq!(echo.body, "user=jindrich&password=s3cr3t+%26+more");
+
+    assert_headers(&echo, vec![
+        ("host", "127.0.0.1:8000"),
+        ("connection", "keep-alive"),
+        ("content-length", "38"),
+        ("upgrade-insecure-requests", "1"),
+        ("origin", "http://127.0.0.1:8000"),
+        ("content-type", "application/x-www-form-urlencoded"),
+        ("user-agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/125.0.0.0 Safari/537.36"),
+        ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"),
+        ("accept-encoding", "gzip, deflate"),
+        ("accept-language", "en-US,en;q=0.9"),
+    ]);
+}
+
+#[tokio::test]
+async fn firefox_json_put() {
+    let payload = json!({ "name": "retch", "stealthy": true });
+    let echo = echo_request(Browser::Firefox, Method::PUT, Some(RequestBody::Json(payload.clone()))).await;
+
+    assert_eq!(echo.method, "PUT");
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&echo.body).unwrap(), payload);
+
+    let content_length = payload.to_string().len().to_string();
+
+    assert_headers(&echo, vec![
+        ("host", "127.0.0.1:8000"),
+        ("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"),
+        ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/png,image/svg+xml,*/*;q=0.8"),
+        ("accept-language", "en,cs;q=0.7,en-US;q=0.3"),
+        ("accept-encoding", "gzip, deflate"),
+        ("content-type", "application/json"),
+        ("content-length", content_length.as_str()),
+        ("origin", "http://127.0.0.1:8000"),
+        ("connection", "keep-alive"),
+        ("upgrade-insecure-requests", "1"),
+        ("priority", "u=0, i"),
+    ]);
+}
+
+#[tokio::test]
+async fn text_patch() {
+    let echo = echo_request(Browser::Chrome, Method::PATCH, Some(RequestBody::Text("Hello, server!".into()))).await;
+
+    assert_eq!(echo.method, "PATCH");
+    assert_eq!(echo.body, "Hello, server!");
+    assert!(echo.headers.contains(&("content-type".into(), "text/plain;charset=UTF-8".into())));
+}
+
+#[tokio::test]
+async fn raw_bytes_have_no_content_type() {
+    let echo = echo_request(Browser::Chrome, Method::POST, Some(RequestBody::Bytes(b"raw".to_vec()))).await;
+
+    assert_eq!(echo.body, "raw");
+    assert!(echo.headers.contains(&("content-length".into(), "3".into())));
+    assert!(!echo.headers.iter().any(|(key, _)| key == "content-type"));
+}
+
+#[tokio::test]
+async fn empty_post_sends_zero_length() {
+    let echo = echo_request(Browser::Firefox, Method::POST, None).await;
+
+    assert_eq!(echo.body, "");
+    assert!(echo.headers.contains(&("content-length".into(), "0".into())));
+    assert!(echo.headers.contains(&("origin".into(), "http://127.0.0.1:8000".into())));
+}
+
+#[tokio::test]
+async fn delete_without_body() {
+    let echo = echo_request(Browser::Chrome, Method::DELETE, None).await;
+
+    assert_eq!(echo.method, "DELETE");
+    assert!(!echo.headers.iter().any(|(key, _)| key == "content-length"));
+    assert!(echo.headers.contains(&("origin".into(), "http://127.0.0.1:8000".into())));
+}
+
+#[tokio::test]
+async fn get_has_no_origin() {
+    get_server().await;
+
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Firefox),
+        ignore_tls_errors: None,
+        ..Default::default()
+    });
+
+    let mut response = retcher.retch("http://127.0.0.1:8000/headers".into(), None).await.unwrap();
+    let body = String::from_utf8(response.bytes().await.unwrap()).unwrap();
+
+    assert!(!body.contains("origin"));
+    assert!(!body.contains("content-length"));
+}
@@ -2,5 +2,29 @@
 mod basic;
 mod requests;
 mod server;
+mod common;
 mod e2e;
-mod compression;
\ No newline at end of file
+mod compression;
+mod methods;
+mod errors;
+mod malformed;
+mod streaming;
+mod body;
+mod redirects;
+mod site;
+mod cookies;
+mod cookie_formats;
+mod proxy;
+mod proxy_auth;
+mod proxy_pool;
+mod tls;
+mod http2;
+mod header_order;
+mod profile;
+mod browsers;
+mod fingerprint;
+mod destinations;
+mod referrer;
+mod navigation;
+mod host;
+mod relay;
@@ -0,0 +1,129 @@
+use indexmap::IndexMap;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::retcher::retcher::{Browser, Destination, FetchErrorKind, FetchOptions, NavigationType};
+use super::common::{self, header, parse_head, retcher};
+use super::server::raw::recording_server;
+use super::server::tls::tls_server;
+
+fn navigation(navigation_type: NavigationType) -> FetchOptions {
+    FetchOptions { navigation_type, ..Default::default() }
+}
+
+/// Sends the request over HTTPS (and HTTP/1.1) and returns the headers as sent.
+async fn https_headers(browser: Browser, options: FetchOptions) -> Vec<(String, String)> {
+    common::https_headers(&retcher(browser, None), Some(options)).await
+}
+
+/// Sends the request over plain HTTP and returns the header names in the order they were sent.
+async fn http_header_names(browser: Browser, options: FetchOptions) -> Vec<String> {
+    let server = recording_server().await;
+    retcher(browser, None).retch(format!("{}/", server.url), Some(options)).await.unwrap();
+    parse_head(&server.heads()[0]).into_iter().map(|(name, _)| name).collect()
+}
+
+#[tokio::test]
+async fn reload_headers() {
+    let reload = https_headers(Browser::Chrome, navigation(NavigationType::Reload)).await;
+    assert_eq!(header(&reload, "Cache-Control"), Some("max-age=0"));
+    assert_eq!(header(&reload, "Pragma"), None);
+    assert_eq!(header(&reload, "Sec-Fetch-User"), Some("?1"));
+
+    let hard_reload = https_headers(Browser::Chrome, navigation(NavigationType::HardReload)).await;
+    assert_eq!(header(&hard_reload, "Cache-Control"), Some("no-cache"));
+    assert_eq!(header(&hard_reload, "Pragma"), Some("no-cache"));
+
+    assert_eq!(
+        http_header_names(Browser::Chrome, navigation(NavigationType::HardReload)).await[..5],
+        ["Host", "Connection", "Pragma", "Cache-Control", "Upgrade-Insecure-Requests"],
+    );
+
+    // Firefox sends them last.
+    let names = http_header_names(Browser::Firefox, navigation(NavigationType::HardReload)).await;
+    assert_eq!(names[names.len() - 3..], ["Priority", "Pragma", "Cache-Control"]);
+
+    let safari = https_headers(Browser::Safari, navigation(NavigationType::Reload)).await;
+    assert_eq!(header(&safari, "Cache-Control"), Some("max-age=0"));
+}
+
+#[tokio::test]
+async fn back_forward_headers() {
+    // The servers listen on different ports.
+    let without_host = |headers: Vec<(String, String)>| headers.into_iter().filter(|(name, _)| name != "Host").collect::<Vec<_>>();
+
+    for browser in [Browser::Chrome, Browser::Firefox, Browser::Safari] {
+        let normal = https_headers(browser.clone(), navigation(NavigationType::Normal)).await;
+        let back_forward = https_headers(browser, navigation(NavigationType::BackForward)).await;
+        assert_eq!(without_host(normal), without_host(back_forward));
+    }
+}
+
+#[tokio::test]
+async fn prefetch_headers() {
+    let prefetch = https_headers(Browser::Chrome, navigation(NavigationType::Prefetch)).await;
+    assert_eq!(header(&prefetch, "Purpose"), Some("prefetch"));
+    assert_eq!(header(&prefetch, "Sec-Purpose"), Some("prefetch"));
+    // The user didn't navigate (yet).
+    assert_eq!(header(&prefetch, "Sec-Fetch-User"), None);
+    assert_eq!(header(&prefetch, "Cache-Control"), None);
+
+    let prerender = https_headers(Browser::Edge, navigation(NavigationType::Prerender)).await;
+    assert_eq!(header(&prerender, "Sec-Purpose"), Some("prefetch;prerender"));
+    assert_eq!(header(&prerender, "Sec-Fetch-User"), None);
+
+    let firefox = https_headers(Browser::Firefox, navigation(NavigationType::Prefetch)).await;
+    assert_eq!(header(&firefox, "Sec-Purpose"), Some("prefetch"));
+    assert_eq!(header(&firefox, "Purpose"), None);
+}
+
+#[tokio::test]
+async fn custom_cache_headers() {
+    let headers = https_headers(Browser::Chrome, FetchOptions {
+        headers: IndexMap::from([("cache-control".to_string(), "max-age=60".to_string())]),
+        ..navigation(NavigationType::Reload)
+    }).await;
+
+    assert_eq!(headers.iter().filter(|(name, _)| name.eq_ignore_ascii_case("Cache-Control")).count(), 1);
+    assert_eq!(header(&headers, "Cache-Control"), Some("max-age=60"));
+}
+
+#[tokio::test]
+async fn typed_navigation_redirects() {
+    let server = tls_server().await;
+    let target = format!("{}/final", server.url.replace("127.0.0.1", "localhost"));
+    let retcher = retcher(Browser::Chrome, None);
+    for cookie in ["strict=1; SameSite=Strict; Secure", "lax=1; SameSite=Lax; Secure"] {
+        retcher.cookies().set_cookie(cookie, &target).unwrap();
+    }
+
+    let url = format!("{}/redirect?to={}", server.url, utf8_percent_encode(&target, NON_ALPHANUMERIC));
+    retcher.retch(url.clone(), None).await.unwrap();
+    retcher.retch(url, Some(FetchOptions { referrer: Some(format!("{}/page", server.url)), ..Default::default() })).await.unwrap();
+
+    let heads: Vec<_> = server.heads().iter().map(|head| parse_head(head)).collect();
+    // The user's navigation has no initiator to be cross-site to, even after a cross-site redirect.
+    assert_eq!(header(&heads[0], "Sec-Fetch-Site"), Some("none"));
+    assert_eq!(header(&heads[1], "Sec-Fetch-Site"), Some("none"));
+    // The redirect chain isn't same-site with where it ends up, so only the `Lax` cookies are sent.
+    assert_eq!(header(&heads[1], "Cookie"), Some("lax=1"));
+
+    assert_eq!(header(&heads[2], "Sec-Fetch-Site"), Some("same-origin"));
+    assert_eq!(header(&heads[3], "Sec-Fetch-Site"), Some("cross-site"));
+}
+
+#[tokio::test]
+async fn unsupported_navigation_types() {
+    let error = retcher(Browser::Firefox, None).retch("http://127.0.0.1:8000/".into(), Some(navigation(NavigationType::Prerender))).await.err().unwrap();
+    assert_eq!(error.kind, FetchErrorKind::InvalidRequest);
+    assert_eq!(error.message, "The browser profile 'firefox-128-linux' has no 'prerender' navigations");
+
+    let error = retcher(Browser::Safari, None).retch("http://127.0.0.1:8000/".into(), Some(navigation(NavigationType::Prefetch))).await.err().unwrap();
+    assert_eq!(error.message, "The browser profile 'safari-17-macos' has no 'prefetch' navigations");
+
+    let error = retcher(Browser::Chrome, None).retch("http://127.0.0.1:8000/".into(), Some(FetchOptions {
+        destination: Destination::Image,
+        ..navigation(NavigationType::Reload)
+    })).await.err().unwrap();
+    assert_eq!(error.kind, FetchErrorKind::InvalidRequest);
+    assert_eq!(error.message, "The 'reload' navigation type is only for the 'document' destination");
+}
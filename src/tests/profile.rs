@@ -0,0 +1,137 @@
+use serde_json::{json, Value};
+
+use crate::retcher::retcher::{Browser, BrowserProfile, EngineOptions, FetchErrorKind, FetchPhase, Retcher};
+use super::server::raw::recording_server;
+
+/// The built-in Chrome profile as JSON, to derive custom profiles from.
+fn chrome_json() -> Value {
+    serde_json::from_str(include_str!("../../profiles/chrome.json")).unwrap()
+}
+
+fn assert_invalid(profile: Value, message: &str) {
+    let error = BrowserProfile::from_json(&profile.to_string()).unwrap_err();
+
+    assert_eq!(error.kind, FetchErrorKind::InvalidProfile);
+    assert_eq!(error.phase, FetchPhase::Setup);
+    assert!(!error.is_retryable());
+    assert!(error.message.contains(message), "{}", error.message);
+}
+
+#[test]
+fn builtin_profiles() {
+    let chrome = BrowserProfile::builtin(&Browser::Chrome);
+    assert_eq!(chrome.name(), "chrome-125-linux");
+    assert_eq!(chrome.browser(), &Browser::Chrome);
+    assert!(chrome.user_agent().contains("Chrome/125"));
+
+    let firefox = BrowserProfile::builtin(&Browser::Firefox);
+    assert_eq!(firefox.name(), "firefox-128-linux");
+    assert_eq!(firefox.browser(), &Browser::Firefox);
+    assert!(firefox.user_agent().contains("Firefox/128"));
+
+    // The built-in profiles are parsed once.
+    assert!(std::sync::Arc::ptr_eq(&chrome, &BrowserProfile::builtin(&Browser::Chrome)));
+}
+
+#[tokio::test]
+async fn custom_profile() {
+    let server = recording_server().await;
+
+    let mut json = chrome_json();
+    json["name"] = json!("chrome-999-linux");
+    json["userAgent"] = json!("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/999.0.0.0 Safari/537.36");
+    json["headers"] = json!([
+        { "name": "Host", "value": "{host}", "http1": true },
+        { "name": "User-Agent", "value": "{userAgent}" },
+        { "name": "Accept", "value": "*/*" },
+        { "name": "X-Profile", "value": "custom" },
+        { "name": "Accept-Encoding", "value": "gzip" },
+        { "name": "Referer", "value": "{referer}", "optional": true },
+    ]);
+    json["customHeadersBefore"] = json!("Accept");
+
+    let profile = BrowserProfile::from_json(&json.to_string()).unwrap();
+    let retcher = Retcher::new(EngineOptions {
+        // The profile decides the browser.
+        browser: Some(Browser::Firefox),
+        profile: Some(profile),
+        ..Default::default()
+    });
+    assert_eq!(retcher.browser, Browser::Chrome);
+    assert_eq!(retcher.profile().unwrap().name(), "chrome-999-linux");
+
+    retcher.retch(format!("{}/", server.url), None).await.unwrap();
+
+    let head = &server.heads()[0];
+    let headers: Vec<&str> = head.trim_end().split("\r\n").skip(1).collect();
+    assert_eq!(headers, vec![
+        format!("Host: {}", server.url.trim_start_matches("http://")).as_str(),
+        "User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/999.0.0.0 Safari/537.36",
+        "Accept: */*",
+        "X-Profile: custom",
+        "Accept-Encoding: gzip",
+    ]);
+}
+
+#[test]
+fn invalid_profiles() {
+    assert_invalid(json!({ "name": "broken" }), "missing field");
+
+    let mut json = chrome_json();
+    json["userAgnet"] = json!("typo");
+    assert_invalid(json, "unknown field `userAgnet`");
+
+    let mut json = chrome_json();
+    json["browser"] = json!("netscape");
+    assert_invalid(json, "unknown variant `netscape`");
+
+    let mut json = chrome_json();
+    json["headers"][0]["value"] = json!("{userAgnet}");
+    assert_invalid(json, "unknown placeholder '{userAgnet}'");
+
+    let mut json = chrome_json();
+    json["headers"][0]["value"] = json!("{priority}");
+    assert_invalid(json, "has a '{priority}' placeholder, but the profile has no priorities");
+
+    let mut json = chrome_json();
+    json["accept"].as_object_mut().unwrap().remove("font");
+    assert_invalid(json, "missing field `font`");
+
+    let mut json = chrome_json();
+    json["headers"][0]["name"] = json!("Bad Header");
+    assert_invalid(json, "'Bad Header' isn't a valid header name");
+
+    let mut json = chrome_json();
+    json["headers"][0]["value"] = json!("line\nbreak");
+    assert_invalid(json, "isn't a valid header value");
+
+    let mut json = chrome_json();
+    json["customHeadersBefore"] = json!("X-Missing");
+    assert_invalid(json, "'X-Missing', which isn't one of its headers");
+
+    let mut json = chrome_json();
+    json["tls"]["cipherList"] = json!("NOT-A-CIPHER");
+    assert_invalid(json, "invalid TLS settings");
+
+    let mut json = chrome_json();
+    json["tls"]["alpn"] = json!([]);
+    assert_invalid(json, "ALPN protocols");
+
+    let mut json = chrome_json();
+    json["http2"]["pseudoHeaderOrder"] = json!(["method", "path", "scheme", "path"]);
+    assert_invalid(json, "pseudo-header order");
+
+    let mut json = chrome_json();
+    json["http2"]["priority"]["image"]["weight"] = json!(0);
+    assert_invalid(json, "priority weight 0");
+}
+
+#[test]
+fn profile_from_file() {
+    let profile = BrowserProfile::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/profiles/firefox.json")).unwrap();
+    assert_eq!(&profile, &*BrowserProfile::builtin(&Browser::Firefox));
+
+    let error = BrowserProfile::from_file("/nonexistent/profile.json").unwrap_err();
+    assert_eq!(error.kind, FetchErrorKind::InvalidProfile);
+    assert!(error.message.contains("Couldn't read the browser profile '/nonexistent/profile.json'"), "{}", error.message);
+}
@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::retcher::retcher::{Browser, EngineOptions, FetchOptions, ProxyOptions, ProxyPoolOptions, ProxyRotation, Retcher};
+use super::server::get_server;
+use super::server::proxy::{http_proxy, ProxiedRequest, ProxyServer};
+
+/// A proxy URL nothing listens on.
+const UNREACHABLE_PROXY: &str = "http://127.0.0.1:1";
+
+async fn proxies(count: usize) -> Vec<ProxyServer<ProxiedRequest>> {
+    let mut proxies = Vec::new();
+    for _ in 0..count {
+        proxies.push(http_proxy().await);
+    }
+    proxies
+}
+
+async fn retcher(urls: &[&str], rotation: ProxyRotation, cooldown: Option<Duration>) -> Retcher {
+    get_server().await;
+
+    Retcher::new(EngineOptions {
+        browser: Some(Browser::Chrome),
+        proxy_pool: Some(ProxyPoolOptions {
+            proxies: urls.iter().map(|url| ProxyOptions::new(*url)).collect(),
+            rotation,
+            cooldown,
+            block_statuses: None,
+        }),
+        ..Default::default()
+    })
+}
+
+/// Sends the request and returns the proxy that served it.
+async fn served_by(retcher: &Retcher, url: &str, session: Option<&str>) -> Option<String> {
+    let response = retcher.retch(url.into(), Some(FetchOptions {
+        session: session.map(str::to_string),
+        ..Default::default()
+    })).await.unwrap();
+
+    response.proxy
+}
+
+#[tokio::test]
+async fn round_robin_rotation() {
+    let proxies = proxies(3).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::RoundRobin, None).await;
+
+    let mut served = Vec::new();
+    for _ in 0..6 {
+        served.push(served_by(&retcher, "http://127.0.0.1:8000/", None).await.unwrap());
+    }
+
+    let expected: Vec<String> = urls.iter().cycle().take(6).map(|url| format!("{}/", url)).collect();
+    assert_eq!(served, expected);
+
+    for proxy in &proxies {
+        assert_eq!(proxy.requests().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn random_rotation() {
+    let proxies = proxies(3).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::Random, None).await;
+
+    for _ in 0..12 {
+        let proxy = served_by(&retcher, "http://127.0.0.1:8000/", None).await.unwrap();
+        assert!(urls.iter().any(|url| proxy == format!("{}/", url)));
+    }
+
+    assert_eq!(proxies.iter().map(|proxy| proxy.requests().len()).sum::<usize>(), 12);
+}
+
+#[tokio::test]
+async fn sticky_per_host_rotation() {
+    let proxies = proxies(3).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::StickyPerHost, None).await;
+
+    let first = served_by(&retcher, "http://127.0.0.1:8000/", None).await;
+    let second = served_by(&retcher, "http://localhost:8000/", None).await;
+    assert_ne!(first, second);
+
+    for _ in 0..3 {
+        assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/echo", None).await, first);
+        assert_eq!(served_by(&retcher, "http://localhost:8000/", None).await, second);
+    }
+
+    assert_eq!(proxies[2].requests().len(), 0);
+}
+
+#[tokio::test]
+async fn sticky_per_session_rotation() {
+    let proxies = proxies(2).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::StickyPerSession, None).await;
+
+    let first = served_by(&retcher, "http://127.0.0.1:8000/", Some("first")).await;
+    let second = served_by(&retcher, "http://127.0.0.1:8000/", Some("second")).await;
+    assert_ne!(first, second);
+
+    for _ in 0..3 {
+        assert_eq!(served_by(&retcher, "http://localhost:8000/", Some("second")).await, second);
+        assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/", Some("first")).await, first);
+    }
+
+    // Requests without a session are rotated.
+    let unsessioned: HashSet<Option<String>> = futures_util::future::join_all(
+        (0..2).map(|_| served_by(&retcher, "http://127.0.0.1:8000/", None)),
+    ).await.into_iter().collect();
+    assert_eq!(unsessioned.len(), 2);
+}
+
+#[tokio::test]
+async fn unreachable_proxy_is_skipped() {
+    let proxy = http_proxy().await;
+    let retcher = retcher(&[UNREACHABLE_PROXY, &proxy.url], ProxyRotation::RoundRobin, None).await;
+
+    let error = retcher.retch("http://127.0.0.1:8000/".into(), None).await.err().expect("the unreachable proxy fails");
+    assert!(error.is_retryable());
+
+    let status = retcher.proxy_pool().unwrap().status();
+    assert!(!status[0].healthy);
+    assert_eq!(status[0].failures, 1);
+    assert!(status[0].cooldown_remaining > Duration::from_secs(50));
+    assert!(status[1].healthy);
+
+    // Only the healthy proxy is used during the cooldown.
+    for _ in 0..3 {
+        assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/", None).await, Some(format!("{}/", proxy.url)));
+    }
+    assert_eq!(proxy.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn blocked_proxy_cools_down() {
+    let proxies = proxies(2).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::RoundRobin, Some(Duration::from_millis(300))).await;
+
+    let response = retcher.retch("http://127.0.0.1:8000/status/429".into(), None).await.unwrap();
+    assert_eq!(response.status, 429);
+    assert_eq!(response.proxy, Some(format!("{}/", urls[0])));
+
+    // A non-blocking error status doesn't count against the proxy.
+    let response = retcher.retch("http://127.0.0.1:8000/status/404".into(), None).await.unwrap();
+    assert_eq!(response.status, 404);
+    assert_eq!(response.proxy, Some(format!("{}/", urls[1])));
+
+    let status = retcher.proxy_pool().unwrap().status();
+    assert_eq!((status[0].healthy, status[0].failures), (false, 1));
+    assert_eq!((status[1].healthy, status[1].failures), (true, 0));
+
+    assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/", None).await, Some(format!("{}/", urls[1])));
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(retcher.proxy_pool().unwrap().status()[0].healthy);
+    assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/", None).await, Some(format!("{}/", urls[0])));
+
+    // A successful request resets the failures.
+    assert_eq!(retcher.proxy_pool().unwrap().status()[0].failures, 0);
+}
+
+#[tokio::test]
+async fn all_proxies_cooling_down() {
+    let proxies = proxies(2).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::RoundRobin, None).await;
+
+    for url in &urls {
+        let response = retcher.retch("http://127.0.0.1:8000/status/403".into(), None).await.unwrap();
+        assert_eq!(response.proxy, Some(format!("{}/", url)));
+    }
+
+    // The proxy that failed first is the first one to get another chance.
+    assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/", None).await, Some(format!("{}/", urls[0])));
+    assert!(retcher.proxy_pool().unwrap().status()[0].healthy);
+}
+
+#[tokio::test]
+async fn sticky_proxy_is_replaced_when_bad() {
+    let proxies = proxies(2).await;
+    let urls: Vec<&str> = proxies.iter().map(|proxy| proxy.url.as_str()).collect();
+    let retcher = retcher(&urls, ProxyRotation::StickyPerHost, None).await;
+
+    let response = retcher.retch("http://127.0.0.1:8000/status/403".into(), None).await.unwrap();
+    assert_eq!(response.proxy, Some(format!("{}/", urls[0])));
+
+    for _ in 0..2 {
+        assert_eq!(served_by(&retcher, "http://127.0.0.1:8000/", None).await, Some(format!("{}/", urls[1])));
+    }
+}
+
+#[tokio::test]
+async fn request_proxy_overrides_pool() {
+    let pooled = http_proxy().await;
+    let proxy = http_proxy().await;
+    let retcher = retcher(&[&pooled.url], ProxyRotation::RoundRobin, None).await;
+
+    let response = retcher.retch("http://127.0.0.1:8000/status/429".into(), Some(FetchOptions {
+        proxy: Some(ProxyOptions::new(&proxy.url)),
+        ..Default::default()
+    })).await.unwrap();
+    assert_eq!(response.proxy, Some(format!("{}/", proxy.url)));
+
+    assert_eq!(pooled.requests().len(), 0);
+    assert!(retcher.proxy_pool().unwrap().status()[0].healthy);
+}
+
+#[tokio::test]
+async fn direct_response_has_no_proxy() {
+    let proxy = http_proxy().await;
+    let retcher = Retcher::new(EngineOptions {
+        proxy_pool: Some(ProxyPoolOptions::new(vec![ProxyOptions {
+            url: proxy.url.clone(),
+            no_proxy: Some("127.0.0.1".into()),
+        }])),
+        ..Default::default()
+    });
+    get_server().await;
+
+    let response = retcher.retch("http://127.0.0.1:8000/".into(), None).await.unwrap();
+    assert_eq!(response.proxy, None);
+    assert_eq!(proxy.requests().len(), 0);
+}
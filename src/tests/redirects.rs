@@ -0,0 +1,212 @@
+use indexmap::IndexMap;
+
+use reqwest::Method;
+
+use crate::retcher::retcher::{
+    Browser, EngineOptions, FetchError, FetchErrorKind, FetchOptions, FetchPhase, FetchResponse, RedirectMode, RequestBody, Retcher, DEFAULT_MAX_REDIRECTS,
+};
+use super::server::get_server;
+use super::server::echo::RequestEcho;
+
+async fn retch(path: &str, options: FetchOptions) -> Result<FetchResponse, FetchError> {
+    get_server().await;
+
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Chrome),
+        ignore_tls_errors: None,
+        ..Default::default()
+    });
+
+    retcher.retch(format!("http://127.0.0.1:8000{}", path), Some(options)).await
+}
+
+#[tokio::test]
+async fn follows_redirects() {
+    let mut response = retch("/redirect/3", FetchOptions::default()).await.unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.url, "http://127.0.0.1:8000/");
+    assert!(response.redirected);
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+
+    let chain: Vec<(&str, u16, &str)> = response.redirects.iter()
+        .map(|hop| (hop.url.as_str(), hop.status, hop.location.as_str()))
+        .collect();
+
+    assert_eq!(chain, vec![
+        ("http://127.0.0.1:8000/redirect/3", 302, "http://127.0.0.1:8000/redirect/2"),
+        ("http://127.0.0.1:8000/redirect/2", 302, "http://127.0.0.1:8000/redirect/1"),
+        ("http://127.0.0.1:8000/redirect/1", 302, "http://127.0.0.1:8000/"),
+    ]);
+
+    for (hop, value) in response.redirects.iter().zip(["3", "2", "1"]) {
+        assert_eq!(hop.set_cookies.len(), 1);
+        assert!(hop.set_cookies[0].starts_with(&format!("hop={}", value)), "{:?}", hop.set_cookies);
+    }
+}
+
+#[tokio::test]
+async fn not_redirected() {
+    let response = retch("/", FetchOptions::default()).await.unwrap();
+
+    assert!(!response.redirected);
+    assert!(response.redirects.is_empty());
+}
+
+#[tokio::test]
+async fn manual_mode_returns_the_redirect() {
+    let response = retch("/redirect/3", FetchOptions {
+        redirect: RedirectMode::Manual,
+        ..Default::default()
+    }).await.unwrap();
+
+    assert_eq!(response.status, 302);
+    assert_eq!(response.url, "http://127.0.0.1:8000/redirect/3");
+    assert_eq!(response.headers.get("location").map(String::as_str), Some("/redirect/2"));
+    assert!(!response.redirected);
+    assert!(response.redirects.is_empty());
+}
+
+#[tokio::test]
+async fn error_mode_rejects_redirects() {
+    let error = retch("/redirect/3", FetchOptions {
+        redirect: RedirectMode::Error,
+        ..Default::default()
+    }).await.err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::Redirect);
+    assert_eq!(error.phase, FetchPhase::Request);
+    assert_eq!(error.url.as_deref(), Some("http://127.0.0.1:8000/redirect/3"));
+
+    // Responses that aren't redirects are fine.
+    assert_eq!(retch("/", FetchOptions {
+        redirect: RedirectMode::Error,
+        ..Default::default()
+    }).await.unwrap().status, 200);
+}
+
+#[tokio::test]
+async fn max_redirects() {
+    let error = retch("/redirect/3", FetchOptions {
+        max_redirects: Some(2),
+        ..Default::default()
+    }).await.err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::Redirect);
+    assert_eq!(error.url.as_deref(), Some("http://127.0.0.1:8000/redirect/1"));
+
+    let response = retch("/redirect/3", FetchOptions {
+        max_redirects: Some(3),
+        ..Default::default()
+    }).await.unwrap();
+
+    assert_eq!(response.redirects.len(), 3);
+}
+
+#[tokio::test]
+async fn redirect_loop() {
+    let error = retch("/redirect/loop", FetchOptions::default()).await.err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::Redirect);
+    assert!(error.message.contains(&DEFAULT_MAX_REDIRECTS.to_string()), "{}", error.message);
+}
+
+#[tokio::test]
+async fn redirect_without_location_is_returned() {
+    let response = retch("/redirect/no-location", FetchOptions::default()).await.unwrap();
+
+    assert_eq!(response.status, 302);
+    assert!(!response.redirected);
+}
+
+#[tokio::test]
+async fn redirect_to_unsupported_protocol() {
+    let error = retch("/redirect/ftp", FetchOptions::default()).await.err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::Redirect);
+    assert_eq!(error.url.as_deref(), Some("http://127.0.0.1:8000/redirect/ftp"));
+}
+
+#[tokio::test]
+async fn post_redirect_becomes_get() {
+    let mut response = retch("/redirect/post", FetchOptions {
+        method: Method::POST,
+        ..Default::default()
+    }).await.unwrap();
+
+    assert!(response.redirected);
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+}
+
+async fn redirect_echo(method: Method, status: u16, location: &str, headers: Vec<(&str, &str)>, body: Option<RequestBody>) -> RequestEcho {
+    let path = format!("/redirect/to?status={}&location={}", status, url::form_urlencoded::byte_serialize(location.as_bytes()).collect::<String>());
+
+    let mut response = retch(&path, FetchOptions {
+        method,
+        headers: IndexMap::from_iter(headers.into_iter().map(|(key, value)| (key.to_string(), value.to_string()))),
+        body,
+        ..Default::default()
+    }).await.unwrap();
+
+    assert!(response.redirected);
+    response.json().await.unwrap()
+}
+
+fn header<'a>(echo: &'a RequestEcho, name: &str) -> Option<&'a str> {
+    echo.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+#[tokio::test]
+async fn see_other_becomes_get_without_body() {
+    for (method, status) in [(Method::POST, 303), (Method::PUT, 303), (Method::POST, 302), (Method::POST, 301)] {
+        let echo = redirect_echo(method.clone(), status, "/echo", vec![("Content-Language", "cs")], Some(RequestBody::Text("Hello!".into()))).await;
+
+        assert_eq!(echo.method, "GET", "{} after {}", status, method);
+        assert_eq!(echo.body, "");
+        assert_eq!(header(&echo, "content-type"), None);
+        assert_eq!(header(&echo, "content-length"), None);
+        assert_eq!(header(&echo, "content-language"), None);
+        assert_eq!(header(&echo, "origin"), None);
+    }
+}
+
+#[tokio::test]
+async fn method_and_body_are_kept() {
+    for (method, status) in [(Method::POST, 307), (Method::POST, 308), (Method::PUT, 302), (Method::PUT, 301)] {
+        let echo = redirect_echo(method.clone(), status, "/echo", vec![], Some(RequestBody::Text("Hello!".into()))).await;
+
+        assert_eq!(echo.method, method.as_str(), "{} after {}", status, method);
+        assert_eq!(echo.body, "Hello!");
+        assert_eq!(header(&echo, "content-type"), Some("text/plain;charset=UTF-8"));
+        assert_eq!(header(&echo, "content-length"), Some("6"));
+    }
+}
+
+#[tokio::test]
+async fn credentials_are_stripped_cross_origin() {
+    let headers = vec![("Authorization", "Bearer s3cr3t"), ("cookie", "session=1"), ("X-Custom", "kept")];
+
+    let echo = redirect_echo(Method::GET, 302, "/echo", headers.clone(), None).await;
+    assert_eq!(header(&echo, "authorization"), Some("Bearer s3cr3t"));
+    assert_eq!(header(&echo, "cookie"), Some("session=1"));
+
+    let echo = redirect_echo(Method::GET, 302, "http://localhost:8000/echo", headers, None).await;
+    assert_eq!(header(&echo, "host"), Some("localhost:8000"));
+    assert_eq!(header(&echo, "authorization"), None);
+    assert_eq!(header(&echo, "cookie"), None);
+    assert_eq!(header(&echo, "x-custom"), Some("kept"));
+}
+
+#[tokio::test]
+async fn referer_is_recomputed() {
+    let referrer = vec![("Referer", "http://127.0.0.1:8000/page?q=1#top")];
+
+    let echo = redirect_echo(Method::GET, 302, "/echo", referrer.clone(), None).await;
+    assert_eq!(header(&echo, "referer"), Some("http://127.0.0.1:8000/page?q=1"));
+
+    let echo = redirect_echo(Method::GET, 302, "http://localhost:8000/echo", referrer, None).await;
+    assert_eq!(header(&echo, "referer"), Some("http://127.0.0.1:8000/"));
+
+    let echo = redirect_echo(Method::GET, 302, "/echo", vec![], None).await;
+    assert_eq!(header(&echo, "referer"), None);
+}
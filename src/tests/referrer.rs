@@ -0,0 +1,111 @@
+use reqwest::Method;
+
+use crate::retcher::retcher::{Browser, Destination, FetchErrorKind, FetchOptions, ReferrerPolicy, RequestBody, Retcher};
+use super::common::{header, parse_head, retcher};
+use super::server::tls::tls_server;
+
+/// Sends the request (with the `{server}` in the referrer replaced by the server's URL) over HTTPS and returns the headers as sent.
+async fn sent_headers(retcher: &Retcher, options: FetchOptions) -> Vec<(String, String)> {
+    let server = tls_server().await;
+    let referrer = options.referrer.as_ref().map(|referrer| referrer.replace("{server}", &server.url));
+    retcher.retch(format!("{}/target", server.url), Some(FetchOptions { referrer, ..options })).await.unwrap();
+    parse_head(&server.heads()[0])
+}
+
+fn from(referrer: &str) -> FetchOptions {
+    FetchOptions { referrer: Some(referrer.into()), ..Default::default() }
+}
+
+#[tokio::test]
+async fn same_origin_referrer() {
+    let headers = sent_headers(&retcher(Browser::Chrome, None), from("{server}/page?q=1#top")).await;
+
+    assert_eq!(header(&headers, "Sec-Fetch-Site"), Some("same-origin"));
+    assert!(header(&headers, "Referer").unwrap().ends_with("/page?q=1"), "{:?}", headers);
+    // Clicking a link is still the user's navigation.
+    assert_eq!(header(&headers, "Sec-Fetch-User"), Some("?1"));
+}
+
+#[tokio::test]
+async fn placeholders_in_the_referrer_stay_as_they_are() {
+    let server = tls_server().await;
+    let retcher = retcher(Browser::Chrome, None);
+    retcher.cookies().set_cookie("secret=1; Secure", &server.url).unwrap();
+
+    let referrer = format!("{}/page?q={{cookie}}", server.url);
+    retcher.retch(format!("{}/target", server.url), Some(from(&referrer))).await.unwrap();
+    let headers = parse_head(&server.heads()[0]);
+
+    // The cookie goes into `Cookie` only, not into the `{cookie}` of the referrer's query.
+    assert!(header(&headers, "Referer").unwrap().ends_with("/page?q={cookie}"), "{:?}", headers);
+    assert_eq!(header(&headers, "Cookie"), Some("secret=1"));
+}
+
+#[tokio::test]
+async fn cross_site_referrer() {
+    let retcher = retcher(Browser::Chrome, None);
+
+    let headers = sent_headers(&retcher, from("https://www.example.com/page?q=1")).await;
+    assert_eq!(header(&headers, "Sec-Fetch-Site"), Some("cross-site"));
+    assert_eq!(header(&headers, "Referer"), Some("https://www.example.com/"));
+    assert_eq!(header(&headers, "Origin"), None);
+
+    let headers = sent_headers(&retcher, FetchOptions {
+        referrer_policy: ReferrerPolicy::UnsafeUrl,
+        ..from("https://www.example.com/page?q=1")
+    }).await;
+    assert_eq!(header(&headers, "Referer"), Some("https://www.example.com/page?q=1"));
+
+    let headers = sent_headers(&retcher, FetchOptions {
+        referrer_policy: ReferrerPolicy::NoReferrer,
+        ..from("https://www.example.com/page?q=1")
+    }).await;
+    assert_eq!(header(&headers, "Referer"), None);
+    assert_eq!(header(&headers, "Sec-Fetch-Site"), Some("cross-site"));
+}
+
+#[tokio::test]
+async fn referrer_origin() {
+    let retcher = retcher(Browser::Chrome, None);
+
+    // A form submitted from another site.
+    let form = || FetchOptions {
+        method: Method::POST,
+        body: Some(RequestBody::Form(vec![("q".into(), "1".into())])),
+        ..from("https://www.example.com/form")
+    };
+    let headers = sent_headers(&retcher, form()).await;
+    assert_eq!(header(&headers, "Origin"), Some("https://www.example.com"));
+
+    let headers = sent_headers(&retcher, FetchOptions { referrer_policy: ReferrerPolicy::NoReferrer, ..form() }).await;
+    assert_eq!(header(&headers, "Origin"), Some("null"));
+
+    // A cross-origin `fetch` call.
+    let headers = sent_headers(&retcher, FetchOptions { destination: Destination::Empty, ..from("https://www.example.com/app") }).await;
+    assert_eq!(header(&headers, "Origin"), Some("https://www.example.com"));
+    assert_eq!(header(&headers, "Sec-Fetch-Site"), Some("cross-site"));
+}
+
+#[tokio::test]
+async fn referrer_same_site_cookies() {
+    let retcher = retcher(Browser::Chrome, None);
+    let server = tls_server().await;
+    retcher.cookies().set_cookie("lax=1; SameSite=Lax; Secure", &server.url).unwrap();
+
+    let post = |referrer: String| FetchOptions { method: Method::POST, ..from(&referrer) };
+    retcher.retch(format!("{}/", server.url), Some(post("https://www.example.com/".into()))).await.unwrap();
+    retcher.retch(format!("{}/", server.url), Some(post(format!("{}/form", server.url)))).await.unwrap();
+
+    // `SameSite=Lax` cookies aren't sent with the cross-site `POST`s.
+    let heads = server.heads();
+    assert!(!heads[0].contains("\r\nCookie:"), "{}", heads[0]);
+    assert!(heads[1].contains("\r\nCookie: lax=1\r\n"), "{}", heads[1]);
+}
+
+#[tokio::test]
+async fn invalid_referrer() {
+    let error = retcher(Browser::Chrome, None).retch("http://127.0.0.1:8000/".into(), Some(from("not a url"))).await.err().unwrap();
+
+    assert_eq!(error.kind, FetchErrorKind::InvalidUrl);
+    assert!(error.message.starts_with("Invalid referrer 'not a url'"), "{}", error.message);
+}
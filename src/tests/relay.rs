@@ -0,0 +1,95 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+use crate::retcher::profile::BrowserProfile;
+use crate::retcher::retcher::{Browser, DEFAULT_CONNECT_TIMEOUT};
+use crate::retcher::tunnel::{register, TunnelConfig, RELAY_CONFIG};
+use super::server::get_server;
+use super::server::tls::tls_server;
+
+fn config() -> TunnelConfig {
+    TunnelConfig {
+        proxy: None,
+        profile: BrowserProfile::builtin(&Browser::Chrome),
+        ignore_tls_errors: true,
+        connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        auth_cache: Default::default(),
+    }
+}
+
+/// Sends the request head to the relay and returns the status line of its response.
+async fn relay_status(relay: &Url, head: String) -> String {
+    let mut stream = TcpStream::connect((relay.host_str().unwrap(), relay.port().unwrap())).await.unwrap();
+    stream.write_all(head.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        response.push(stream.read_u8().await.unwrap());
+    }
+    String::from_utf8(response).unwrap().lines().next().unwrap().to_string()
+}
+
+fn connect(target: &str, username: &str) -> String {
+    let credentials = BASE64_STANDARD.encode(format!("{}:", username));
+    format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\nProxy-Authorization: Basic {}\r\n\r\n", target, target, credentials)
+}
+
+#[tokio::test]
+async fn tunnels_need_the_credential() {
+    let server = tls_server().await;
+    let target = server.url.trim_start_matches("https://").to_string();
+
+    let registration = register(config()).unwrap();
+    let relay = registration.tunnel_url();
+    let credential = relay.username().to_string();
+    let (id, secret) = credential.split_once('.').unwrap();
+    assert_eq!(secret.len(), 32);
+
+    assert_eq!(relay_status(&relay, connect(&target, &credential)).await, "HTTP/1.1 200 Connection Established");
+
+    let wrong_secret = format!("{}.{}", id, "0".repeat(32));
+    for username in ["0", id, &wrong_secret, ""] {
+        assert_eq!(relay_status(&relay, connect(&target, username)).await, "HTTP/1.1 407 Proxy Authentication Required", "{}", username);
+    }
+
+    // The config is gone with its registration.
+    drop(registration);
+    assert_eq!(relay_status(&relay, connect(&target, &credential)).await, "HTTP/1.1 407 Proxy Authentication Required");
+}
+
+#[tokio::test]
+async fn forwarding_needs_the_credential() {
+    let registration = register(config()).unwrap();
+    let (relay, headers) = registration.forwarding();
+    let credential = headers[RELAY_CONFIG].to_str().unwrap().to_string();
+
+    for value in ["0", &credential[..credential.len() - 1]] {
+        let head = format!("GET http://127.0.0.1:8000/ HTTP/1.1\r\nHost: 127.0.0.1:8000\r\n{}: {}\r\n\r\n", RELAY_CONFIG, value);
+        assert_eq!(relay_status(&relay, head).await, "HTTP/1.1 407 Proxy Authentication Required", "{}", value);
+    }
+}
+
+#[tokio::test]
+async fn forwarded_body_arriving_with_the_head() {
+    get_server().await;
+    let registration = register(config()).unwrap();
+    let (relay, headers) = registration.forwarding();
+
+    // Sent at once, the chunked body is read (and buffered) along with the head.
+    let request = format!(
+        "POST http://127.0.0.1:8000/echo HTTP/1.1\r\nHost: 127.0.0.1:8000\r\n{}: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+        4\r\npay\n\r\n3;ext=1\r\nl\rd\r\n0\r\nTrailer: 1\r\n\r\n",
+        RELAY_CONFIG,
+        headers[RELAY_CONFIG].to_str().unwrap(),
+    );
+    let mut stream = TcpStream::connect((relay.host_str().unwrap(), relay.port().unwrap())).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains(r#""body":"pay\nl\rd""#), "{}", response);
+}
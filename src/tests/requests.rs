@@ -1,4 +1,6 @@
-use std::{collections::HashMap, iter::zip};
+use std::iter::zip;
+
+use indexmap::IndexMap;
 
 use crate::retcher::retcher::{Browser, EngineOptions, FetchOptions, Retcher};
 use super::server::{get_server, request_headers::RequestHeaders};
@@ -10,6 +12,7 @@ async fn default_requests() {
     let retcher = Retcher::new(EngineOptions {
         browser: Some(Browser::Chrome),
         ignore_tls_errors: Some(true),
+        ..Default::default()
     });
 
     let response = retcher.retch("http://127.0.0.1:8000".into(), None).await;
@@ -19,7 +22,7 @@ async fn default_requests() {
         Err(e) => panic!("{:?}", e),
     };
 
-    assert_eq!(body, true);
+    assert!(body);
 }
 
 #[tokio::test]
@@ -29,17 +32,18 @@ async fn default_http_headers() {
     let retcher = Retcher::new(EngineOptions {
         browser: Some(Browser::Chrome),
         ignore_tls_errors: Some(true),
+        ..Default::default()
     });
 
     let response = retcher.retch("http://127.0.0.1:8000/headers".into(), None).await;
 
     let headers: RequestHeaders = match response {
-        Ok(response) => serde_json::from_str(String::from_utf8(response.body.unwrap()).unwrap().as_str()).unwrap(),
+        Ok(mut response) => serde_json::from_str(String::from_utf8(response.bytes().await.unwrap()).unwrap().as_str()).unwrap(),
         Err(e) => panic!("{:?}", e),
     };
 
     let expected = vec![
-        ("host", "127.0.0.1"),
+        ("host", "127.0.0.1:8000"),
         ("connection", "keep-alive"),
         ("upgrade-insecure-requests", "1"),
         ("user-agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/125.0.0.0 Safari/537.36"),
@@ -68,6 +72,7 @@ async fn custom_http_headers() {
     let retcher = Retcher::new(EngineOptions {
         browser: Some(Browser::Chrome),
         ignore_tls_errors: Some(true),
+        ..Default::default()
     });
 
     let custom_headers: Vec<(String, String)> = vec![
@@ -77,23 +82,25 @@ async fn custom_http_headers() {
     ];
 
     let response = retcher.retch("http://127.0.0.1:8000/headers".into(), Some(FetchOptions{
-        headers: HashMap::from_iter(custom_headers.into_iter()),
+        headers: IndexMap::from_iter(custom_headers),
+        ..Default::default()
     })).await;
 
     let headers: RequestHeaders = match response {
-        Ok(response) => serde_json::from_str(String::from_utf8(response.body.unwrap()).unwrap().as_str()).unwrap(),
+        Ok(mut response) => serde_json::from_str(String::from_utf8(response.bytes().await.unwrap()).unwrap().as_str()).unwrap(),
         Err(e) => panic!("{:?}", e),
     };
 
     let expected = vec![
-        ("host", "127.0.0.1"),
+        ("host", "127.0.0.1:8000"),
         ("connection", "keep-alive"),
         ("upgrade-insecure-requests", "1"),
+        // Chrome sends the headers set by the page before `User-Agent`.
+        (CUSTOM_RANDOM_HEADER.0, CUSTOM_RANDOM_HEADER.1),
         ("user-agent", CUSTOM_USER_AGENT),
         ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"),
         ("accept-encoding", "gzip, deflate"),
         ("accept-language", CUSTOM_ACCEPT_LANGUAGE),
-        (CUSTOM_RANDOM_HEADER.0, CUSTOM_RANDOM_HEADER.1),
     ];
 
     assert_eq!(headers.0.len(), expected.len());
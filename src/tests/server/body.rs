@@ -0,0 +1,22 @@
+use rocket::http::ContentType;
+
+pub static CZECH_TEXT: &str = "Příliš žluťoučký kůň úpěl ďábelské ódy";
+
+/// Returns `CZECH_TEXT` encoded in the charset given in the path, declared in the `Content-Type` header.
+#[get("/body/text/<charset>")]
+pub fn encoded_text_route(charset: &str) -> (ContentType, Vec<u8>) {
+    let encoding = encoding_rs::Encoding::for_label(charset.as_bytes()).unwrap();
+    let (bytes, _, _) = encoding.encode(CZECH_TEXT);
+
+    (ContentType::parse_flexible(&format!("text/plain; charset={}", charset)).unwrap(), bytes.into_owned())
+}
+
+#[get("/body/json")]
+pub fn json_route() -> (ContentType, &'static str) {
+    (ContentType::JSON, r#"{"name":"retch","tags":["http","fetch"],"stars":5}"#)
+}
+
+#[get("/body/json/invalid")]
+pub fn invalid_json_route() -> (ContentType, &'static str) {
+    (ContentType::JSON, r#"{"name":"retch","#)
+}
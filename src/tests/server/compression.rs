@@ -7,6 +7,8 @@ use rocket::Request;
 use serde_json::json;
 use tokio::io::AsyncReadExt;
 
+// Variant names double as `Content-Encoding` tokens, hence the lowercase.
+#[allow(non_camel_case_types)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum CompressionMethod {
     unknown,
@@ -16,9 +18,9 @@ pub enum CompressionMethod {
     zstd,
 }
 
-pub static BODY: &'static str = "This is the data to be compressed!";
+pub static BODY: &str = "This is the data to be compressed!";
 
-struct CompressedData {
+pub struct CompressedData {
     data: Vec<u8>,
     encoding: CompressionMethod,
 }
@@ -97,6 +99,15 @@ impl<'r> FromRequest<'r> for CompressionMethod {
     }
 }
 
+/// Claims to be gzip-compressed, but isn't.
+#[get("/compression/broken")]
+pub fn broken_compression_route() -> CompressedData {
+    CompressedData {
+        data: b"This is definitely not gzip!".to_vec(),
+        encoding: CompressionMethod::gzip,
+    }
+}
+
 #[get("/compression")]
 pub async fn compression_route(compresssion_method: CompressionMethod) -> CompressedData {
     let payload = Payload::new(compresssion_method);
@@ -0,0 +1,23 @@
+use rocket::http::Header;
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+
+/// Responds with a `Set-Cookie` header for every value.
+pub struct SetCookies(Vec<String>);
+
+impl<'r> Responder<'r, 'static> for SetCookies {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build_from("Cookies set".respond_to(request)?);
+
+        for set_cookie in self.0 {
+            response.header_adjoin(Header::new("Set-Cookie", set_cookie));
+        }
+
+        response.ok()
+    }
+}
+
+#[get("/cookies/set?<set_cookie>")]
+pub fn set_cookies_route(set_cookie: Vec<String>) -> SetCookies {
+    SetCookies(set_cookie)
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::request_headers::RequestHeaders;
+
+/// The request as seen by the server, echoed back to the client.
+#[derive(Serialize, Deserialize)]
+pub struct RequestEcho {
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+fn echo(method: &str, headers: RequestHeaders, body: Vec<u8>) -> String {
+    json!(RequestEcho {
+        method: method.to_string(),
+        headers: headers.0,
+        body: String::from_utf8_lossy(&body).to_string(),
+    }).to_string()
+}
+
+#[post("/echo", data = "<body>")]
+pub fn echo_post(headers: RequestHeaders, body: Vec<u8>) -> String {
+    echo("POST", headers, body)
+}
+
+#[put("/echo", data = "<body>")]
+pub fn echo_put(headers: RequestHeaders, body: Vec<u8>) -> String {
+    echo("PUT", headers, body)
+}
+
+#[patch("/echo", data = "<body>")]
+pub fn echo_patch(headers: RequestHeaders, body: Vec<u8>) -> String {
+    echo("PATCH", headers, body)
+}
+
+#[delete("/echo", data = "<body>")]
+pub fn echo_delete(headers: RequestHeaders, body: Vec<u8>) -> String {
+    echo("DELETE", headers, body)
+}
+
+#[get("/echo")]
+pub fn echo_get(headers: RequestHeaders) -> String {
+    echo("GET", headers, Vec::new())
+}
@@ -1,31 +1,86 @@
+use std::net::TcpStream;
+use std::sync::OnceLock;
 use std::time::Duration;
-use rocket::{Error, Ignite, Rocket};
-use tokio::task::JoinHandle;
 
 pub mod request_headers;
 pub mod compression;
+pub mod echo;
+pub mod raw;
+pub mod stream;
+pub mod body;
+pub mod redirect;
+pub mod cookies;
+pub mod proxy;
+pub mod tls;
 
 use request_headers::headers;
-use compression::compression_route;
+use compression::{compression_route, broken_compression_route};
+use cookies::set_cookies_route;
+use echo::{echo_get, echo_post, echo_put, echo_patch, echo_delete};
+use stream::stream_route;
+use body::{encoded_text_route, json_route, invalid_json_route};
+use redirect::{redirect_route, redirect_loop_route, redirect_no_location_route, redirect_ftp_route, redirect_post_route, redirect_to_get, redirect_to_post, redirect_to_put};
 
 #[get("/")]
 fn hello() -> String {
     "Hello, world!".into()
 }
 
-// [TODO!!!] - Server is run each time a test is run, but it should be run only once for all tests
-pub async fn get_server() -> JoinHandle<Result<Rocket<Ignite>, Error>> {
-    let server = rocket::build()
-        .mount("/", routes![
-            hello, 
-            headers, 
-            compression_route
-        ]);
-
-    let handle = tokio::spawn(server.launch());
-    tokio::time::sleep(Duration::from_millis(50)).await;
-    handle
+/// Responds with the given status, e.g. to play a server blocking the client with `429 Too Many Requests`.
+#[get("/status/<code>")]
+fn status_route(code: u16) -> (rocket::http::Status, String) {
+    (rocket::http::Status::new(code), format!("Status {}", code))
 }
 
+static SERVER: OnceLock<()> = OnceLock::new();
 
+/// Starts the test server (once for all the tests) and waits until it accepts connections.
+/// 
+/// The server runs on its own thread and runtime, so it outlives the runtimes of the individual `#[tokio::test]`s.
+pub async fn get_server() {
+    SERVER.get_or_init(|| {
+        std::thread::spawn(|| {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
 
+            runtime.block_on(async {
+                let server = rocket::build()
+                    .mount("/", routes![
+                        hello, 
+                        status_route,
+                        headers, 
+                        compression_route,
+                        broken_compression_route,
+                        echo_get,
+                        echo_post,
+                        echo_put,
+                        echo_patch,
+                        echo_delete,
+                        stream_route,
+                        encoded_text_route,
+                        json_route,
+                        invalid_json_route,
+                        redirect_route,
+                        redirect_loop_route,
+                        redirect_no_location_route,
+                        redirect_ftp_route,
+                        redirect_post_route,
+                        redirect_to_get,
+                        redirect_to_post,
+                        redirect_to_put,
+                        set_cookies_route,
+                    ]);
+
+                server.launch().await.unwrap();
+            });
+        });
+
+        for _ in 0..200 {
+            if TcpStream::connect("127.0.0.1:8000").is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(25));
+        }
+
+        panic!("The test server didn't start in time");
+    });
+}
@@ -0,0 +1,347 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::retcher::proxy_auth::{digest_response, parse_challenges, DigestParams, ProxyCredentials};
+
+/// A request seen by the HTTP proxy stand-in.
+#[derive(Debug, Clone)]
+pub struct ProxiedRequest {
+    /// The request line, e.g. `GET http://127.0.0.1:8000/ HTTP/1.1` or `CONNECT 127.0.0.1:8000 HTTP/1.1`.
+    pub request_line: String,
+    /// The header lines as sent, in their order and casing.
+    pub headers: Vec<String>,
+    pub proxy_authorization: Option<String>,
+}
+
+/// A connection request seen by the SOCKS5 proxy stand-in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocksRequest {
+    /// The target host - an IP address when the client resolved it, a domain name otherwise.
+    pub host: SocksHost,
+    pub port: u16,
+    /// The username and password the client authenticated with.
+    pub credentials: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SocksHost {
+    Ip(String),
+    Domain(String),
+}
+
+/// A proxy stand-in running on an ephemeral port.
+pub struct ProxyServer<T> {
+    /// The proxy URL (without credentials).
+    pub url: String,
+    requests: Arc<Mutex<Vec<T>>>,
+}
+
+impl<T: Clone> ProxyServer<T> {
+    /// Returns the requests the proxy has seen so far.
+    pub fn requests(&self) -> Vec<T> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// The authentication an HTTP proxy stand-in requires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyAuth {
+    Basic,
+    Digest,
+}
+
+const NONCE: &str = "dcd98b7102dd2f0e8b11d0f600bfb0c093";
+
+/// Reads the request head (up to and including the empty line), leaving the body in the socket.
+async fn read_head(socket: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while !head.ends_with(b"\r\n\r\n") {
+        match socket.read(&mut byte).await {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => head.push(byte[0]),
+        }
+    }
+
+    String::from_utf8(head).ok()
+}
+
+/// Checks the `Proxy-Authorization` value against the required authentication.
+fn is_authorized(auth: ProxyAuth, credentials: &ProxyCredentials, method: &str, target: &str, authorization: Option<&str>) -> bool {
+    let Some(authorization) = authorization else {
+        return false;
+    };
+
+    match auth {
+        ProxyAuth::Basic => authorization == format!("Basic {}", BASE64_STANDARD.encode(format!("{}:{}", credentials.username, credentials.password))),
+        ProxyAuth::Digest => {
+            let Some(digest) = parse_challenges([authorization]).pop() else {
+                return false;
+            };
+            let param = |name: &str| digest.param(name).unwrap_or_default();
+
+            let expected = digest_response(&DigestParams {
+                credentials,
+                realm: "retcher",
+                nonce: NONCE,
+                algorithm: Some("MD5"),
+                qop: Some("auth"),
+                nc: param("nc"),
+                cnonce: param("cnonce"),
+                method,
+                uri: target,
+            });
+
+            digest.scheme == "digest"
+                && param("username") == credentials.username
+                && param("nonce") == NONCE
+                && param("uri") == target
+                && param("opaque") == "5ccc069c403ebaf9f0171e9517f40e41"
+                && param("response") == expected
+        }
+    }
+}
+
+/// Starts an HTTP forward proxy, handling both absolute-form requests (for `http://` targets) and `CONNECT` tunnels.
+pub async fn http_proxy() -> ProxyServer<ProxiedRequest> {
+    start_http_proxy(None, None).await
+}
+
+/// Starts an HTTP forward proxy answering `407 Proxy Authentication Required` until the client authenticates as `user:p@ss`.
+pub async fn authenticating_http_proxy(auth: ProxyAuth) -> ProxyServer<ProxiedRequest> {
+    start_http_proxy(Some(auth), None).await
+}
+
+/// Starts an HTTP forward proxy like `authenticating_http_proxy` with `Basic`, but whose `407` responses claim a 1 TiB body
+/// (and keep the connection open after the few bytes they actually send).
+pub async fn oversized_challenge_http_proxy() -> ProxyServer<ProxiedRequest> {
+    start_http_proxy(Some(ProxyAuth::Basic), Some(1 << 40)).await
+}
+
+/// Starts the HTTP proxy stand-in, `challenge_length` overriding the `Content-Length` of its `407` responses.
+async fn start_http_proxy(auth: Option<ProxyAuth>, challenge_length: Option<u64>) -> ProxyServer<ProxiedRequest> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let credentials = ProxyCredentials { username: "user".into(), password: "p@ss".into() };
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let credentials = credentials.clone();
+
+            tokio::spawn(async move {
+                // Answering a challenge may happen on the same connection.
+                loop {
+                    let Some(head) = read_head(&mut socket).await else { return };
+                    let mut lines = head.trim_end().split("\r\n");
+                    let request_line = lines.next().unwrap_or_default().to_string();
+                    let headers: Vec<String> = lines.map(str::to_string).collect();
+
+                    let header = |wanted: &str| headers.iter()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                        .map(|(_, value)| value.trim().to_string());
+                    let proxy_authorization = header("proxy-authorization");
+
+                    let mut body = vec![0u8; header("content-length").and_then(|length| length.parse().ok()).unwrap_or(0)];
+                    if socket.read_exact(&mut body).await.is_err() {
+                        return;
+                    }
+
+                    recorded.lock().unwrap().push(ProxiedRequest {
+                        request_line: request_line.clone(),
+                        headers: headers.clone(),
+                        proxy_authorization: proxy_authorization.clone(),
+                    });
+
+                    let mut parts = request_line.split(' ');
+                    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+                    if let Some(auth) = auth {
+                        if !is_authorized(auth, &credentials, method, target, proxy_authorization.as_deref()) {
+                            let challenge = match auth {
+                                ProxyAuth::Basic => "Basic realm=\"retcher\"".to_string(),
+                                ProxyAuth::Digest => format!(
+                                    "Basic realm=\"retcher\", Digest realm=\"retcher\", nonce=\"{}\", qop=\"auth,auth-int\", algorithm=MD5, opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+                                    NONCE,
+                                ),
+                            };
+                            let response = format!(
+                                "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: {}\r\nContent-Length: {}\r\n\r\ndenied",
+                                challenge,
+                                challenge_length.unwrap_or(6),
+                            );
+                            if socket.write_all(response.as_bytes()).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                    }
+
+                    if method == "CONNECT" {
+                        let Ok(mut upstream) = TcpStream::connect(target).await else {
+                            let _ = socket.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n").await;
+                            return;
+                        };
+                        let _ = socket.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await;
+                        let _ = copy_bidirectional(&mut socket, &mut upstream).await;
+                        return;
+                    }
+
+                    // Forward the request in the origin form, over a new connection for every request.
+                    let Ok(url) = url::Url::parse(target) else { return };
+                    let Ok(mut upstream) = TcpStream::connect((url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or(80))).await else {
+                        let _ = socket.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n").await;
+                        return;
+                    };
+
+                    let mut forwarded = format!("{} {} HTTP/1.1\r\n", method, &url[url::Position::BeforePath..]);
+                    for line in headers.iter().filter(|line| !line.to_lowercase().starts_with("connection:")) {
+                        forwarded.push_str(&format!("{}\r\n", line));
+                    }
+                    forwarded.push_str("Connection: close\r\n\r\n");
+
+                    if upstream.write_all(forwarded.as_bytes()).await.is_err() || upstream.write_all(&body).await.is_err() {
+                        return;
+                    }
+
+                    // Relay the response, keeping the client connection open when its length is known.
+                    let Some(response) = read_head(&mut upstream).await else { return };
+                    let response: Vec<&str> = response.trim_end().split("\r\n").filter(|line| !line.to_lowercase().starts_with("connection:")).collect();
+                    let length = response.iter()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                        .and_then(|(_, value)| value.trim().parse::<usize>().ok());
+
+                    if socket.write_all(format!("{}\r\n\r\n", response.join("\r\n")).as_bytes()).await.is_err() {
+                        return;
+                    }
+
+                    let Some(length) = length else {
+                        let _ = tokio::io::copy(&mut upstream, &mut socket).await;
+                        return;
+                    };
+
+                    let mut body = vec![0u8; length];
+                    if upstream.read_exact(&mut body).await.is_err() || socket.write_all(&body).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    ProxyServer { url, requests }
+}
+
+async fn read_string<R: AsyncRead + Unpin>(socket: &mut R) -> Option<String> {
+    let length = socket.read_u8().await.ok()?;
+    let mut bytes = vec![0u8; length as usize];
+    socket.read_exact(&mut bytes).await.ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Starts a SOCKS5 proxy (RFC 1928), requiring the username/password authentication (RFC 1929) when `credentials` are given.
+pub async fn socks5_proxy(credentials: Option<(&str, &str)>) -> ProxyServer<SocksRequest> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("socks5://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let expected = credentials.map(|(username, password)| (username.to_string(), password.to_string()));
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let expected = expected.clone();
+
+            tokio::spawn(async move {
+                // The greeting: version, then the offered authentication methods.
+                let mut header = [0u8; 2];
+                if socket.read_exact(&mut header).await.is_err() || header[0] != 5 {
+                    return;
+                }
+                let mut methods = vec![0u8; header[1] as usize];
+                if socket.read_exact(&mut methods).await.is_err() {
+                    return;
+                }
+
+                let method = if expected.is_some() { 2 } else { 0 };
+                if !methods.contains(&method) {
+                    let _ = socket.write_all(&[5, 0xff]).await;
+                    return;
+                }
+                if socket.write_all(&[5, method]).await.is_err() {
+                    return;
+                }
+
+                let mut credentials = None;
+                if method == 2 {
+                    if socket.read_u8().await.ok() != Some(1) {
+                        return;
+                    }
+                    let (Some(username), Some(password)) = (read_string(&mut socket).await, read_string(&mut socket).await) else { return };
+                    let accepted = expected.as_ref() == Some(&(username.clone(), password.clone()));
+                    let _ = socket.write_all(&[1, if accepted { 0 } else { 1 }]).await;
+                    if !accepted {
+                        return;
+                    }
+                    credentials = Some((username, password));
+                }
+
+                // The connection request: version, command, reserved, then the address.
+                let mut request = [0u8; 4];
+                if socket.read_exact(&mut request).await.is_err() || request[1] != 1 {
+                    return;
+                }
+                let host = match request[3] {
+                    1 => {
+                        let mut octets = [0u8; 4];
+                        if socket.read_exact(&mut octets).await.is_err() {
+                            return;
+                        }
+                        SocksHost::Ip(Ipv4Addr::from(octets).to_string())
+                    }
+                    4 => {
+                        let mut octets = [0u8; 16];
+                        if socket.read_exact(&mut octets).await.is_err() {
+                            return;
+                        }
+                        SocksHost::Ip(Ipv6Addr::from(octets).to_string())
+                    }
+                    3 => match read_string(&mut socket).await {
+                        Some(domain) => SocksHost::Domain(domain),
+                        None => return,
+                    },
+                    _ => return,
+                };
+                let Ok(port) = socket.read_u16().await else { return };
+
+                recorded.lock().unwrap().push(SocksRequest { host: host.clone(), port, credentials });
+
+                let upstream = match &host {
+                    SocksHost::Ip(ip) => TcpStream::connect((ip.as_str(), port)).await,
+                    SocksHost::Domain(domain) => TcpStream::connect((domain.as_str(), port)).await,
+                };
+                let Ok(mut upstream) = upstream else {
+                    // "Connection refused"
+                    let _ = socket.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).await;
+                    return;
+                };
+
+                if socket.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.is_err() {
+                    return;
+                }
+                let _ = copy_bidirectional(&mut socket, &mut upstream).await;
+            });
+        }
+    });
+
+    ProxyServer { url, requests }
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Starts a bare TCP server answering every request with the given bytes and returns its base URL.
+/// 
+/// This is for malformed responses Rocket refuses to produce (non-UTF-8 headers, unknown status codes...).
+pub async fn raw_server(response: &'static [u8]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(_) => return,
+            };
+
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+
+                let _ = socket.write_all(response).await;
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+
+    format!("http://{}", address)
+}
+
+/// Starts a bare TCP server that accepts connections but never answers (like a blackholed server) and returns its `host:port`.
+pub async fn silent_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            connections.push(socket);
+        }
+    });
+
+    address.to_string()
+}
+
+/// A bare TCP server stand-in recording the heads of the requests it receives, as they were sent.
+pub struct RecordingServer {
+    /// The base URL, e.g. `http://127.0.0.1:41234`.
+    pub url: String,
+    heads: Arc<Mutex<Vec<String>>>,
+}
+
+impl RecordingServer {
+    /// Returns the request heads the server has seen so far.
+    pub fn heads(&self) -> Vec<String> {
+        self.heads.lock().unwrap().clone()
+    }
+}
+
+/// Starts a bare TCP server answering every request with `Hello, world!`, recording the request heads.
+///
+/// Unlike Rocket, it sees the header names with their original casing.
+pub async fn recording_server() -> RecordingServer {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let heads = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = heads.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                recorded.lock().unwrap().push(String::from_utf8_lossy(&request).into_owned());
+
+                let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\nHello, world!").await;
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+
+    RecordingServer { url, heads }
+}
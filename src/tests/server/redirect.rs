@@ -0,0 +1,60 @@
+use rocket::http::{CookieJar, Header, Status};
+use rocket::response::Redirect;
+
+/// Redirects `hops` times (setting a `hop` cookie on every redirect) before landing on `/`.
+#[get("/redirect/<hops>")]
+pub fn redirect_route(hops: u32, cookies: &CookieJar<'_>) -> Redirect {
+    cookies.add(("hop", hops.to_string()));
+
+    if hops <= 1 {
+        Redirect::found("/")
+    } else {
+        Redirect::found(format!("/redirect/{}", hops - 1))
+    }
+}
+
+#[get("/redirect/loop")]
+pub fn redirect_loop_route() -> Redirect {
+    Redirect::found("/redirect/loop")
+}
+
+#[get("/redirect/no-location")]
+pub fn redirect_no_location_route() -> (Status, &'static str) {
+    (Status::Found, "Nowhere to go")
+}
+
+#[get("/redirect/ftp")]
+pub fn redirect_ftp_route() -> Redirect {
+    Redirect::found("ftp://127.0.0.1/file.txt")
+}
+
+#[post("/redirect/post")]
+pub fn redirect_post_route() -> Redirect {
+    Redirect::found("/")
+}
+
+#[derive(Responder)]
+pub struct RedirectTo {
+    inner: (),
+    location: Header<'static>,
+}
+
+fn redirect_to(location: String, status: u16) -> (Status, RedirectTo) {
+    (Status::new(status), RedirectTo { inner: (), location: Header::new("Location", location) })
+}
+
+/// Redirects to `location` with the given `status`, for any method.
+#[get("/redirect/to?<location>&<status>")]
+pub fn redirect_to_get(location: String, status: u16) -> (Status, RedirectTo) {
+    redirect_to(location, status)
+}
+
+#[post("/redirect/to?<location>&<status>", data = "<_body>")]
+pub fn redirect_to_post(location: String, status: u16, _body: Vec<u8>) -> (Status, RedirectTo) {
+    redirect_to(location, status)
+}
+
+#[put("/redirect/to?<location>&<status>", data = "<_body>")]
+pub fn redirect_to_put(location: String, status: u16, _body: Vec<u8>) -> (Status, RedirectTo) {
+    redirect_to(location, status)
+}
@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+use rocket::response::stream::ByteStream;
+
+pub static CHUNKS: [&str; 3] = ["first;", "second;", "third"];
+pub static CHUNK_DELAY: Duration = Duration::from_millis(300);
+
+/// Sends the body in a few chunks, with a pause before each but the first one.
+#[get("/stream")]
+pub fn stream_route() -> ByteStream![Vec<u8>] {
+    ByteStream! {
+        for (i, chunk) in CHUNKS.iter().enumerate() {
+            if i > 0 {
+                rocket::tokio::time::sleep(CHUNK_DELAY).await;
+            }
+            yield chunk.as_bytes().to_vec();
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use httlib_hpack::{Decoder, Encoder};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::retcher::tls::{CertificateAuthority, TlsStream};
+
+/// The parts of a TLS `ClientHello` the fingerprinting services look at.
+#[derive(Debug, Clone, Default)]
+pub struct ClientHello {
+    /// The cipher suites, in their order.
+    pub cipher_suites: Vec<u16>,
+    /// The extension types, in their order.
+    pub extensions: Vec<u16>,
+    /// The supported groups (extension 10).
+    pub groups: Vec<u16>,
+    /// The groups the client sent a key share for (extension 51).
+    pub key_shares: Vec<u16>,
+    /// The signature algorithms (extension 13).
+    pub signature_algorithms: Vec<u16>,
+    /// The ALPN protocols (extension 16).
+    pub alpn: Vec<String>,
+}
+
+/// An HTTPS server stand-in running on an ephemeral port, recording the `ClientHello`s and the request heads it receives.
+///
+/// Its certificate is signed by a CA of its own, so it is only trusted with `ignore_tls_errors`.
+pub struct TlsServer {
+    /// The base URL, e.g. `https://127.0.0.1:41234`.
+    pub url: String,
+    hellos: Arc<Mutex<Vec<ClientHello>>>,
+    heads: Arc<Mutex<Vec<String>>>,
+}
+
+impl TlsServer {
+    /// Returns the `ClientHello`s the server has seen so far.
+    pub fn hellos(&self) -> Vec<ClientHello> {
+        self.hellos.lock().unwrap().clone()
+    }
+
+    /// Returns the heads of the requests the server has seen so far, as they were sent.
+    pub fn heads(&self) -> Vec<String> {
+        self.heads.lock().unwrap().clone()
+    }
+}
+
+/// The first frames of an HTTP/2 connection, as sent.
+#[derive(Debug, Clone, Default)]
+pub struct Http2Connection {
+    /// The parameters of the first `SETTINGS` frame, in their order.
+    pub settings: Vec<(u16, u32)>,
+    /// The increment of the first connection-level `WINDOW_UPDATE`.
+    pub window_update: Option<u32>,
+    /// The requests' `HEADERS` frames.
+    pub headers: Vec<Http2Headers>,
+}
+
+/// A `HEADERS` frame, decoded.
+#[derive(Debug, Clone, Default)]
+pub struct Http2Headers {
+    pub flags: u8,
+    /// The exclusive flag, the stream dependency and the weight (1 to 256).
+    pub priority: Option<(bool, u32, u16)>,
+    /// The header fields in their order, pseudo-headers included.
+    pub fields: Vec<(String, String)>,
+    /// The names of the fields the client added to the dynamic table.
+    pub indexed: Vec<String>,
+    /// The header block, as encoded.
+    pub block: Vec<u8>,
+}
+
+/// An HTTP/2 server stand-in running on an ephemeral port, recording the connections' first frames.
+pub struct Http2Server {
+    /// The base URL, e.g. `https://127.0.0.1:41234`.
+    pub url: String,
+    connections: Arc<Mutex<Vec<Http2Connection>>>,
+}
+
+impl Http2Server {
+    /// Returns the connections the server has seen so far.
+    pub fn connections(&self) -> Vec<Http2Connection> {
+        self.connections.lock().unwrap().clone()
+    }
+}
+
+type ReplayedStream = tokio::io::Join<tokio::io::Chain<Cursor<Vec<u8>>, ReadHalf<TcpStream>>, WriteHalf<TcpStream>>;
+
+/// Accepts a TLS connection, recording its `ClientHello`.
+async fn accept(mut socket: TcpStream, authority: &CertificateAuthority, hellos: &Mutex<Vec<ClientHello>>, alpn: &[u8]) -> Option<TlsStream<ReplayedStream>> {
+    // The `ClientHello` fits in the first record, which is replayed to OpenSSL once parsed.
+    let mut record = vec![0u8; 5];
+    socket.read_exact(&mut record).await.ok()?;
+    if record[0] != 22 {
+        return None;
+    }
+    let length = u16::from_be_bytes([record[3], record[4]]) as usize;
+    record.resize(5 + length, 0);
+    socket.read_exact(&mut record[5..]).await.ok()?;
+
+    if let Some(hello) = parse_client_hello(&record[5..]) {
+        hellos.lock().unwrap().push(hello);
+    }
+
+    let (reader, writer) = tokio::io::split(socket);
+    let stream = tokio::io::join(Cursor::new(record).chain(reader), writer);
+    authority.accept(stream, "127.0.0.1", Some(alpn)).await.ok()
+}
+
+/// Starts an HTTPS server answering every request with `Hello, world!` over HTTP/1.1.
+///
+/// Except for `/redirect?to=<URL>`, which redirects to the (percent-encoded) URL.
+pub async fn tls_server() -> TlsServer {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("https://{}", listener.local_addr().unwrap());
+    let hellos = Arc::new(Mutex::new(Vec::new()));
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let authority = Arc::new(CertificateAuthority::new("Retcher test server").unwrap());
+
+    let recorded = hellos.clone();
+    let recorded_heads = heads.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let recorded_heads = recorded_heads.clone();
+            let authority = authority.clone();
+
+            tokio::spawn(async move {
+                let Some(mut stream) = accept(socket, &authority, &recorded, b"http/1.1").await else {
+                    return;
+                };
+
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&request).into_owned();
+                let location = head.split(' ').nth(1)
+                    .and_then(|target| target.strip_prefix("/redirect?to="))
+                    .map(|location| percent_decode_str(location).decode_utf8_lossy().into_owned());
+                recorded_heads.lock().unwrap().push(head);
+
+                let response = match location {
+                    Some(location) => format!("HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", location),
+                    None => "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\nHello, world!".to_string(),
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    TlsServer { url, hellos, heads }
+}
+
+/// Starts an HTTPS server speaking HTTP/2 only, answering every request with `Hello, world!`.
+///
+/// Except for `/large-header`, whose response has an `x-large` header of 5000 `a`s the server indexes in its dynamic table,
+/// after growing it to the client's `SETTINGS_HEADER_TABLE_SIZE`. Further responses refer to the indexed header.
+pub async fn http2_server() -> Http2Server {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("https://{}", listener.local_addr().unwrap());
+    let connections = Arc::new(Mutex::new(Vec::new()));
+    let authority = Arc::new(CertificateAuthority::new("Retcher test server").unwrap());
+
+    let recorded = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let authority = authority.clone();
+
+            tokio::spawn(async move {
+                let Some(mut stream) = accept(socket, &authority, &Mutex::default(), b"h2").await else {
+                    return;
+                };
+
+                let mut preface = [0u8; 24];
+                if stream.read_exact(&mut preface).await.is_err() || stream.write_all(&frame(0x4, 0, 0, &[])).await.is_err() {
+                    return;
+                }
+
+                let index = {
+                    let mut connections = recorded.lock().unwrap();
+                    connections.push(Http2Connection::default());
+                    connections.len() - 1
+                };
+                let mut settings_seen = false;
+                let mut decoder = Decoder::default();
+                let mut encoder = Encoder::default();
+                let mut table_resized = false;
+
+                loop {
+                    let mut head = [0u8; 9];
+                    if stream.read_exact(&mut head).await.is_err() {
+                        return;
+                    }
+                    let length = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+                    let (kind, flags) = (head[3], head[4]);
+                    let stream_id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+                    let mut payload = vec![0u8; length];
+                    if stream.read_exact(&mut payload).await.is_err() {
+                        return;
+                    }
+
+                    let response = {
+                        let mut connections = recorded.lock().unwrap();
+                        let connection = &mut connections[index];
+
+                        match kind {
+                            // SETTINGS
+                            0x4 if flags & 0x1 == 0 => {
+                                if !settings_seen {
+                                    connection.settings = payload.chunks(6)
+                                        .map(|setting| (u16::from_be_bytes([setting[0], setting[1]]), u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]])))
+                                        .collect();
+                                    settings_seen = true;
+                                }
+                                Some(frame(0x4, 0x1, 0, &[]))
+                            }
+                            // WINDOW_UPDATE
+                            0x8 if stream_id == 0 && connection.window_update.is_none() => {
+                                connection.window_update = Some(u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) & 0x7fff_ffff);
+                                None
+                            }
+                            // HEADERS, without CONTINUATIONs
+                            0x1 => {
+                                let headers = parse_headers(flags, &payload, &mut decoder);
+                                let large_header = headers.fields.iter().any(|(name, value)| name == ":path" && value == "/large-header");
+                                connection.headers.push(headers);
+
+                                let mut block = Vec::new();
+                                if large_header && !table_resized {
+                                    let size = connection.settings.iter().find(|(id, _)| *id == 0x1).map_or(4096, |(_, size)| *size);
+                                    encoder.update_max_dynamic_size(size, &mut block).unwrap();
+                                    table_resized = true;
+                                }
+                                encoder.encode((b":status".to_vec(), b"200".to_vec(), Encoder::BEST_FORMAT), &mut block).unwrap();
+                                if large_header {
+                                    let field = (b"x-large".to_vec(), vec![b'a'; 5000], Encoder::WITH_INDEXING | Encoder::BEST_FORMAT);
+                                    encoder.encode(field, &mut block).unwrap();
+                                }
+                                Some([frame(0x1, 0x4, stream_id, &block), frame(0x0, 0x1, stream_id, b"Hello, world!")].concat())
+                            }
+                            _ => None,
+                        }
+                    };
+
+                    let Some(response) = response else { continue };
+                    if stream.write_all(&response).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    Http2Server { url, connections }
+}
+
+fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.extend([kind, flags]);
+    frame.extend(stream_id.to_be_bytes());
+    frame.extend(payload);
+    frame
+}
+
+/// Decodes a `HEADERS` frame, without `CONTINUATION`s.
+fn parse_headers(flags: u8, payload: &[u8], decoder: &mut Decoder) -> Http2Headers {
+    let mut block = payload;
+    let mut headers = Http2Headers { flags, ..Default::default() };
+
+    if flags & 0x20 != 0 {
+        let dependency = u32::from_be_bytes([block[0], block[1], block[2], block[3]]);
+        headers.priority = Some((dependency >> 31 == 1, dependency & 0x7fff_ffff, block[4] as u16 + 1));
+        block = &block[5..];
+    }
+    headers.block = block.to_vec();
+
+    let mut fields = Vec::new();
+    decoder.decode(&mut block.to_vec(), &mut fields).unwrap();
+    for (name, value, flags) in fields {
+        let name = String::from_utf8(name).unwrap();
+        if flags & Decoder::WITH_INDEXING != 0 {
+            headers.indexed.push(name.clone());
+        }
+        headers.fields.push((name, String::from_utf8(value).unwrap()));
+    }
+
+    headers
+}
+
+/// Reads big-endian length-prefixed fields out of a handshake message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(length)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn number(&mut self, size: usize) -> Option<usize> {
+        Some(self.take(size)?.iter().fold(0, |number, byte| number << 8 | *byte as usize))
+    }
+
+    fn vector(&mut self, size: usize) -> Option<Reader<'a>> {
+        let length = self.number(size)?;
+        Some(Reader(self.take(length)?))
+    }
+
+    fn u16_list(mut self) -> Option<Vec<u16>> {
+        let mut list = Vec::new();
+        while !self.0.is_empty() {
+            list.push(self.number(2)? as u16);
+        }
+        Some(list)
+    }
+}
+
+fn parse_client_hello(handshake: &[u8]) -> Option<ClientHello> {
+    let mut reader = Reader(handshake);
+    if reader.number(1)? != 1 {
+        return None;
+    }
+    let mut body = reader.vector(3)?;
+
+    body.take(2 + 32)?; // legacy_version, random
+    body.vector(1)?; // legacy_session_id
+
+    let mut hello = ClientHello {
+        cipher_suites: body.vector(2)?.u16_list()?,
+        ..Default::default()
+    };
+
+    body.vector(1)?; // legacy_compression_methods
+    let mut extensions = body.vector(2)?;
+
+    while !extensions.0.is_empty() {
+        let extension = extensions.number(2)? as u16;
+        let mut data = extensions.vector(2)?;
+        hello.extensions.push(extension);
+
+        match extension {
+            10 => hello.groups = data.vector(2)?.u16_list()?,
+            13 => hello.signature_algorithms = data.vector(2)?.u16_list()?,
+            16 => {
+                let mut protocols = data.vector(2)?;
+                while !protocols.0.is_empty() {
+                    hello.alpn.push(String::from_utf8_lossy(protocols.vector(1)?.0).into_owned());
+                }
+            }
+            51 => {
+                let mut shares = data.vector(2)?;
+                while !shares.0.is_empty() {
+                    hello.key_shares.push(shares.number(2)? as u16);
+                    shares.vector(2)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(hello)
+}
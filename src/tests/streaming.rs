@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+use futures_util::StreamExt;
+
+use crate::retcher::retcher::{Browser, EngineOptions, FetchErrorKind, FetchResponse, Retcher};
+use super::server::get_server;
+use super::server::stream::{CHUNKS, CHUNK_DELAY};
+
+async fn stream_response() -> FetchResponse {
+    get_server().await;
+
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Firefox),
+        ignore_tls_errors: None,
+        ..Default::default()
+    });
+
+    retcher.retch("http://127.0.0.1:8000/stream".into(), None).await.unwrap()
+}
+
+#[tokio::test]
+async fn body_is_a_stream() {
+    let mut response = stream_response().await;
+    assert!(!response.body_used());
+
+    let mut body = response.body().unwrap();
+    assert!(response.body_used());
+
+    let start = Instant::now();
+    let first = body.next().await.unwrap().unwrap();
+
+    // The first chunk is available long before the server finishes sending the body.
+    assert_eq!(first, CHUNKS[0]);
+    assert!(start.elapsed() < CHUNK_DELAY);
+
+    let mut rest = Vec::new();
+    while let Some(chunk) = body.next().await {
+        rest.extend_from_slice(&chunk.unwrap());
+    }
+
+    assert_eq!(String::from_utf8(rest).unwrap(), CHUNKS[1..].concat());
+}
+
+#[tokio::test]
+async fn chunk_by_chunk() {
+    let mut response = stream_response().await;
+
+    let mut chunks = Vec::new();
+    while let Some(chunk) = response.chunk().await.unwrap() {
+        assert!(response.body_used());
+        chunks.push(String::from_utf8(chunk.to_vec()).unwrap());
+    }
+
+    assert_eq!(chunks.concat(), CHUNKS.concat());
+    assert!(response.chunk().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn body_can_be_used_only_once() {
+    let mut response = stream_response().await;
+
+    assert_eq!(response.bytes().await.unwrap(), CHUNKS.concat().as_bytes());
+    assert!(response.body_used());
+
+    assert_eq!(response.bytes().await.err().unwrap().kind, FetchErrorKind::BodyUsed);
+    assert_eq!(response.body().err().unwrap().kind, FetchErrorKind::BodyUsed);
+    assert_eq!(response.chunk().await.err().unwrap().kind, FetchErrorKind::BodyUsed);
+}
+
+#[tokio::test]
+async fn partially_streamed_body_cannot_be_buffered() {
+    let mut response = stream_response().await;
+
+    response.chunk().await.unwrap();
+
+    assert_eq!(response.bytes().await.err().unwrap().kind, FetchErrorKind::BodyUsed);
+}
@@ -0,0 +1,171 @@
+use crate::retcher::retcher::{Browser, EngineOptions, FetchErrorKind, FetchPhase, ProxyOptions, Retcher};
+use super::common::retcher;
+use super::server::proxy::{http_proxy, socks5_proxy};
+use super::server::tls::{tls_server, ClientHello};
+
+/// The renegotiation SCSV OpenSSL adds to the cipher suites (browsers send the `renegotiation_info` extension instead).
+const EMPTY_RENEGOTIATION_INFO_SCSV: u16 = 0x00ff;
+
+/// Chrome's extensions (GREASE and padding left out), as captured from Chrome 125. It sends them in a random order.
+#[cfg(feature = "boringssl")]
+const CHROME_EXTENSIONS: [u16; 16] = [0, 5, 10, 11, 13, 16, 18, 23, 27, 35, 43, 45, 51, 17513, 65037, 65281];
+
+/// Firefox's extensions (padding left out) in their order, as captured from Firefox 126.
+#[cfg(feature = "boringssl")]
+const FIREFOX_EXTENSIONS: [u16; 16] = [0, 23, 65281, 10, 11, 35, 16, 5, 34, 51, 43, 13, 45, 28, 27, 65037];
+
+/// Whether OpenSSL can send several key shares (3.5+), older versions only send one for the first group.
+#[cfg(feature = "openssl")]
+fn multiple_key_shares() -> bool {
+    openssl::version::number() >= 0x3050_0000
+}
+
+#[cfg(feature = "boringssl")]
+fn multiple_key_shares() -> bool {
+    true
+}
+
+/// Chrome's post-quantum group (`X25519Kyber768Draft00`), which OpenSSL doesn't know.
+fn post_quantum_groups() -> Vec<u16> {
+    if cfg!(feature = "boringssl") { vec![0x6399] } else { Vec::new() }
+}
+
+/// Whether the value is one of the GREASE values (RFC 8701), `0x0a0a` to `0xfafa`.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn without_grease(values: &[u16]) -> Vec<u16> {
+    values.iter().copied().filter(|value| !is_grease(*value)).collect()
+}
+
+/// Sends a request to a fresh HTTPS stand-in and returns the `ClientHello` it got.
+async fn client_hello(browser: Browser) -> ClientHello {
+    let server = tls_server().await;
+    let mut response = retcher(browser, None).retch(format!("{}/", server.url), None).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+
+    let hellos = server.hellos();
+    assert_eq!(hellos.len(), 1);
+    hellos[0].clone()
+}
+
+fn cipher_suites(hello: &ClientHello) -> Vec<u16> {
+    without_grease(&hello.cipher_suites).into_iter().filter(|suite| *suite != EMPTY_RENEGOTIATION_INFO_SCSV).collect()
+}
+
+/// The extensions without the padding, which both browsers only send depending on the `ClientHello`'s length.
+#[cfg(feature = "boringssl")]
+fn unpadded_extensions(hello: &ClientHello) -> Vec<u16> {
+    hello.extensions.iter().copied().filter(|extension| *extension != 21).collect()
+}
+
+#[tokio::test]
+async fn chrome_client_hello() {
+    let hello = client_hello(Browser::Chrome).await;
+
+    assert_eq!(cipher_suites(&hello), vec![
+        0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+    ]);
+    assert_eq!(without_grease(&hello.groups), [post_quantum_groups(), vec![0x001d, 0x0017, 0x0018]].concat());
+    assert_eq!(without_grease(&hello.key_shares), [post_quantum_groups(), vec![0x001d]].concat());
+    assert_eq!(hello.signature_algorithms, vec![0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601]);
+    assert_eq!(hello.alpn, vec!["h2", "http/1.1"]);
+    // status_request
+    assert!(hello.extensions.contains(&5));
+}
+
+#[tokio::test]
+async fn firefox_client_hello() {
+    let hello = client_hello(Browser::Firefox).await;
+
+    assert_eq!(cipher_suites(&hello), vec![
+        0x1301, 0x1303, 0x1302, 0xc02b, 0xc02f, 0xcca9, 0xcca8, 0xc02c, 0xc030, 0xc00a, 0xc009, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+    ]);
+    assert_eq!(hello.groups, vec![0x001d, 0x0017, 0x0018, 0x0019, 0x0100, 0x0101]);
+    assert_eq!(hello.key_shares, if multiple_key_shares() { vec![0x001d, 0x0017] } else { vec![0x001d] });
+    assert_eq!(hello.signature_algorithms, vec![
+        0x0403, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601,
+    ]);
+    assert_eq!(hello.alpn, vec!["h2", "http/1.1"]);
+    assert!(hello.extensions.contains(&5));
+}
+
+#[tokio::test]
+async fn untrusted_certificate() {
+    let server = tls_server().await;
+    let retcher = Retcher::new(EngineOptions {
+        browser: Some(Browser::Chrome),
+        ..Default::default()
+    });
+
+    let error = retcher.retch(format!("{}/", server.url), None).await.err().expect("the certificate isn't trusted");
+    assert_eq!(error.kind, FetchErrorKind::Tls);
+    assert_eq!(error.phase, FetchPhase::Connect);
+    assert!(error.message.contains("certificate verify failed"), "{}", error.message);
+
+    // The handshake still got as far as the `ClientHello`.
+    assert_eq!(server.hellos().len(), 1);
+}
+
+#[tokio::test]
+async fn client_hello_through_http_proxy() {
+    let server = tls_server().await;
+    let proxy = http_proxy().await;
+    let retcher = retcher(Browser::Firefox, Some(ProxyOptions::new(&proxy.url)));
+
+    let mut response = retcher.retch(format!("{}/", server.url), None).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+
+    let authority = server.url.trim_start_matches("https://");
+    assert_eq!(proxy.requests()[0].request_line, format!("CONNECT {} HTTP/1.1", authority));
+    assert_eq!(server.hellos()[0].groups[..2], [0x001d, 0x0017]);
+}
+
+#[tokio::test]
+async fn client_hello_through_socks_proxy() {
+    let server = tls_server().await;
+    let proxy = socks5_proxy(None).await;
+    let retcher = retcher(Browser::Chrome, Some(ProxyOptions::new(&proxy.url)));
+
+    let mut response = retcher.retch(format!("{}/", server.url), None).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello, world!");
+
+    assert_eq!(proxy.requests().len(), 1);
+    assert_eq!(without_grease(&server.hellos()[0].key_shares), [post_quantum_groups(), vec![0x001d]].concat());
+}
+
+#[cfg(feature = "boringssl")]
+#[tokio::test]
+async fn chrome_extension_order() {
+    let hellos = [client_hello(Browser::Chrome).await, client_hello(Browser::Chrome).await];
+
+    for hello in &hellos {
+        // Chrome sends GREASE first and last (before the padding), and as the first cipher suite, group and key share.
+        let extensions = unpadded_extensions(hello);
+        assert!(is_grease(extensions[0]), "{:?}", extensions);
+        assert!(is_grease(extensions[extensions.len() - 1]), "{:?}", extensions);
+        assert_eq!(extensions.iter().filter(|extension| is_grease(**extension)).count(), 2);
+        assert!(is_grease(hello.cipher_suites[0]));
+        assert!(is_grease(hello.groups[0]));
+        assert!(is_grease(hello.key_shares[0]));
+
+        let mut sorted = without_grease(&extensions);
+        sorted.sort();
+        assert_eq!(sorted, CHROME_EXTENSIONS);
+    }
+
+    // Permuted, out of 16! orders.
+    assert_ne!(without_grease(&hellos[0].extensions), without_grease(&hellos[1].extensions));
+}
+
+#[cfg(feature = "boringssl")]
+#[tokio::test]
+async fn firefox_extension_order() {
+    let hello = client_hello(Browser::Firefox).await;
+
+    assert_eq!(unpadded_extensions(&hello), FIREFOX_EXTENSIONS);
+    // Firefox doesn't send GREASE values.
+    let values = [&hello.cipher_suites, &hello.extensions, &hello.groups, &hello.key_shares];
+    assert!(values.iter().all(|values| without_grease(values).len() == values.len()));
+}